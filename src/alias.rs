@@ -1,20 +1,178 @@
 use std::cmp;
 use std::collections::BTreeMap;
 use std::ffi::OsStr;
-use std::fmt::Write as _;
-use std::fs;
+use std::fmt::{Display, Write as _};
+use std::io::{self, BufRead};
 use std::ops::Bound;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::{env, fs};
+
+use globset::{GlobBuilder, GlobMatcher};
 
-use crate::cli;
 use crate::config::Config;
+use crate::{cli, git};
+
+/// The set of repos a command operates on.
+pub enum Target {
+    /// All repos under a path.
+    Path(PathBuf),
+    /// All repos under the root whose relative path matches a glob.
+    Glob(GlobMatcher),
+    /// An explicit list of repos, without searching for repos in subdirectories.
+    ///
+    /// Entries which could not be resolved are stored as an error message.
+    List(Vec<Result<PathBuf, String>>),
+    /// The repos matching any of several targets, which are walked in turn.
+    Union(Vec<Target>),
+}
+
+/// Resolve each of `names` like `resolve_target`. If more than one name is given, the repos
+/// matching any of them are used.
+pub fn resolve_targets(
+    names: &[String],
+    args: &cli::Args,
+    config: &Config,
+) -> crate::Result<Target> {
+    match names {
+        [] => resolve_target(None, args, config),
+        [name] => resolve_target(Some(name), args, config),
+        names => {
+            let targets = names
+                .iter()
+                .map(|name| resolve_target(Some(name), args, config))
+                .collect::<crate::Result<_>>()?;
+            Ok(Target::Union(targets))
+        }
+    }
+}
+
+pub fn resolve_target(
+    name: Option<&str>,
+    args: &cli::Args,
+    config: &Config,
+) -> crate::Result<Target> {
+    if args.here {
+        return match name {
+            Some(_) => Err(crate::Error::from_message(
+                "a target cannot be passed with --here",
+            )),
+            None => resolve_here(),
+        };
+    }
+
+    match name {
+        None => Ok(Target::Path(config.root.clone())),
+        Some(name) if is_glob(name) => {
+            let glob = GlobBuilder::new(name)
+                .literal_separator(true)
+                .build()
+                .map_err(|err| {
+                    crate::Error::from_message(format!("invalid glob `{}`: {}", name, err))
+                })?;
+            log::trace!("resolved glob `{}`", name);
+            Ok(Target::Glob(glob.compile_matcher()))
+        }
+        Some(name) => Ok(Target::Path(resolve(name, args, config)?)),
+    }
+}
+
+/// Read a list of paths or aliases from stdin, one per line.
+pub fn resolve_stdin_target(args: &cli::Args, config: &Config) -> crate::Result<Target> {
+    if args.here {
+        return Err(crate::Error::from_message(
+            "--stdin cannot be passed with --here",
+        ));
+    }
+
+    let mut paths = Vec::new();
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|err| crate::Error::with_context(err, "failed to read stdin"))?;
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        paths.push(resolve(name, args, config).map_err(|err| err.to_string()));
+    }
+
+    log::trace!("resolved {} targets from stdin", paths.len());
+    Ok(Target::List(paths))
+}
+
+/// Get the repo containing the current directory, which may be outside the root.
+fn resolve_here() -> crate::Result<Target> {
+    let path = discover_here()?;
+    log::trace!("resolved current repo to `{}`", path.display());
+    Ok(Target::Path(path))
+}
+
+/// Get the repo containing the current directory for `--here` if it is outside the root, so the
+/// user can be warned that its settings do not come from the configured root.
+pub fn here_outside_root(config: &Config) -> Option<PathBuf> {
+    let path = discover_here().ok()?;
+    if path.starts_with(&config.root) {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+fn discover_here() -> crate::Result<PathBuf> {
+    let cwd = env::current_dir()
+        .map_err(|err| crate::Error::with_context(err, "failed to get current directory"))?;
+    git::Repository::discover_workdir(&cwd)
+}
+
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?', '[', '{'])
+}
+
+impl Target {
+    pub fn path<'a>(&'a self, config: &'a Config) -> &'a Path {
+        match self {
+            Target::Path(path) => path,
+            Target::Glob(_) | Target::List(_) | Target::Union(_) => &config.root,
+        }
+    }
+
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        match self {
+            Target::Path(_) | Target::List(_) | Target::Union(_) => true,
+            Target::Glob(glob) => glob.is_match(relative_path),
+        }
+    }
+}
 
 pub fn resolve(name: &str, args: &cli::Args, config: &Config) -> crate::Result<PathBuf> {
+    resolve_inner(name, args, config, false, true)
+}
+
+/// Like `resolve`, but includes the confidence of each suggestion in the error message.
+pub fn resolve_verbose(name: &str, args: &cli::Args, config: &Config) -> crate::Result<PathBuf> {
+    resolve_inner(name, args, config, true, true)
+}
+
+/// Like `resolve`, but does not check that the path exists.
+pub fn resolve_allow_missing(
+    name: &str,
+    args: &cli::Args,
+    config: &Config,
+) -> crate::Result<PathBuf> {
+    resolve_inner(name, args, config, false, false)
+}
+
+fn resolve_inner(
+    name: &str,
+    args: &cli::Args,
+    config: &Config,
+    show_confidence: bool,
+    check_exists: bool,
+) -> crate::Result<PathBuf> {
     if let Some(path) = resolve_prefix(&config.aliases, name, args)? {
         let full_path = config.root.join(path);
         log::trace!("resolved alias `{}` to `{}`", name, full_path.display());
 
-        if !full_path.exists() {
+        if check_exists && !full_path.exists() {
             Err(crate::Error::from_message(format!(
                 "alias `{}` resolved to invalid path `{}`",
                 name,
@@ -27,9 +185,13 @@ pub fn resolve(name: &str, args: &cli::Args, config: &Config) -> crate::Result<P
         let full_path = config.root.join(name);
         log::trace!("resolved path `{}` to `{}`", name, full_path.display());
 
-        if !full_path.exists() {
+        if check_exists && !full_path.exists() {
             Err(crate::Error::from_message(resolve_error_message(
-                name, &full_path, args, config,
+                name,
+                &full_path,
+                args,
+                config,
+                show_confidence,
             )))
         } else {
             Ok(full_path)
@@ -37,6 +199,37 @@ pub fn resolve(name: &str, args: &cli::Args, config: &Config) -> crate::Result<P
     }
 }
 
+/// Find the names of the aliases which resolve to `path`, in sorted order.
+pub fn reverse<'a>(path: &Path, config: &'a Config) -> Vec<&'a str> {
+    let path = normalize(path);
+    config
+        .aliases
+        .iter()
+        .filter(|(_, alias_path)| normalize(&config.root.join(alias_path)) == path)
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Make `path` comparable with other paths, resolving symlinks if it exists, and otherwise
+/// removing `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
+
 fn resolve_prefix<'a>(
     map: &'a BTreeMap<String, PathBuf>,
     prefix: &str,
@@ -69,47 +262,57 @@ fn resolve_prefix<'a>(
     }
 }
 
-pub fn resolve_error_message(name: &str, path: &Path, args: &cli::Args, config: &Config) -> String {
+pub fn resolve_error_message(
+    name: &str,
+    path: &Path,
+    args: &cli::Args,
+    config: &Config,
+    show_confidence: bool,
+) -> String {
     let mut message = format!("failed to resolve path or alias `{}`", name);
 
     if !args.no_alias {
-        let mut alias_suggestions = best_suggestions(suggest_aliases(name, config));
-        if let Some(first) = alias_suggestions.next() {
-            write!(
-                &mut message,
-                "\ndid you mean one of these aliases: {}",
-                first
-            )
-            .unwrap();
-            for suggestion in alias_suggestions {
-                write!(&mut message, ", {}", suggestion).unwrap();
-            }
-            write!(&mut message, "?").unwrap()
-        }
+        let alias_suggestions = best_suggestions(suggest_aliases(name, config));
+        write_suggestions(&mut message, "aliases", alias_suggestions, show_confidence);
     }
 
-    let mut path_suggestions = best_suggestions(suggest_paths(path, config));
-    if let Some(first) = path_suggestions.next() {
-        write!(
-            &mut message,
-            "\ndid you mean one of these paths: {}",
-            first.display()
-        )
-        .unwrap();
-        for suggestion in path_suggestions {
-            write!(&mut message, ", {}", suggestion.display()).unwrap();
-        }
-        write!(&mut message, "?").unwrap()
-    }
+    let path_suggestions = best_suggestions(suggest_paths(path, config))
+        .map(|(confidence, path)| (confidence, path.display().to_string()));
+    write_suggestions(&mut message, "paths", path_suggestions, show_confidence);
 
     message
 }
 
-fn best_suggestions<T>(mut result: Vec<(f64, T)>) -> impl Iterator<Item = T> {
+fn write_suggestions<T: Display>(
+    message: &mut String,
+    kind: &str,
+    mut suggestions: impl Iterator<Item = (f64, T)>,
+    show_confidence: bool,
+) {
+    let write_suggestion = |message: &mut String, (confidence, suggestion): (f64, T)| {
+        if show_confidence {
+            write!(message, "{} ({:.3})", suggestion, confidence).unwrap();
+        } else {
+            write!(message, "{}", suggestion).unwrap();
+        }
+    };
+
+    if let Some(first) = suggestions.next() {
+        write!(message, "\ndid you mean one of these {}: ", kind).unwrap();
+        write_suggestion(message, first);
+        for suggestion in suggestions {
+            write!(message, ", ").unwrap();
+            write_suggestion(message, suggestion);
+        }
+        write!(message, "?").unwrap()
+    }
+}
+
+fn best_suggestions<T>(mut result: Vec<(f64, T)>) -> impl Iterator<Item = (f64, T)> {
     const MAX: usize = 4;
 
     result.sort_by(|&(l, _), &(r, _)| l.partial_cmp(&r).unwrap_or(cmp::Ordering::Less));
-    result.into_iter().rev().map(|(_, value)| value).take(MAX)
+    result.into_iter().rev().take(MAX)
 }
 
 fn suggest_aliases<'a>(name: &str, config: &'a Config) -> Vec<(f64, &'a str)> {
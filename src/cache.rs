@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::data_dir;
+
+const FILE_NAME: &str = "discovery-cache.json";
+
+/// Remembers which directories are repos and what subdirectories other directories contain, keyed
+/// by the modification time of each directory, so unchanged directories need not be read again.
+///
+/// A directory's modification time changes when entries are added to or removed from it, so an
+/// entry is reused only while the directory itself is unchanged.
+#[derive(Debug, Default)]
+pub struct DiscoveryCache {
+    /// The file the cache is saved to, or `None` if the cache is disabled.
+    file_path: Option<PathBuf>,
+    directories: BTreeMap<PathBuf, CachedDirectory>,
+    changed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CachedDirectory {
+    modified: SystemTime,
+    /// Whether the directory is a repo. The subdirectories of repos are not recorded.
+    pub is_repo: bool,
+    pub subdirectories: Vec<PathBuf>,
+}
+
+impl DiscoveryCache {
+    /// Load the cache, if it is enabled by the `discovery-cache` setting. If `--refresh-cache` was
+    /// passed, the cache starts empty and is replaced when saved.
+    pub fn load(config: &Config) -> Self {
+        if config.discovery_cache != Some(true) {
+            return DiscoveryCache::default();
+        }
+
+        let file_path = data_dir::path(FILE_NAME);
+
+        let directories = if config.refresh_cache {
+            BTreeMap::new()
+        } else {
+            data_dir::load(&file_path, "discovery cache")
+        };
+
+        DiscoveryCache {
+            file_path: Some(file_path),
+            directories,
+            changed: false,
+        }
+    }
+
+    /// Get the cached entry for `path`, if the directory has not been modified since it was
+    /// recorded.
+    pub fn get(&self, path: &Path) -> Option<&CachedDirectory> {
+        self.file_path.as_ref()?;
+
+        let entry = self.directories.get(path)?;
+        if modified(path)? == entry.modified {
+            log::trace!("using cached entry for `{}`", path.display());
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Record whether `path` is a repo, and its subdirectories if not. `modified` is the
+    /// modification time of the directory from before it was read.
+    pub fn insert(
+        &mut self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        is_repo: bool,
+        subdirectories: Vec<PathBuf>,
+    ) {
+        let modified = match (&self.file_path, modified) {
+            (Some(_), Some(modified)) => modified,
+            _ => return,
+        };
+
+        let entry = CachedDirectory {
+            modified,
+            is_repo,
+            subdirectories,
+        };
+        if self.directories.get(path) == Some(&entry) {
+            return;
+        }
+
+        if let Some(previous) = self.directories.insert(path.to_owned(), entry) {
+            // Forget about subdirectories which no longer exist
+            let current = &self.directories[path].subdirectories;
+            let removed: Vec<PathBuf> = previous
+                .subdirectories
+                .iter()
+                .filter(|name| !current.contains(name))
+                .map(|name| path.join(name))
+                .collect();
+            if !removed.is_empty() {
+                self.directories
+                    .retain(|path, _| !removed.iter().any(|removed| path.starts_with(removed)));
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Write the cache back to disk, if anything changed.
+    pub fn save(&self) {
+        let file_path = match &self.file_path {
+            Some(file_path) if self.changed => file_path,
+            _ => return,
+        };
+
+        data_dir::save(file_path, &self.directories, "discovery cache");
+    }
+}
+
+/// Get the modification time of the directory at `path`.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
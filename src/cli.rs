@@ -1,21 +1,209 @@
 mod clone;
+mod commit;
+mod config;
 mod edit;
 mod exec;
+mod fsck;
+mod gone;
+mod init;
+mod move_repo;
 mod pull;
 mod resolve;
+mod set_upstream;
 mod status;
+mod theme;
+mod undo;
+mod verify;
+mod whereis;
 
 pub use self::clone::{run as clone, CloneArgs};
+pub use self::commit::{run as commit, CommitArgs};
+pub use self::config::{run as config, ConfigArgs};
 pub use self::edit::{run as edit, EditArgs};
 pub use self::exec::{run as exec, ExecArgs};
+pub use self::fsck::{run as fsck, FsckArgs};
+pub use self::gone::{run as gone, GoneArgs};
+pub use self::init::{run as init, InitArgs};
+pub use self::move_repo::{run as move_repo, MoveArgs};
 pub use self::pull::{run as pull, PullArgs};
 pub use self::resolve::{run as resolve, ResolveArgs};
+pub use self::set_upstream::{run as set_upstream, SetUpstreamArgs};
 pub use self::status::{run as status, StatusArgs};
+pub use self::theme::{run as theme, ThemeArgs};
+pub use self::undo::{run as undo, UndoArgs};
+pub use self::verify::{run as verify, VerifyArgs};
+pub use self::whereis::{run as whereis, WhereisArgs};
 
-use clap::{Parser, Subcommand};
+use std::cmp;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use chrono::Duration;
+use clap::{AppSettings, ArgEnum as _, Parser, Subcommand};
+
+use crate::config::{Config, RelativeTo};
+use crate::{git, interrupt};
 
 pub fn parse_args() -> Args {
-    Args::parse()
+    let mut args = Args::parse();
+    // A deadline too far in the future to represent is the same as no deadline.
+    args.deadline_at = args
+        .deadline
+        .and_then(|deadline| Instant::now().checked_add(deadline.to_std().unwrap_or_default()));
+    args
+}
+
+/// Get the default number of threads to use for a command when `--jobs` is not set.
+///
+/// Commands which spend most of their time waiting on the network benefit from running more jobs
+/// than there are CPUs, whereas commands which do local work (possibly running arbitrary
+/// commands) are limited by the number of CPUs.
+pub fn default_jobs(command: &Command) -> usize {
+    const MAX_NETWORK_JOBS: usize = 32;
+
+    let cpus = num_cpus::get();
+    match command {
+        Command::Status(_) | Command::Pull(_) | Command::Clone(_) => {
+            cmp::min(cpus * 4, MAX_NETWORK_JOBS)
+        }
+        Command::Edit(_)
+        | Command::Resolve(_)
+        | Command::Exec(_)
+        | Command::Fsck(_)
+        | Command::Commit(_)
+        | Command::Whereis(_)
+        | Command::SetUpstream(_)
+        | Command::Verify(_)
+        | Command::Gone(_)
+        | Command::Move(_)
+        | Command::Config(_)
+        | Command::Theme(_)
+        | Command::Undo(_)
+        | Command::Init(_) => cpus,
+    }
+}
+
+/// Whether a command may connect to remotes, and so is affected by the SSH transport.
+pub fn connects_to_remotes(command: &Command) -> bool {
+    match command {
+        Command::Pull(_) | Command::Clone(_) => true,
+        Command::Status(status_args) => status_args.fetch(),
+        Command::Edit(_)
+        | Command::Resolve(_)
+        | Command::Exec(_)
+        | Command::Fsck(_)
+        | Command::Commit(_)
+        | Command::Whereis(_)
+        | Command::SetUpstream(_)
+        | Command::Verify(_)
+        | Command::Gone(_)
+        | Command::Move(_)
+        | Command::Config(_)
+        | Command::Theme(_)
+        | Command::Undo(_)
+        | Command::Init(_) => false,
+    }
+}
+
+/// Whether a command may write to the repos it visits, and so should skip repos with the
+/// `read-only` setting.
+pub fn writes_to_repos(command: &Command) -> bool {
+    match command {
+        Command::Pull(_)
+        | Command::Exec(_)
+        | Command::Commit(_)
+        | Command::SetUpstream(_)
+        | Command::Undo(_) => true,
+        Command::Gone(gone_args) => gone_args.delete(),
+        Command::Edit(_)
+        | Command::Status(_)
+        | Command::Resolve(_)
+        | Command::Clone(_)
+        | Command::Fsck(_)
+        | Command::Whereis(_)
+        | Command::Verify(_)
+        | Command::Move(_)
+        | Command::Config(_)
+        | Command::Theme(_)
+        | Command::Init(_) => false,
+    }
+}
+
+/// An outcome which can cause the command to exit with a failure, chosen with `--fail-on`.
+#[derive(Copy, Clone, Debug, PartialEq, clap::ArgEnum)]
+pub enum FailOn {
+    /// The command failed for a repo, such as because it could not be opened.
+    RepoErrors,
+    /// The upstream of the current branch no longer exists.
+    GoneUpstream,
+    /// The repo has uncommitted changes.
+    Dirty,
+    /// The current branch is behind its upstream.
+    Behind,
+}
+
+impl Args {
+    pub fn jobs(&self) -> usize {
+        match self.jobs {
+            0 => default_jobs(&self.command),
+            jobs => jobs,
+        }
+    }
+
+    /// Get the time at which the command should stop, if `--deadline` was passed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline_at
+    }
+
+    pub fn deadline_exceeded(&self) -> bool {
+        deadline_exceeded(self.deadline_at)
+    }
+
+    /// Whether repos matching `condition` cause the command to exit with a failure. By default, only
+    /// repo errors do, unless `--ignore-errors` is passed.
+    pub fn fails_on(&self, condition: FailOn) -> bool {
+        if self.fail_on.is_empty() {
+            condition == FailOn::RepoErrors && !self.ignore_errors
+        } else {
+            self.fail_on.contains(&condition)
+        }
+    }
+
+    /// Check that `--fail-on` was only passed conditions which the command can find. Only `mgit
+    /// status` finds repos with a gone upstream, uncommitted changes or which are behind.
+    pub fn check_fail_on(&self) -> crate::Result<()> {
+        if matches!(self.command, Command::Status(_)) {
+            return Ok(());
+        }
+
+        match self
+            .fail_on
+            .iter()
+            .find(|&&condition| condition != FailOn::RepoErrors)
+        {
+            Some(condition) => Err(crate::Error::from_message(format!(
+                "the `--fail-on {}` option can only be used with `mgit status`",
+                condition.to_possible_value().unwrap().get_name()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Build the limit on concurrent network operations from `--max-network`,
+    /// `--concurrency-per-remote` and the config file.
+    pub fn network_limit(&self, config: &Config) -> git::NetworkLimit {
+        let per_remote = self
+            .concurrency_per_remote
+            .or(config.concurrency_per_remote)
+            .unwrap_or(0);
+        git::NetworkLimit::new(self.max_network)
+            .with_host_limits(per_remote, &config.concurrency_per_host)
+    }
+
+    /// Whether the command should write its output in the porcelain format.
+    pub fn porcelain(&self) -> bool {
+        matches!(&self.command, Command::Pull(pull_args) if pull_args.porcelain())
+    }
 }
 
 const VERSION: &str = env!("VERGEN_GIT_SHA");
@@ -36,12 +224,153 @@ pub struct Args {
         long,
         short,
         global = true,
-        help = "Number of threads to use. If set to 0, uses the number of available CPUs",
+        parse(from_occurrences),
+        help = "Write more detail to the log file: debug messages with -v, and trace messages with -vv. \
+            Overrides the MGIT_LOG environment variable"
+    )]
+    pub verbose: u64,
+    #[clap(
+        long,
+        short,
+        global = true,
+        help = "Number of threads to use. If set to 0, picks a default based on the command: \
+            four times the number of available CPUs (up to 32) for network-bound commands, \
+            or the number of available CPUs otherwise",
         default_value = "0"
     )]
     pub jobs: usize,
+    #[clap(
+        long,
+        global = true,
+        value_name = "COUNT",
+        help = "Maximum number of network operations (clones and fetches) to run at once, \
+            independently of the number of threads. If set to 0, there is no limit",
+        default_value = "0"
+    )]
+    pub max_network: usize,
+    #[clap(
+        long,
+        global = true,
+        value_name = "COUNT",
+        help = "Maximum number of network operations to run at once against each remote host, for hosts \
+            without their own limit in the config file. Defaults to the `concurrency-per-remote` setting. \
+            If set to 0, there is no limit"
+    )]
+    pub concurrency_per_remote: Option<usize>,
+    #[clap(
+        long,
+        global = true,
+        arg_enum,
+        value_name = "BASE",
+        help = "Show repo paths relative to the config root, relative to the current directory, or as absolute paths",
+        default_value = "root"
+    )]
+    pub relative_to: RelativeTo,
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "The profile from the config file to use. Defaults to the value of the MGIT_PROFILE environment variable"
+    )]
+    pub profile: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        help = "Run the command on the repo containing the current directory, even if it is outside the root"
+    )]
+    pub here: bool,
     #[clap(long, global = true, help = "Print output in JSON Lines format")]
     pub json: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Print output as a single pretty-printed JSON array. Implies --json"
+    )]
+    pub json_pretty: bool,
+    #[clap(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Write output to a file instead of stdout. Plain output is written without colors once \
+            each repo finishes",
+        parse(from_os_str)
+    )]
+    pub output: Option<PathBuf>,
+    #[clap(
+        long,
+        global = true,
+        help = "Write JSON or porcelain records in the order repos were found, instead of as they finish"
+    )]
+    pub keep_order: bool,
+    #[clap(
+        long,
+        short = '0',
+        global = true,
+        help = "Terminate paths printed by `resolve` and porcelain records with a NUL character instead \
+            of a newline, for use with `xargs -0`"
+    )]
+    pub null: bool,
+    #[clap(
+        long,
+        global = true,
+        value_name = "COLS",
+        help = "Cap the width of the column repo paths are written in, truncating longer paths with an \
+            ellipsis. Defaults to half the terminal width"
+    )]
+    pub max_path_width: Option<usize>,
+    #[clap(
+        long,
+        global = true,
+        help = "Re-scan every directory for repos instead of using the discovery cache, and replace \
+            the cache with the results"
+    )]
+    pub refresh_cache: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Skip repos with a detached HEAD instead of running the command on them"
+    )]
+    pub skip_detached: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Exit successfully even if the command failed for some repos"
+    )]
+    pub ignore_errors: bool,
+    #[clap(
+        long,
+        global = true,
+        arg_enum,
+        value_name = "CONDITION",
+        help = "Which outcomes cause the command to exit with a failure. Defaults to `repo-errors`",
+        long_help = "Which outcomes cause the command to exit with a failure. `repo-errors` fails if the \
+            command failed for any repo, while `gone-upstream`, `dirty` and `behind` fail if `mgit status` \
+            finds a repo in that state, and can only be used with that command. Can be passed more than \
+            once or as a comma-separated list. Defaults to `repo-errors`",
+        multiple_occurrences = true,
+        use_delimiter = true,
+        conflicts_with = "ignore-errors"
+    )]
+    pub fail_on: Vec<FailOn>,
+    #[clap(
+        long,
+        global = true,
+        value_name = "DURATION",
+        help = "Stop the command after this long, e.g. `30m` or `2h`. Repos which have not started are skipped, \
+            network operations are cancelled and running commands are killed",
+        parse(try_from_str = parse_duration)
+    )]
+    pub deadline: Option<Duration>,
+    #[clap(
+        long,
+        global = true,
+        help = "Print how long the command took once it finishes, including the time spent finding \
+            repos and running the command in them"
+    )]
+    pub time: bool,
+    /// The time at which `deadline` expires, measured from when the arguments were parsed.
+    #[clap(skip)]
+    deadline_at: Option<Instant>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -58,4 +387,84 @@ pub enum Command {
     Exec(ExecArgs),
     #[clap(name = "clone")]
     Clone(CloneArgs),
+    #[clap(name = "fsck")]
+    Fsck(FsckArgs),
+    #[clap(name = "init")]
+    Init(InitArgs),
+    #[clap(name = "commit")]
+    Commit(CommitArgs),
+    #[clap(name = "whereis")]
+    Whereis(WhereisArgs),
+    #[clap(name = "set-upstream")]
+    SetUpstream(SetUpstreamArgs),
+    #[clap(name = "verify")]
+    Verify(VerifyArgs),
+    #[clap(name = "gone")]
+    Gone(GoneArgs),
+    #[clap(name = "move")]
+    Move(MoveArgs),
+    #[clap(name = "config")]
+    Config(ConfigArgs),
+    #[clap(name = "undo")]
+    Undo(UndoArgs),
+    #[clap(name = "theme", setting = AppSettings::Hidden)]
+    Theme(ThemeArgs),
+}
+
+/// Check whether `deadline` has passed, or the command was interrupted, in which case any remaining
+/// work should stop.
+pub fn deadline_exceeded(deadline: Option<Instant>) -> bool {
+    interrupt::is_interrupted() || matches!(deadline, Some(deadline) if Instant::now() >= deadline)
+}
+
+/// Parse a duration such as `30d`, with units `s`, `m`, `h`, `d`, `w` or `y`.
+pub fn parse_duration(text: &str) -> crate::Result<Duration> {
+    let unit_start = text
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (count, unit) = text.split_at(unit_start);
+    let count: i64 = count.parse().map_err(|_| {
+        crate::Error::from_message(format!("invalid duration `{}`: expected a number", text))
+    })?;
+
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => {
+            return Err(crate::Error::from_message(format!(
+                "invalid duration `{}`: expected a unit of `s`, `m`, `h`, `d`, `w` or `y`",
+                text
+            )))
+        }
+    };
+
+    match count.checked_mul(multiplier) {
+        Some(seconds) if seconds <= Duration::max_value().num_seconds() => {
+            Ok(Duration::seconds(seconds))
+        }
+        _ => Err(crate::Error::from_message(format!(
+            "invalid duration `{}`: too large",
+            text
+        ))),
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("30s").unwrap(), Duration::seconds(30));
+    assert_eq!(parse_duration("90d").unwrap(), Duration::days(90));
+    assert_eq!(parse_duration("2y").unwrap(), Duration::days(730));
+    assert!(parse_duration("d").is_err());
+    assert!(parse_duration("12").is_err());
+    assert!(parse_duration("12 days").is_err());
+
+    for unit in ["s", "m", "h", "d", "w", "y"] {
+        assert!(parse_duration(&format!("1{}", unit)).is_ok());
+        assert!(parse_duration(&format!("{}{}", i64::MAX, unit)).is_err());
+        assert!(parse_duration(&format!("{}{}", i64::MAX / 1000 + 1, unit)).is_err());
+    }
 }
@@ -41,6 +41,24 @@ pub struct CloneArgs {
         help = "an alias to create for the new repository"
     )]
     alias: Option<String>,
+    #[clap(
+        long,
+        value_name = "SPEC",
+        help = "create a partial clone using a filter of `blob:none`, `blob:limit=<size>` or `tree:<depth>`. Partial clones are created by running `git clone`, so git must be installed",
+        parse(try_from_str)
+    )]
+    filter: Option<git::CloneFilter>,
+    #[clap(
+        long,
+        help = "create a local branch tracking each remote branch, instead of only the default branch"
+    )]
+    track_all: bool,
+    #[clap(
+        long,
+        help = "create a bare repo mirroring every ref of the remote, like `git clone --mirror`. The directory name defaults to the repository name with a `.git` suffix",
+        conflicts_with = "track-all"
+    )]
+    mirror: bool,
 }
 
 pub fn run(
@@ -58,7 +76,13 @@ pub fn run(
     let path = if let Some(name) = &clone_args.name {
         root.join(name)
     } else if let Some(name) = clone_args.repo.dir_name() {
-        root.join(name)
+        if clone_args.mirror {
+            let mut name = name.to_owned();
+            name.push(".git");
+            root.join(name)
+        } else {
+            root.join(name)
+        }
     } else {
         return Err(crate::Error::from_message(
             "failed to resolve directory name from url (try passing it with --name)",
@@ -66,18 +90,50 @@ pub fn run(
     };
 
     let relative_path = config.get_relative_path(&path);
-    let settings = config.settings(&relative_path);
+    let settings = config.settings(relative_path);
 
     out.writeln_message(format!("cloning into `{}`", path.display()));
 
     let block = out.block()?;
-    let line = block.add_line(PullLineContent::new(relative_path.to_owned()));
-    git::Repository::clone(&path, clone_args.repo.as_ref(), &settings, |progress| {
-        line.content().tick(progress);
-        line.update();
-    })?;
+    let line = block.add_line(PullLineContent::new(
+        config.get_display_path(&path),
+        path.clone(),
+        args.max_path_width,
+    ));
+    let network_limit = args.network_limit(config);
+    let repo = git::Repository::clone(
+        &path,
+        clone_args.repo.as_ref(),
+        &settings,
+        &git::CloneOptions {
+            filter: clone_args.filter.as_ref(),
+            mirror: clone_args.mirror,
+            network_limit: &network_limit,
+            deadline: args.deadline(),
+        },
+        |progress| {
+            match progress {
+                git::CloneProgress::Transfer(progress) => line.content().tick(progress),
+                git::CloneProgress::Checkout { completed, total } => {
+                    line.content().tick_checkout(completed, total)
+                }
+            }
+            line.update();
+        },
+    )?;
     drop(block);
 
+    // The repo has been cloned, so a failure here should not stop the alias being created
+    if clone_args.track_all {
+        match repo.track_remote_branches() {
+            Ok(1) => out.writeln_message("created 1 tracking branch"),
+            Ok(count) => out.writeln_message(format_args!("created {} tracking branches", count)),
+            Err(err) => {
+                out.writeln_warning(format_args!("failed to create tracking branches: {}", err))
+            }
+        }
+    }
+
     if let Some(alias) = &clone_args.alias {
         out.writeln_message(format_args!(
             "creating alias `{} = \"{}\"`",
@@ -85,19 +141,28 @@ pub fn run(
             path.display()
         ));
         config::edit(|document| {
-            match document.as_table_mut().entry("aliases") {
-                toml_edit::Entry::Occupied(_) => {
-                    return Err(crate::Error::from_message(format!(
-                        "alias `{}` already exists",
-                        alias
-                    )))
-                }
-                toml_edit::Entry::Vacant(entry) => {
-                    entry.insert(toml_edit::value(relative_path.to_str().ok_or_else(
-                        || crate::Error::from_message("path is invalid UTF-16"),
-                    )?));
-                }
+            let aliases = document
+                .as_table_mut()
+                .entry("aliases")
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+                .ok_or_else(|| crate::Error::from_message("`aliases` is not a table"))?;
+
+            if aliases.contains_key(alias) {
+                return Err(crate::Error::from_message(format!(
+                    "alias `{}` already exists",
+                    alias
+                )));
             }
+
+            aliases.insert(
+                alias,
+                toml_edit::value(
+                    relative_path
+                        .to_str()
+                        .ok_or_else(|| crate::Error::from_message("path is invalid UTF-8"))?,
+                ),
+            );
             Ok(())
         })?;
     }
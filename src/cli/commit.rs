@@ -0,0 +1,191 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::format::Template;
+use crate::output::{self, LineContent, Output};
+use crate::walk::{self, walk_with_output};
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Commit changes in your repos")]
+pub struct CommitArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to commit in. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to commit in from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+    #[clap(
+        long,
+        short,
+        value_name = "MESSAGE",
+        help = "the commit message, with placeholders {path} for the path of the repo relative to the root, and {branch}"
+    )]
+    message: String,
+    #[clap(
+        long,
+        short,
+        help = "add a Signed-off-by trailer using the committer identity of each repo"
+    )]
+    sign_off: bool,
+    #[clap(
+        value_name = "PATHSPEC",
+        help = "only stage changes matching these pathspecs. Defaults to staging every change",
+        last = true
+    )]
+    pathspecs: Vec<String>,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    commit_args: &CommitArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if commit_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&commit_args.targets, args, config)?
+    };
+
+    let message = Template::parse(&commit_args.message, CommitLineContent::PLACEHOLDERS)?;
+
+    let options = git::CommitOptions {
+        pathspecs: &commit_args.pathspecs,
+        sign_off: commit_args.sign_off,
+    };
+
+    walk_with_output(
+        args,
+        out,
+        config,
+        &target,
+        |block, entry| CommitLineContent::build(block, entry, args.max_path_width),
+        |entry, line| CommitLineContent::update(entry, line, config, &message, &options),
+    )
+}
+
+struct CommitLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<git::CommitOutcome>>>,
+}
+
+impl CommitLineContent {
+    const PLACEHOLDERS: &'static [&'static str] = &["path", "branch"];
+
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(CommitLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        config: &Config,
+        message: &Template,
+        options: &git::CommitOptions,
+    ) {
+        log::debug!("committing in repo at `{}`", entry.relative_path.display());
+
+        let outcome = entry
+            .repo
+            .head_status(&entry.settings)
+            .map_err(|err| crate::Error::with_context(err, "failed to get repo status"))
+            .and_then(|head| {
+                // Unlike the displayed path, the message does not depend on `--relative-to`
+                let message = message.render(|name| match name {
+                    "path" => config.get_relative_path(&entry.path).display().to_string(),
+                    "branch" => head.to_string(),
+                    _ => unreachable!(),
+                });
+                entry.repo.commit(&message, options)
+            });
+
+        *line.content().state.lock().unwrap() = Some(outcome);
+    }
+}
+
+impl LineContent for CommitLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {}
+            Some(Ok(git::CommitOutcome::NothingToCommit)) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "nothing to commit")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(git::CommitOutcome::Committed(oid))) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
+                write!(stdout, "committed {}", oid)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonCommit<'a> {
+            Commit {
+                path: String,
+                #[serde(flatten)]
+                outcome: &'a git::CommitOutcome,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => unreachable!(),
+            Some(Ok(outcome)) => JsonCommit::Commit {
+                path: self.relative_path.display().to_string(),
+                outcome,
+            },
+            Some(Err(error)) => JsonCommit::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
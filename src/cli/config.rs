@@ -0,0 +1,56 @@
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+use crate::cli;
+use crate::config::Config;
+use crate::output::Output;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Inspect the config file")]
+pub struct ConfigArgs {
+    #[clap(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    #[clap(
+        name = "show",
+        about = "Print the config after applying the selected profile. Secrets such as SSH passphrases are left out"
+    )]
+    Show,
+}
+
+pub fn run(
+    out: &Output,
+    _: &cli::Args,
+    config_args: &ConfigArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    match config_args.command {
+        ConfigCommand::Show => show(out, config),
+    }
+}
+
+fn show(out: &Output, config: &Config) -> crate::Result<()> {
+    if out.is_json() {
+        #[derive(Serialize)]
+        struct JsonConfig<'a> {
+            kind: &'static str,
+            #[serde(flatten)]
+            config: &'a Config,
+        }
+
+        out.writeln_json(&JsonConfig {
+            kind: "config",
+            config,
+        })?;
+    } else {
+        // Converting to a `toml::Value` first ensures tables are written after plain values
+        let value = toml::Value::try_from(config).map_err(|err| {
+            crate::Error::from_message(format!("failed to serialize config: {}", err))
+        })?;
+        out.writeln_message(value.to_string().trim_end());
+    }
+    Ok(())
+}
@@ -1,28 +1,41 @@
+use std::time::{Duration, Instant};
+use std::{cmp, thread};
 use std::{
-    borrow::Cow,
-    io::{self, Write as _},
+    env,
+    io::{self, BufRead as _, Write as _},
 };
 use std::{
     ffi::OsString,
     process::{Child, ExitStatus},
+    sync::atomic::{AtomicUsize, Ordering},
     sync::{Arc, Mutex},
 };
-use std::{path::PathBuf, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 use std::{process::Stdio, str::FromStr};
 
 use clap::{AppSettings, Parser};
 use crossterm::{
-    style::{Attribute, SetAttribute},
+    style::{Attribute, Color, ResetColor, SetAttribute, SetForegroundColor},
     terminal::{self, Clear, ClearType},
 };
 use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    alias, cli,
-    config::{Config, Shell},
+    alias::{self, Target},
+    cli,
+    config::{Config, Settings, Shell},
+    format::Template,
+    git,
+    history::ExecHistory,
+    interrupt,
     output::{self, LineContent, Output},
-    walk::{self, walk_with_output},
+    predicate::Predicate,
+    pty::PtyChild,
+    walk,
 };
 
 #[derive(Debug, Parser)]
@@ -32,9 +45,17 @@ use crate::{
 pub struct ExecArgs {
     #[clap(
         value_name = "TARGET",
-        help = "the path or alias of the repo(s) to execute the command in"
+        help = "the path, alias or glob of the repo(s) to execute the command in. \
+            To pass more than one, separate the command from them with `--`, as in `mgit exec work tools -- make test`. \
+            Repos matching more than one target are only included once"
     )]
     target: Option<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to execute the command in from stdin, one per line",
+        conflicts_with = "target"
+    )]
+    stdin: bool,
     #[clap(
         value_name = "COMMAND",
         help = "the command to execute",
@@ -51,8 +72,167 @@ pub struct ExecArgs {
         parse(try_from_str)
     )]
     shell: Option<Shell>,
+    #[clap(
+        long,
+        help = "run the command once in each directory containing repos, instead of once per repo. \
+            Shorthand for `--cwd dir`"
+    )]
+    chdir_root: bool,
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "DIR",
+        help = "where to run the command. `repo` runs it once in each repo. `dir` runs it once in each \
+            directory containing repos, with the paths of the repos in the MULTIGIT_REPOS environment \
+            variable. `root` runs it once in the root directory, with the relative paths of the repos \
+            appended as arguments and in the MULTIGIT_REPOS environment variable",
+        default_value = "repo",
+        conflicts_with = "chdir-root"
+    )]
+    cwd: ExecCwd,
+    #[clap(
+        long,
+        value_name = "IDENTITY",
+        help = "override the author of commits made by git commands, in the form `NAME <EMAIL>`"
+    )]
+    author: Option<Identity>,
+    #[clap(
+        long,
+        value_name = "IDENTITY",
+        help = "override the committer of commits made by git commands, in the form `NAME <EMAIL>`"
+    )]
+    committer: Option<Identity>,
+    #[clap(
+        long,
+        value_name = "NAME=VALUE",
+        help = "set an environment variable for the command, overriding any value from the `env` setting",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    env: Vec<EnvVar>,
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        help = "a template for each line of output, with placeholders {path} and {code}"
+    )]
+    format: Option<String>,
+    #[clap(
+        long,
+        alias = "shell-escape-check",
+        help = "check the syntax of the command before running it in any repos. Only supported for the `bash` shell"
+    )]
+    check: bool,
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "ACTION",
+        help = "what to do when the command fails. `continue` runs it in every repo regardless, `abort` \
+            skips the repos it has not started in yet, and `prompt` asks whether to continue. With more \
+            than one job, commands already running in other repos are allowed to finish",
+        default_value = "continue"
+    )]
+    on_error: OnError,
+    #[clap(
+        long,
+        help = "keep running the command in other repos after it fails, and list the failures once it has \
+            finished. This is the default, and is the same as `--on-error continue`",
+        conflicts_with = "on-error"
+    )]
+    keep_going: bool,
+    #[clap(
+        long,
+        help = "capture the output of the command and print each line prefixed with the path of the repo. \
+            The output from each repo is printed all at once when it finishes, so output from \
+            different repos is not interleaved"
+    )]
+    prefix: bool,
+    #[clap(
+        long,
+        help = "with --prefix, print the output from each repo under a header with its path, instead of \
+            prefixing each line",
+        requires = "prefix"
+    )]
+    print_path_header: bool,
+    #[clap(
+        long,
+        help = "run the command in a terminal, so programs which check whether their output is a terminal \
+            still print colors. With --prefix, the command runs in a pseudo-terminal and its output is captured. \
+            Otherwise, the command is connected directly to this terminal, so interactive programs can be used, \
+            and its output is shown under a header with the path of the repo. Requires --jobs 1, since only \
+            one command can make use of the terminal at a time"
+    )]
+    tty: bool,
+    #[clap(
+        long = "where",
+        value_name = "PREDICATE",
+        help = "only run the command in repos whose status matches a predicate, such as `behind > 0 && !dirty`. \
+            Predicates can use the flags `dirty`, `detached` and `gone`, compare `ahead` or `behind` with a \
+            number, and combine conditions with `!`, `&&`, `||` and parentheses"
+    )]
+    predicate: Option<String>,
+    #[clap(
+        long,
+        help = "skip repos with uncommitted changes, so the command cannot overwrite them"
+    )]
+    require_clean: bool,
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "run the command again, up to this many times, in repos where it exits with a non-zero status"
+    )]
+    retries: Option<usize>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        help = "how long to wait before retrying a failed command, e.g. `5s`",
+        parse(try_from_str = cli::parse_duration),
+        requires = "retries"
+    )]
+    retry_delay: Option<chrono::Duration>,
+    #[clap(
+        long,
+        help = "once the command finishes, remove the lines for repos where it succeeded, so only failures are left. \
+            Lines are still shown while the command is running"
+    )]
+    only_errors: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        help = "skip repos where the same command last succeeded less than this long ago, e.g. `1d`. \
+            The time the command succeeded in each repo is recorded in a file in the data directory",
+        parse(try_from_str = cli::parse_duration)
+    )]
+    skip_if_newer_than: Option<chrono::Duration>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ArgEnum)]
+enum ExecCwd {
+    Repo,
+    Dir,
+    Root,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ArgEnum)]
+enum OnError {
+    Continue,
+    Abort,
+    Prompt,
+}
+
+#[derive(Debug)]
+struct Identity {
+    name: String,
+    email: String,
+}
+
+#[derive(Debug)]
+struct EnvVar {
+    name: String,
+    value: String,
 }
 
+const REPOS_VAR: &str = "MULTIGIT_REPOS";
+
 pub fn run(
     out: &Output,
     args: &cli::Args,
@@ -60,22 +240,466 @@ pub fn run(
     config: &Config,
 ) -> crate::Result<()> {
     let shell = exec_args.shell.unwrap_or(config.default_shell);
+    if exec_args.command().is_empty() {
+        return Err(crate::Error::from_message(
+            "no command was passed after `--`",
+        ));
+    }
 
-    let root = if let Some(name) = &exec_args.target {
-        Cow::Owned(alias::resolve(name, args, config)?)
+    let target = if exec_args.stdin {
+        alias::resolve_stdin_target(args, config)?
     } else {
-        Cow::Borrowed(&*config.root)
+        alias::resolve_targets(&exec_args.targets(), args, config)?
     };
+    let format = exec_args
+        .format
+        .as_deref()
+        .map(|format| Template::parse(format, ExecLineContent::PLACEHOLDERS))
+        .transpose()?
+        .map(Arc::new);
 
-    // let mut join_handles = Vec::new();
-    walk_with_output(
+    if exec_args.check {
+        check_syntax(out, shell, exec_args.command())?;
+    }
+    if exec_args.tty && args.jobs() != 1 {
+        return Err(crate::Error::from_message(
+            "`--tty` requires commands to run one at a time (pass `--jobs 1`)",
+        ));
+    }
+    if exec_args.tty && !exec_args.prefix && !out.is_interactive() {
+        return Err(crate::Error::from_message(
+            "`--tty` requires interactive output, unless `--prefix` is passed",
+        ));
+    }
+    if exec_args.on_error == OnError::Prompt && !out.can_prompt() {
+        return Err(crate::Error::from_message(
+            "`--on-error prompt` requires an interactive terminal",
+        ));
+    }
+
+    let predicate = exec_args
+        .predicate
+        .as_deref()
+        .map(Predicate::parse)
+        .transpose()
+        .map_err(|err| crate::Error::with_context(err, "invalid `--where` predicate"))?;
+    let cwd = exec_args.cwd();
+    if cwd != ExecCwd::Repo {
+        // These options depend on the state of each repo, so cannot apply when running per directory
+        for (set, name) in [
+            (predicate.is_some(), "--where"),
+            (exec_args.require_clean, "--require-clean"),
+            (
+                exec_args.skip_if_newer_than.is_some(),
+                "--skip-if-newer-than",
+            ),
+        ] {
+            if set {
+                return Err(crate::Error::from_message(format!(
+                    "`{}` can only be used with `--cwd repo`",
+                    name
+                )));
+            }
+        }
+
+        return run_per_directory(out, args, exec_args, config, &target, shell, &format);
+    }
+
+    let report = ExecReport::default();
+    let history = exec_args.skip_if_newer_than.map(|_| {
+        let command: Vec<_> = exec_args
+            .command()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        ExecHistory::load(&command)
+    });
+
+    let block = out.block()?;
+    if exec_args.only_errors {
+        block.set_finished_errors_only();
+    }
+    walk::walk_with_block(
         args,
-        out,
+        &block,
+        config,
+        &target,
+        |block, entry| ExecLineContent::build(block, entry, &format, args, exec_args.prefix),
+        |entry, line| {
+            ExecLineContent::update(
+                entry,
+                line,
+                shell,
+                exec_args,
+                predicate.as_ref(),
+                history.as_ref(),
+            );
+            line.content()
+                .write_output(&block, exec_args.print_path_header);
+            exec_args.on_error.handle(&block, line.content());
+            report.record(line.content());
+        },
+    );
+    let result = walk::check_errors(args, &block);
+    drop(block);
+
+    if let Some(history) = &history {
+        history.save();
+    }
+    report.write(out, ("repo", "repos"));
+    result
+}
+
+/// Collects the outcome of each command, to be summarized once they have all finished.
+#[derive(Default)]
+struct ExecReport {
+    succeeded: AtomicUsize,
+    /// The number of commands which succeeded after being retried.
+    retried: AtomicUsize,
+    failed: Mutex<Vec<(PathBuf, String)>>,
+    /// The number of repos the command was not run in, such as those which ran it recently.
+    skipped: AtomicUsize,
+}
+
+/// Check that a command parses under `shell`, without running it.
+fn check_syntax(out: &Output, shell: Shell, args: &[OsString]) -> crate::Result<()> {
+    let mut command = match shell.syntax_check_command(args) {
+        Some(command) => command,
+        // The command is run directly, so there is no syntax to check
+        None if matches!(shell, Shell::None) => return Ok(()),
+        None => {
+            out.writeln_warning(format_args!(
+                "skipping syntax check, which is not supported for shell `{:?}`",
+                shell
+            ));
+            return Ok(());
+        }
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    log::debug!("spawning command `${:?}`", command);
+
+    let output = command
+        .output()
+        .map_err(|err| crate::Error::with_context(err, "failed to check command syntax"))?;
+    if !output.status.success() {
+        return Err(crate::Error::from_message(format!(
+            "command has invalid syntax: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+struct DirectoryEntry {
+    path: PathBuf,
+    relative_path: PathBuf,
+    /// The settings for the directory itself, rather than the repos within it.
+    settings: Settings,
+    repos: Vec<PathBuf>,
+}
+
+impl walk::WorkItem for DirectoryEntry {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn relative_path(&self) -> &Path {
+        &self.relative_path
+    }
+}
+
+/// Run the command once in each directory containing repos, or once in the root directory.
+fn run_per_directory(
+    out: &Output,
+    args: &cli::Args,
+    exec_args: &ExecArgs,
+    config: &Config,
+    target: &Target,
+    shell: Shell,
+    format: &Option<Arc<Template>>,
+) -> crate::Result<()> {
+    let cwd = exec_args.cwd();
+    let report = ExecReport::default();
+
+    let owned_block = out.block()?;
+    if exec_args.only_errors {
+        owned_block.set_finished_errors_only();
+    }
+    let block = &owned_block;
+    let mut directories: Vec<(DirectoryEntry, _)> = Vec::new();
+    let mut root_repos = Vec::new();
+    let mut errors = Vec::new();
+
+    // Directories are only added once a repo in them is not skipped, so each has at least one repo
+    let new_directory = |path: &Path| {
+        let relative_path = config.get_display_path(path);
+        let line = block.add_line(ExecLineContent::new(
+            relative_path.clone(),
+            path.to_owned(),
+            format.clone(),
+            args,
+            exec_args.prefix,
+        ));
+        let entry = DirectoryEntry {
+            path: path.to_owned(),
+            relative_path,
+            settings: config.settings(config.get_relative_path(path)),
+            repos: Vec::new(),
+        };
+        (entry, line)
+    };
+
+    walk::walk(
         config,
-        root,
-        ExecLineContent::build,
-        |entry, line| ExecLineContent::update(entry, line, shell, exec_args),
-    )
+        target,
+        |entry| {
+            if walk::skip(args, block, &entry) {
+                return;
+            }
+
+            if cwd == ExecCwd::Root {
+                root_repos.push(config.get_relative_path(&entry.path).to_owned());
+                return;
+            }
+
+            let parent = entry.path.parent().unwrap_or(&entry.path);
+            match directories.last_mut() {
+                Some((dir, _)) if dir.path == parent => dir.repos.push(entry.path),
+                _ => {
+                    let (mut dir, line) = new_directory(parent);
+                    dir.repos.push(entry.path);
+                    directories.push((dir, line));
+                }
+            }
+        },
+        |_| (),
+        |err| errors.push(err),
+    );
+
+    if !root_repos.is_empty() {
+        root_repos.sort();
+        let (mut dir, line) = new_directory(&config.root);
+        dir.repos = root_repos;
+        directories.push((dir, line));
+    }
+
+    walk::walk_update(args, block, &mut directories, |dir, line| {
+        match cwd {
+            ExecCwd::Root => ExecLineContent::update_root(dir, line, shell, exec_args),
+            ExecCwd::Repo | ExecCwd::Dir => {
+                ExecLineContent::update_directory(dir, line, shell, exec_args)
+            }
+        }
+        line.content()
+            .write_output(block, exec_args.print_path_header);
+        exec_args.on_error.handle(block, line.content());
+        report.record(line.content());
+    });
+    walk::add_discovery_errors(block, config, errors);
+    let result = walk::check_errors(args, block);
+    drop(owned_block);
+
+    report.write(out, ("directory", "directories"));
+    result
+}
+
+impl ExecReport {
+    fn record(&self, content: &ExecLineContent) {
+        let failure = match &*content.state.lock().unwrap() {
+            ExecState::Finished(status) if status.success() => None,
+            ExecState::Finished(status) => Some(status.to_string()),
+            ExecState::Error(err) => Some(err.to_string()),
+            ExecState::Skipped(Some(_)) => {
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            ExecState::Pending | ExecState::Running(_) | ExecState::Skipped(None) => return,
+        };
+
+        match failure {
+            None => {
+                self.succeeded.fetch_add(1, Ordering::Relaxed);
+                if content.attempts.load(Ordering::Relaxed) > 1 {
+                    self.retried.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Some(failure) => self
+                .failed
+                .lock()
+                .unwrap()
+                .push((content.relative_path.clone(), failure)),
+        }
+    }
+
+    /// Print the list of failed commands, followed by a count of successes and failures.
+    fn write(&self, out: &Output, (singular, plural): (&str, &str)) {
+        let mut failed = self.failed.lock().unwrap();
+        failed.sort();
+
+        // In JSON mode, failures are already included in the output for each repo
+        if !out.is_json() && !failed.is_empty() {
+            out.writeln_message("failed:");
+            for (path, failure) in failed.iter() {
+                out.writeln_message(format_args!("  {}: {}", path.display(), failure));
+            }
+        }
+
+        let succeeded = self.succeeded.load(Ordering::Relaxed);
+        let retried = match self.retried.load(Ordering::Relaxed) {
+            0 => String::new(),
+            retried => format!(" ({} after retrying)", retried),
+        };
+        let skipped = match self.skipped.load(Ordering::Relaxed) {
+            0 => String::new(),
+            skipped => format!(", {} skipped", skipped),
+        };
+        let total = succeeded + failed.len();
+        out.writeln_message(format_args!(
+            "ran in {} {}: {} succeeded{}, {} failed{}",
+            total,
+            if total == 1 { singular } else { plural },
+            succeeded,
+            retried,
+            failed.len(),
+            skipped
+        ));
+    }
+}
+
+impl OnError {
+    /// Decide whether to keep running the command after it has finished in one repo or directory.
+    fn handle(self, block: &output::Block, content: &ExecLineContent) {
+        if !content.is_error() || block.is_aborted() {
+            return;
+        }
+
+        match self {
+            OnError::Continue => (),
+            OnError::Abort => block.abort(),
+            OnError::Prompt => {
+                let question = format!(
+                    "command failed in `{}`, continue?",
+                    content.relative_path.display()
+                );
+                if !matches!(block.prompt(question), Ok(true)) {
+                    block.abort();
+                }
+            }
+        }
+    }
+}
+
+impl ExecArgs {
+    /// The position of the `--` separating extra targets from the command, if any. Once a target
+    /// is given, clap keeps `--` as part of the command.
+    fn separator(&self) -> Option<usize> {
+        self.target.as_ref()?;
+        self.command.iter().position(|arg| arg == "--")
+    }
+
+    fn targets(&self) -> Vec<String> {
+        let mut targets: Vec<String> = self.target.iter().cloned().collect();
+        if let Some(separator) = self.separator() {
+            targets.extend(
+                self.command[..separator]
+                    .iter()
+                    .map(|arg| arg.to_string_lossy().into_owned()),
+            );
+        }
+        targets
+    }
+
+    fn command(&self) -> &[OsString] {
+        match self.separator() {
+            Some(separator) => &self.command[separator + 1..],
+            None => &self.command,
+        }
+    }
+
+    fn cwd(&self) -> ExecCwd {
+        if self.chdir_root {
+            ExecCwd::Dir
+        } else {
+            self.cwd
+        }
+    }
+
+    fn build_command(&self, shell: Shell, path: &Path, settings: &Settings) -> Command {
+        let mut command = shell.command(self.command());
+        command.current_dir(path);
+
+        if let Some(env) = &settings.env {
+            command.envs(env);
+        }
+        for var in &self.env {
+            command.env(&var.name, &var.value);
+        }
+
+        if let Some(author) = &self.author {
+            command
+                .env("GIT_AUTHOR_NAME", &author.name)
+                .env("GIT_AUTHOR_EMAIL", &author.email);
+        }
+        if let Some(committer) = &self.committer {
+            command
+                .env("GIT_COMMITTER_NAME", &committer.name)
+                .env("GIT_COMMITTER_EMAIL", &committer.email);
+        }
+
+        command
+    }
+}
+
+impl FromStr for Identity {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, email) = s
+            .trim_end()
+            .strip_suffix('>')
+            .and_then(|s| s.split_once('<'))
+            .ok_or_else(|| crate::Error::from_message("expected `NAME <EMAIL>`"))?;
+        let (name, email) = (name.trim(), email.trim());
+
+        if name.is_empty() {
+            return Err(crate::Error::from_message("name is empty"));
+        }
+        match email.split_once('@') {
+            Some((user, host))
+                if !user.is_empty()
+                    && !host.is_empty()
+                    && !email.contains(|ch: char| ch.is_whitespace() || ch == '<') => {}
+            _ => {
+                return Err(crate::Error::from_message(format!(
+                    "invalid email address `{}`",
+                    email
+                )))
+            }
+        }
+
+        Ok(Identity {
+            name: name.to_owned(),
+            email: email.to_owned(),
+        })
+    }
+}
+
+impl FromStr for EnvVar {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok(EnvVar {
+                name: name.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => Err(crate::Error::from_message("expected `NAME=VALUE`")),
+        }
+    }
 }
 
 impl Shell {
@@ -89,6 +713,18 @@ impl Shell {
         "powershell-core",
     ];
 
+    /// Get a command which checks the syntax of `args` without running it, if supported by this shell.
+    fn syntax_check_command(self, args: &[OsString]) -> Option<Command> {
+        match self {
+            Shell::Bash => {
+                let mut command = Command::new("/bin/sh");
+                command.arg("-n").arg("-c").args(args);
+                Some(command)
+            }
+            Shell::None | Shell::Cmd | Shell::Powershell | Shell::PowershellCore => None,
+        }
+    }
+
     pub fn command(self, args: &[OsString]) -> Command {
         assert!(!args.is_empty());
 
@@ -144,7 +780,16 @@ impl FromStr for Shell {
 
 struct ExecLineContent {
     relative_path: PathBuf,
+    absolute_path: PathBuf,
+    format: Option<Arc<Template>>,
+    max_path_width: Option<usize>,
+    /// A time after which the command is killed.
+    deadline: Option<Instant>,
     state: Arc<Mutex<ExecState>>,
+    /// The number of times the command has been started, which is more than one if it was retried.
+    attempts: AtomicUsize,
+    /// The lines written to stdout or stderr by the command, if `--prefix` was passed.
+    output: Option<Arc<Mutex<Vec<String>>>>,
 }
 
 enum ExecState {
@@ -152,17 +797,51 @@ enum ExecState {
     Running(u32),
     Finished(ExitStatus),
     Error(crate::Error),
+    /// The command was not run. If there is no reason, the repo did not match the `--where`
+    /// predicate and the line is hidden.
+    Skipped(Option<&'static str>),
 }
 
 impl ExecLineContent {
+    const PLACEHOLDERS: &'static [&'static str] = &["path", "code"];
+
+    fn new(
+        relative_path: PathBuf,
+        absolute_path: PathBuf,
+        format: Option<Arc<Template>>,
+        args: &cli::Args,
+        capture: bool,
+    ) -> Self {
+        ExecLineContent {
+            relative_path,
+            absolute_path,
+            format,
+            max_path_width: args.max_path_width,
+            deadline: args.deadline(),
+            state: Arc::new(Mutex::new(ExecState::Pending)),
+            attempts: AtomicUsize::new(0),
+            output: if capture {
+                Some(Arc::new(Mutex::new(Vec::new())))
+            } else {
+                None
+            },
+        }
+    }
+
     fn build<'out, 'block>(
         block: &'block output::Block<'out>,
         entry: &walk::Entry,
+        format: &Option<Arc<Template>>,
+        args: &cli::Args,
+        capture: bool,
     ) -> output::Line<'out, 'block, Self> {
-        block.add_line(ExecLineContent {
-            relative_path: entry.relative_path.clone(),
-            state: Arc::new(Mutex::new(ExecState::Pending)),
-        })
+        block.add_line(ExecLineContent::new(
+            entry.relative_path.clone(),
+            entry.path.clone(),
+            format.clone(),
+            args,
+            capture,
+        ))
     }
 
     fn update<'out, 'block>(
@@ -170,75 +849,479 @@ impl ExecLineContent {
         line: &output::Line<'out, 'block, Self>,
         shell: Shell,
         exec_args: &ExecArgs,
+        predicate: Option<&Predicate>,
+        history: Option<&ExecHistory>,
     ) {
-        let mut command = shell.command(&exec_args.command);
-        command.current_dir(&entry.path);
+        let window = exec_args
+            .skip_if_newer_than
+            .and_then(|window| window.to_std().ok());
+        if let (Some(history), Some(window)) = (history, window) {
+            if history.ran_within(&entry.path, window) {
+                *line.content().state.lock().unwrap() = ExecState::Skipped(Some("ran recently"));
+                return;
+            }
+        }
+
+        if let Some(predicate) = predicate {
+            let options = git::StatusOptions {
+                offline: true,
+                ..Default::default()
+            };
+            match entry.repo.status(&entry.settings, &options) {
+                Ok((status, _)) if predicate.is_match(&status) => (),
+                Ok(_) => {
+                    *line.content().state.lock().unwrap() = ExecState::Skipped(None);
+                    return;
+                }
+                Err(err) => {
+                    *line.content().state.lock().unwrap() = ExecState::Error(
+                        crate::Error::with_context(err, "failed to get repo status"),
+                    );
+                    return;
+                }
+            }
+        }
+
+        if exec_args.require_clean {
+            match entry.repo.working_tree_status() {
+                Ok(status) if status.is_dirty() => {
+                    *line.content().state.lock().unwrap() =
+                        ExecState::Skipped(Some("working tree is dirty"));
+                    return;
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    *line.content().state.lock().unwrap() = ExecState::Error(
+                        crate::Error::with_context(err, "failed to get working tree status"),
+                    );
+                    return;
+                }
+            }
+        }
+
+        let command = exec_args.build_command(shell, &entry.path, &entry.settings);
+
+        ExecLineContent::run(line, command, exec_args);
+
+        if let Some(history) = history {
+            if matches!(&*line.content().state.lock().unwrap(), ExecState::Finished(status) if status.success())
+            {
+                history.record(&entry.path);
+            }
+        }
+    }
+
+    fn update_directory<'out, 'block>(
+        dir: &DirectoryEntry,
+        line: &output::Line<'out, 'block, Self>,
+        shell: Shell,
+        exec_args: &ExecArgs,
+    ) {
+        let command = exec_args.build_command(shell, &dir.path, &dir.settings);
+
+        ExecLineContent::run_with_repos(dir, line, command, exec_args)
+    }
+
+    fn update_root<'out, 'block>(
+        dir: &DirectoryEntry,
+        line: &output::Line<'out, 'block, Self>,
+        shell: Shell,
+        exec_args: &ExecArgs,
+    ) {
+        let mut command = exec_args.build_command(shell, &dir.path, &dir.settings);
+
+        // The arguments after a script are assigned to `$0`, `$1`, ..., so add a placeholder for `$0`
+        // to make the repo paths available as `$@`
+        if matches!(shell, Shell::Bash) && exec_args.command().len() == 1 {
+            command.arg("sh");
+        }
+        command.args(&dir.repos);
+
+        ExecLineContent::run_with_repos(dir, line, command, exec_args)
+    }
+
+    /// Run a command with the paths of the repos in `dir` in the `MULTIGIT_REPOS` variable.
+    fn run_with_repos<'out, 'block>(
+        dir: &DirectoryEntry,
+        line: &output::Line<'out, 'block, Self>,
+        mut command: Command,
+        exec_args: &ExecArgs,
+    ) {
+        match env::join_paths(&dir.repos) {
+            Ok(repos) => {
+                command.env(REPOS_VAR, repos);
+            }
+            Err(err) => {
+                *line.content().state.lock().unwrap() = ExecState::Error(
+                    crate::Error::with_context(err, "failed to set repo paths variable"),
+                );
+                return;
+            }
+        }
+
+        ExecLineContent::run(line, command, exec_args)
+    }
+
+    /// Run the command, retrying it up to `--retries` times while it exits with a non-zero status.
+    fn run<'out, 'block>(
+        line: &output::Line<'out, 'block, Self>,
+        mut command: Command,
+        exec_args: &ExecArgs,
+    ) {
+        let content = line.content();
+        // Without `--prefix`, `--tty` connects the command directly to the terminal
+        let stream = exec_args.tty && content.output.is_none();
+
+        if stream {
+            command.stdin(Stdio::inherit());
+            command.stdout(Stdio::inherit());
+            command.stderr(Stdio::inherit());
+        } else if content.output.is_some() {
+            command.stdin(Stdio::null());
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        } else {
+            command.stdin(Stdio::null());
+            command.stdout(Stdio::null());
+            command.stderr(Stdio::null());
+        }
+
+        let retry_delay = exec_args
+            .retry_delay
+            .and_then(|delay| delay.to_std().ok())
+            .unwrap_or_default();
+
+        for attempt in 1.. {
+            content.attempts.store(attempt, Ordering::Relaxed);
+
+            let result = if stream {
+                let header =
+                    walk::DirectoryLineContent::new(walk::display_path(&content.relative_path));
+                let result = line.suspend(
+                    &header,
+                    || {
+                        let mut child = content.state.lock().unwrap().spawn(&mut command, false)?;
+                        Some(wait_until(&mut child, content.deadline))
+                    },
+                    |result| match result {
+                        Some(Ok(status)) => Some(ExecFooterLineContent { status: *status }),
+                        _ => None,
+                    },
+                );
+                match result {
+                    Ok(Some(result)) => result,
+                    Ok(None) => return,
+                    Err(err) => Err(err),
+                }
+            } else {
+                let mut child = match content
+                    .state
+                    .lock()
+                    .unwrap()
+                    .spawn(&mut command, exec_args.tty)
+                {
+                    Some(child) => child,
+                    None => return,
+                };
+                let readers = content.capture(&mut child);
+                line.update();
+                let result = wait_until(&mut child, content.deadline);
+                join_readers(readers);
+                result
+            };
+
+            let failed = matches!(&result, Ok(status) if !status.success());
+            let can_retry = match content.deadline {
+                _ if interrupt::is_interrupted() => false,
+                Some(deadline) => Instant::now() + retry_delay < deadline,
+                None => true,
+            };
+            if failed && attempt <= exec_args.retries.unwrap_or(0) && can_retry {
+                log::debug!(
+                    "retrying command in `{}` after attempt {} failed",
+                    content.relative_path.display(),
+                    attempt
+                );
+                if !interrupt::sleep(retry_delay) {
+                    content.state.lock().unwrap().finish(result);
+                    return;
+                }
+                // Only the output of the final attempt is shown
+                if let Some(output) = &content.output {
+                    output.lock().unwrap().clear();
+                }
+                continue;
+            }
+
+            content.state.lock().unwrap().finish(result);
+            return;
+        }
+    }
+
+    /// Start threads which read the output of a command into `self.output`, line by line.
+    fn capture(&self, child: &mut Process) -> Vec<thread::JoinHandle<()>> {
+        fn spawn_reader(
+            reader: impl io::Read + Send + 'static,
+            output: Arc<Mutex<Vec<String>>>,
+        ) -> thread::JoinHandle<()> {
+            thread::spawn(move || {
+                for line in io::BufReader::new(reader).split(b'\n') {
+                    match line {
+                        Ok(line) => {
+                            let line = String::from_utf8_lossy(&line);
+                            let line = line.strip_suffix('\r').unwrap_or(&line);
+                            output.lock().unwrap().push(line.to_owned());
+                        }
+                        Err(_) => break,
+                    }
+                }
+            })
+        }
+
+        let output = match &self.output {
+            Some(output) => output,
+            None => return Vec::new(),
+        };
+
+        let mut readers = Vec::new();
+        match child {
+            Process::Piped(child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    readers.push(spawn_reader(stdout, output.clone()));
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    readers.push(spawn_reader(stderr, output.clone()));
+                }
+            }
+            Process::Tty(child) => match child.reader() {
+                Ok(reader) => readers.push(spawn_reader(reader, output.clone())),
+                Err(err) => log::warn!("{}", err),
+            },
+        }
+        readers
+    }
+
+    /// Write the captured output of the command above the block, with each line prefixed by the
+    /// path it ran in, or under a header with the path if `header` is true.
+    fn write_output(&self, block: &output::Block, header: bool) {
+        if let Some(output) = &self.output {
+            let prefix = walk::display_path(&self.relative_path);
+            let output = output.lock().unwrap();
+            if header {
+                if !output.is_empty() {
+                    block
+                        .writeln_above(
+                            Some(&walk::DirectoryLineContent::new(prefix)),
+                            output.iter(),
+                        )
+                        .ok();
+                }
+            } else {
+                block
+                    .writeln_above(
+                        None,
+                        output
+                            .iter()
+                            .map(|line| format!("{}: {}", prefix.display(), line)),
+                    )
+                    .ok();
+            }
+        }
+    }
+}
+
+/// Wait for the threads reading the output of a command which has exited.
+///
+/// Processes started by the command may keep its output open after it has exited or been killed,
+/// so readers which have not finished after a short time are left running in the background.
+fn join_readers(readers: Vec<thread::JoinHandle<()>>) {
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    let start = Instant::now();
+    while readers.iter().any(|reader| !reader.is_finished()) {
+        if start.elapsed() >= TIMEOUT {
+            log::debug!("output of command is still open after it exited, so not waiting for it");
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
 
-        command.stdin(Stdio::null());
-        command.stdout(Stdio::null());
-        command.stderr(Stdio::null());
+    for reader in readers {
+        reader.join().ok();
+    }
+}
+
+/// Wait for a child process to exit, killing it if it is still running at `deadline` or the
+/// command is interrupted.
+fn wait_until(child: &mut Process, deadline: Option<Instant>) -> crate::Result<ExitStatus> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|err| crate::Error::with_context(err, "failed to run command"))?
+        {
+            return Ok(status);
+        }
 
-        let child = line.content().state.lock().unwrap().spawn(command);
-        if let Some(mut child) = child {
-            line.update();
-            let wait_result = child.wait();
-            line.content().state.lock().unwrap().finish(wait_result);
+        let now = Instant::now();
+        let reason = if interrupt::is_interrupted() {
+            Some("interrupted")
+        } else if matches!(deadline, Some(deadline) if now >= deadline) {
+            Some("deadline exceeded")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            child
+                .kill()
+                .map_err(|err| crate::Error::with_context(err, "failed to kill command"))?;
+            child.wait().ok();
+            return Err(crate::Error::from_message(format!(
+                "{}, so the command was killed",
+                reason
+            )));
+        }
+
+        let timeout = match deadline {
+            Some(deadline) => cmp::min(POLL_INTERVAL, deadline - now),
+            None => POLL_INTERVAL,
+        };
+        thread::sleep(timeout);
+    }
+}
+
+/// A running command, either with its output piped to this process or in a pseudo-terminal.
+enum Process {
+    Piped(Child),
+    Tty(PtyChild),
+}
+
+impl Process {
+    fn id(&self) -> u32 {
+        match self {
+            Process::Piped(child) => child.id(),
+            Process::Tty(child) => child.id().unwrap_or_default(),
+        }
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            Process::Piped(child) => child.try_wait(),
+            Process::Tty(child) => child.try_wait(),
+        }
+    }
+
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        match self {
+            Process::Piped(child) => child.wait(),
+            Process::Tty(child) => child.wait(),
+        }
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        match self {
+            Process::Piped(child) => child.kill(),
+            Process::Tty(child) => child.kill(),
         }
     }
 }
 
 impl ExecState {
-    fn spawn(&mut self, mut command: Command) -> Option<Child> {
-        match command.spawn() {
+    fn spawn(&mut self, command: &mut Command, tty: bool) -> Option<Process> {
+        let child = if tty {
+            PtyChild::spawn(command).map(Process::Tty)
+        } else {
+            command
+                .spawn()
+                .map(Process::Piped)
+                .map_err(|err| crate::Error::with_context(err, "failed to spawn command"))
+        };
+
+        match child {
             Ok(child) => {
                 *self = ExecState::Running(child.id());
                 Some(child)
             }
             Err(err) => {
-                let error = crate::Error::with_context(err, "failed to spawn command");
-                *self = ExecState::Error(error);
+                *self = ExecState::Error(err);
                 None
             }
         }
     }
 
-    fn finish(&mut self, status: io::Result<ExitStatus>) {
+    fn finish(&mut self, status: crate::Result<ExitStatus>) {
         match status {
             Ok(status) => {
                 *self = ExecState::Finished(status);
             }
             Err(err) => {
-                *self = ExecState::Error(crate::Error::with_context(err, "failed to run command"));
+                *self = ExecState::Error(err);
             }
         }
     }
 }
 
 impl LineContent for ExecLineContent {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
-        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+    fn is_error(&self) -> bool {
+        match &*self.state.lock().unwrap() {
+            ExecState::Finished(status) => !status.success(),
+            ExecState::Error(_) => true,
+            ExecState::Pending | ExecState::Running(_) | ExecState::Skipped(_) => false,
+        }
+    }
 
-        let (cols, _) = terminal::size()?;
+    fn is_hidden(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), ExecState::Skipped(None))
+    }
 
-        write!(
-            stdout,
-            "{:padding$} ",
-            self.relative_path.display(),
-            padding = cols as usize / 2
-        )?;
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
 
         let state = self.state.lock().unwrap();
 
+        if let (Some(template), ExecState::Finished(status)) = (&self.format, &*state) {
+            let text = template.render(|name| match name {
+                "path" => self.relative_path.display().to_string(),
+                "code" => status
+                    .code()
+                    .map(|code| code.to_string())
+                    .unwrap_or_default(),
+                _ => unreachable!(),
+            });
+            write!(stdout, "{}", text)?;
+            return Ok(());
+        }
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let attempts = self.attempts.load(Ordering::Relaxed);
         match &*state {
-            ExecState::Pending => (),
+            ExecState::Pending | ExecState::Skipped(None) => (),
+            ExecState::Skipped(Some(reason)) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "skipped: {}", reason)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
             ExecState::Running(id) => {
                 write!(stdout, "Running process ")?;
                 crossterm::queue!(stdout, SetAttribute(Attribute::Bold))?;
                 write!(stdout, "{}", id)?;
                 crossterm::queue!(stdout, SetAttribute(Attribute::Reset))?;
+                if attempts > 1 {
+                    write!(stdout, " (attempt {})", attempts)?;
+                }
             }
             ExecState::Finished(status) => {
                 write!(stdout, "{}", status)?;
+                if attempts > 1 {
+                    crossterm::queue!(stdout, SetForegroundColor(Color::Yellow))?;
+                    write!(stdout, " after {} attempts", attempts)?;
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
             }
             ExecState::Error(error) => {
                 error.write(stdout)?;
@@ -248,35 +1331,144 @@ impl LineContent for ExecLineContent {
         Ok(())
     }
 
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()> {
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
         #[derive(Serialize)]
         #[serde(tag = "kind", rename_all = "snake_case")]
         enum JsonExec<'a> {
             Exec {
                 path: String,
+                absolute_path: String,
                 code: Option<i32>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                attempts: Option<usize>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                output: Option<&'a [String]>,
             },
             Error {
                 path: String,
+                absolute_path: String,
                 #[serde(flatten)]
                 error: &'a crate::Error,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                attempts: Option<usize>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                output: Option<&'a [String]>,
+            },
+            Skipped {
+                path: String,
+                absolute_path: String,
+                reason: &'a str,
             },
         }
 
         let state = self.state.lock().unwrap();
+        let output = self.output.as_ref().map(|output| output.lock().unwrap());
+        // Only included if the command was retried, to keep the common case concise
+        let attempts = match self.attempts.load(Ordering::Relaxed) {
+            0 | 1 => None,
+            attempts => Some(attempts),
+        };
 
         let json = match &*state {
-            ExecState::Pending | ExecState::Running(_) => unreachable!(),
+            ExecState::Pending | ExecState::Running(_) | ExecState::Skipped(None) => {
+                unreachable!()
+            }
+            ExecState::Skipped(Some(reason)) => JsonExec::Skipped {
+                path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
+                reason,
+            },
             ExecState::Finished(status) => JsonExec::Exec {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 code: status.code(),
+                attempts,
+                output: output.as_deref().map(Vec::as_slice),
             },
             ExecState::Error(error) => JsonExec::Error {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 error,
+                attempts,
+                output: output.as_deref().map(Vec::as_slice),
             },
         };
 
-        serde_json::to_writer(stdout, &json)
+        serde_json::to_writer(writer, &json)
     }
 }
+
+/// A footer showing how a command connected directly to the terminal exited, written below its
+/// output.
+struct ExecFooterLineContent {
+    status: ExitStatus,
+}
+
+impl LineContent for ExecFooterLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        let color = if self.status.success() {
+            Color::Green
+        } else {
+            Color::Red
+        };
+        crossterm::queue!(stdout, SetForegroundColor(color))?;
+        write!(stdout, "{}", self.status)?;
+        crossterm::queue!(stdout, ResetColor)?;
+        Ok(())
+    }
+
+    fn write_json(&self, _: &mut dyn io::Write) -> serde_json::Result<()> {
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_parse_identity() {
+    let identity = Identity::from_str("Jane Doe <jane@example.com>").unwrap();
+    assert_eq!(identity.name, "Jane Doe");
+    assert_eq!(identity.email, "jane@example.com");
+
+    let cases = vec![
+        "Jane Doe",
+        "<jane@example.com>",
+        "Jane Doe <jane>",
+        "Jane Doe <@example.com>",
+        "Jane Doe <jane@>",
+        "Jane Doe <jane doe@example.com>",
+    ];
+
+    for case in cases {
+        assert!(Identity::from_str(case).is_err(), "{}", case);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_write_header_and_footer() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let write = |content: &dyn LineContent| {
+        let buffer = output::TestWriter::default();
+        output::write_delimiter(&mut buffer.writer(), content).unwrap();
+        buffer.contents()
+    };
+
+    assert_eq!(
+        write(&walk::DirectoryLineContent::new(walk::display_path(
+            Path::new("")
+        ))),
+        "\x1b[0G\x1b[38;5;11m\x1b[4m.\x1b[0m\x1b[0m\n\x1b[0G"
+    );
+    assert_eq!(
+        write(&ExecFooterLineContent {
+            status: ExitStatus::from_raw(0),
+        }),
+        "\x1b[0G\x1b[38;5;10mexit status: 0\x1b[0m\n\x1b[0G"
+    );
+    assert_eq!(
+        write(&ExecFooterLineContent {
+            status: ExitStatus::from_raw(3 << 8),
+        }),
+        "\x1b[0G\x1b[38;5;9mexit status: 3\x1b[0m\n\x1b[0G"
+    );
+}
@@ -0,0 +1,236 @@
+use std::ffi::OsString;
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::process::{Output as ProcessOutput, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use bstr::ByteSlice;
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::{Config, Shell};
+use crate::output::{self, LineContent, Output};
+use crate::walk;
+use crate::{alias, cli};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Check the integrity of your repos")]
+pub struct FsckArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to check. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to check from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+    #[clap(long, help = "only check the connectivity of reachable objects")]
+    connectivity_only: bool,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    fsck_args: &FsckArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if fsck_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&fsck_args.targets, args, config)?
+    };
+
+    let clean = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+
+    let block = out.block()?;
+    walk::walk_with_block(
+        args,
+        &block,
+        config,
+        &target,
+        |block, entry| FsckLineContent::build(block, entry, args.max_path_width),
+        |entry, line| {
+            let is_clean = FsckLineContent::update(entry, line, fsck_args);
+            if is_clean {
+                clean.fetch_add(1, Ordering::Relaxed);
+            } else {
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+        },
+    );
+    let result = walk::check_errors(args, &block);
+    drop(block);
+
+    let clean = clean.load(Ordering::Relaxed);
+    let failed = failed.load(Ordering::Relaxed);
+    out.writeln_message(format_args!(
+        "checked {} {}: {} clean, {} with errors",
+        clean + failed,
+        if clean + failed == 1 { "repo" } else { "repos" },
+        clean,
+        failed,
+    ));
+    result
+}
+
+struct FsckLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<FsckOutcome>>>,
+}
+
+#[derive(Serialize)]
+struct FsckOutcome {
+    code: Option<i32>,
+    problems: Vec<String>,
+}
+
+impl FsckLineContent {
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(FsckLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        fsck_args: &FsckArgs,
+    ) -> bool {
+        log::debug!("checking repo at `{}`", entry.relative_path.display());
+
+        let mut args = vec![
+            OsString::from("git"),
+            OsString::from("fsck"),
+            OsString::from("--no-dangling"),
+            OsString::from("--no-progress"),
+        ];
+        if fsck_args.connectivity_only {
+            args.push(OsString::from("--connectivity-only"));
+        }
+
+        let mut command = Shell::None.command(&args);
+        command
+            .current_dir(&entry.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        log::debug!("spawning command `${:?}`", command);
+
+        let outcome = command
+            .output()
+            .map(FsckOutcome::from_output)
+            .map_err(|err| crate::Error::with_context(err, "failed to run `git fsck`"));
+        let is_clean = matches!(&outcome, Ok(outcome) if outcome.is_clean());
+
+        *line.content().state.lock().unwrap() = Some(outcome);
+        is_clean
+    }
+}
+
+impl FsckOutcome {
+    fn from_output(output: ProcessOutput) -> Self {
+        let problems = output
+            .stdout
+            .lines()
+            .chain(output.stderr.lines())
+            .map(|line| line.to_str_lossy().trim().to_owned())
+            .filter(|line| !line.is_empty() && !line.starts_with("notice:"))
+            .collect();
+
+        FsckOutcome {
+            code: output.status.code(),
+            problems,
+        }
+    }
+
+    fn is_clean(&self) -> bool {
+        self.code == Some(0) && self.problems.is_empty()
+    }
+}
+
+impl LineContent for FsckLineContent {
+    fn is_error(&self) -> bool {
+        !matches!(&*self.state.lock().unwrap(), Some(Ok(outcome)) if outcome.is_clean())
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {}
+            Some(Ok(outcome)) if outcome.is_clean() => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
+                write!(stdout, "clean")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(outcome)) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Red))?;
+                match outcome.problems.first() {
+                    Some(first) => {
+                        write!(stdout, "{} problem(s): {}", outcome.problems.len(), first)?
+                    }
+                    None => match outcome.code {
+                        Some(code) => write!(stdout, "exited with code {}", code)?,
+                        None => write!(stdout, "terminated by signal")?,
+                    },
+                }
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonFsck<'a> {
+            Fsck {
+                path: String,
+                #[serde(flatten)]
+                outcome: &'a FsckOutcome,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => unreachable!(),
+            Some(Ok(outcome)) => JsonFsck::Fsck {
+                path: self.relative_path.display().to_string(),
+                outcome,
+            },
+            Some(Err(error)) => JsonFsck::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
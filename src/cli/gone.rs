@@ -0,0 +1,189 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, LineContent, Output};
+use crate::walk::{self, walk_with_output};
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(about = "List branches whose upstream branch no longer exists")]
+pub struct GoneArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to find branches in. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to find branches in from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+    #[clap(
+        long,
+        help = "delete the branches which are fully merged into the default branch. Unmerged branches and the current branch are kept"
+    )]
+    delete: bool,
+}
+
+impl GoneArgs {
+    pub fn delete(&self) -> bool {
+        self.delete
+    }
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    gone_args: &GoneArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if gone_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&gone_args.targets, args, config)?
+    };
+
+    walk_with_output(
+        args,
+        out,
+        config,
+        &target,
+        |block, entry| GoneLineContent::build(block, entry, args.max_path_width),
+        |entry, line| GoneLineContent::update(entry, line, gone_args.delete),
+    )
+}
+
+struct GoneLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<Vec<git::GoneBranch>>>>,
+}
+
+impl GoneLineContent {
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(GoneLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        delete: bool,
+    ) {
+        log::debug!(
+            "finding gone branches in repo at `{}`",
+            entry.relative_path.display()
+        );
+
+        let outcome = entry
+            .repo
+            .gone_branches(&entry.settings, delete)
+            .map_err(|err| crate::Error::with_context(err, "failed to find gone branches"));
+        *line.content().state.lock().unwrap() = Some(outcome);
+    }
+}
+
+impl LineContent for GoneLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn is_problem(&self) -> bool {
+        match &*self.state.lock().unwrap() {
+            None => false,
+            Some(Ok(branches)) => branches
+                .iter()
+                .any(|branch| branch.state != git::GoneBranchState::Deleted),
+            Some(Err(_)) => true,
+        }
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {}
+            Some(Ok(branches)) if branches.is_empty() => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "no gone branches")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(branches)) => {
+                for (index, branch) in branches.iter().enumerate() {
+                    if index != 0 {
+                        write!(stdout, ", ")?;
+                    }
+
+                    let (color, label) = match branch.state {
+                        git::GoneBranchState::Gone => (Color::Yellow, None),
+                        git::GoneBranchState::Deleted => (Color::Green, Some("deleted")),
+                        git::GoneBranchState::Unmerged => (Color::Yellow, Some("unmerged")),
+                        git::GoneBranchState::CheckedOut => (Color::Yellow, Some("checked out")),
+                        git::GoneBranchState::Default => (Color::Yellow, Some("default branch")),
+                    };
+                    crossterm::queue!(stdout, SetForegroundColor(color))?;
+                    write!(stdout, "{}", branch.name)?;
+                    if let Some(label) = label {
+                        write!(stdout, " ({})", label)?;
+                    }
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonGone<'a> {
+            Gone {
+                path: String,
+                branches: &'a [git::GoneBranch],
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => unreachable!(),
+            Some(Ok(branches)) => JsonGone::Gone {
+                path: self.relative_path.display().to_string(),
+                branches,
+            },
+            Some(Err(error)) => JsonGone::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
@@ -0,0 +1,185 @@
+use std::env;
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+
+use clap::Parser;
+use fs_err::OpenOptions;
+
+use crate::output::Output;
+use crate::{config, interrupt};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Create a starter config file")]
+pub struct InitArgs {
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "the path to write the config file to. Defaults to the value of the MULTIGIT_CONFIG_PATH environment variable",
+        parse(from_os_str)
+    )]
+    path: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "ROOT",
+        help = "the directory containing your repos. Defaults to the current directory",
+        parse(from_os_str)
+    )]
+    root: Option<PathBuf>,
+    #[clap(long, short, help = "overwrite the config file if it already exists")]
+    force: bool,
+}
+
+const TEMPLATE: &str = r#"
+# The default shell used by `mgit exec`.
+# default-shell = "bash"
+
+# The editor used by `mgit edit`.
+# editor = "code"
+
+# Where log files are written, and how many days to keep them for. The log directory can also be set
+# with the MGIT_LOG_DIR environment variable.
+# log-dir = "/path/to/logs"
+# log-retention-days = 7
+
+# The symbol `mgit status` shows for clean repos.
+# clean-glyph = "✓"
+
+# How `mgit status --verbose` shows the time of the last commit: "relative", like "3 days ago", or
+# "absolute", as an ISO 8601 timestamp. Override it with --relative-date or --absolute-date.
+# date-format = "relative"
+
+# Set this to true to remember which directories contain repos, so later commands only re-scan
+# directories which changed since they were last read. Pass --refresh-cache to re-scan everything.
+# discovery-cache = false
+
+# The maximum number of clones and fetches to run at once against each remote host, for hosts which
+# rate limit connections. Hosts can be given their own limit in the [concurrency-per-host] table, and
+# the default can be overridden with --concurrency-per-remote. 0 means no limit.
+# concurrency-per-remote = 4
+
+# Settings applied to every repo. These can be overridden for specific repos in the [settings] table.
+# default-branch = "main"
+# default-remote = "origin"
+# remote-priority = ["upstream", "origin"]
+# When prune is not set, the repo's `fetch.prune` and `remote.<name>.prune` git config are used, and
+# when fetch-tags is not set, tags are fetched unless the remote has `tagOpt = --no-tags`, like `git fetch`.
+# prune = true
+# fetch-tags = true
+# autostash = false
+# Set this to false to let `mgit pull` create a merge commit when a branch has diverged from its
+# upstream, instead of failing. Merges with conflicts are never made.
+# ff-only = true
+# Set this to true to get usernames and passwords for HTTPS remotes by running `git credential fill`,
+# for systems where credential helpers are not found otherwise. The built-in lookup is used if it fails.
+# credential-fill = false
+# Set this to false to skip connecting to the default remote to find its default branch, e.g. for
+# repos whose remote is slow or unreachable.
+# detect-default-branch = true
+# Set this to true for repos such as vendored code or mirrors which should be shown by `mgit status`,
+# but skipped by commands that modify repos, like `mgit pull`, `mgit commit` and `mgit exec`.
+# read-only = false
+# Detached HEADs are described by the nearest tag. Set this to false to only use annotated tags, like
+# `git describe` does.
+# describe-all-tags = true
+# Environment variables set for commands run by `mgit exec`. Variables set for a more specific glob in
+# the [settings] table are added to these, and variables passed with --env take precedence.
+# env = { CARGO_TARGET_DIR = "/tmp/target" }
+
+# The symbols `mgit status` uses to show the state of repos. Use the "ascii" preset if your terminal
+# lacks Unicode support, and override individual symbols as needed.
+[glyphs]
+# preset = "unicode"
+# gone = "×"
+# up-to-date = "≡"
+# ahead = "↑"
+# behind = "↓"
+# conflicted = "U"
+# working-changed = "!"
+# index-changed = "~"
+
+# Short names for paths relative to the root, which can be used in place of a path in any command.
+[aliases]
+# example = "path/to/example"
+
+# Limits on concurrent clones and fetches for specific remote hosts, overriding concurrency-per-remote.
+[concurrency-per-host]
+# "github.com" = 8
+
+# Settings applied to repos whose path relative to the root matches a glob.
+[settings]
+# "vendor/**" = { ignore = true }
+# "work/*" = { default-branch = "develop", editor = "code", env = { NODE_ENV = "development" } }
+# Settings inherited from the defaults or a less specific glob can be cleared with `unset`.
+# "forks/*" = { unset = ["prune", "default-remote"] }
+
+# Named profiles which can override the root, aliases and default settings. Select one with the
+# --profile option or the MGIT_PROFILE environment variable.
+# [profiles.work]
+# root = "/path/to/work"
+# default-branch = "develop"
+# aliases = { api = "services/api" }
+"#;
+
+pub fn run(out: &Output, init_args: &InitArgs) -> crate::Result<()> {
+    let path = match &init_args.path {
+        Some(path) => path.clone(),
+        None => config::file_path().ok_or_else(|| {
+            crate::Error::from_message(format!(
+                "either the `--path` option or the `{}` environment variable must be provided",
+                config::FILE_PATH_VAR
+            ))
+        })?,
+    };
+
+    let root = match &init_args.root {
+        Some(root) => root.clone(),
+        None => env::current_dir()
+            .map_err(|err| crate::Error::with_context(err, "failed to get current directory"))?,
+    };
+    let root = root
+        .to_str()
+        .ok_or_else(|| crate::Error::from_message("root path is invalid UTF-8"))?;
+
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+
+    let guard = interrupt::defer_exit();
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!init_args.force)
+        .open(&path)
+    {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(crate::Error::from_message(format!(
+                "config file `{}` already exists (pass --force to overwrite it)",
+                path.display()
+            )))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    log::debug!("Writing config to `{}`", path.display());
+    write!(
+        file,
+        "# The directory containing your repos.\nroot = {}\n{}",
+        toml::Value::String(root.to_owned()),
+        TEMPLATE
+    )?;
+    drop(guard);
+
+    out.writeln_message(format_args!("created config file `{}`", path.display()));
+    if config::file_path().as_deref() != Some(path.as_path()) {
+        out.writeln_message("add the following line to your shell profile:");
+        out.writeln_message(format_args!(
+            "  export {}=\"{}\"",
+            config::FILE_PATH_VAR,
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::config::{self, Config};
+use crate::output::Output;
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Move a repo to a new path, updating any aliases which point to it")]
+pub struct MoveArgs {
+    #[clap(value_name = "SOURCE", help = "the path or alias of the repo to move")]
+    source: String,
+    #[clap(
+        value_name = "DESTINATION",
+        help = "the new path of the repo, relative to the root",
+        parse(from_os_str)
+    )]
+    destination: PathBuf,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    move_args: &MoveArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let source = alias::resolve(&move_args.source, args, config)?;
+    let destination = config.root.join(&move_args.destination);
+
+    if git::Repository::try_open(&source)?.is_none() {
+        return Err(crate::Error::from_message(format!(
+            "`{}` is not a repo",
+            source.display()
+        )));
+    }
+    if destination.exists() {
+        return Err(crate::Error::from_message(format!(
+            "destination `{}` already exists",
+            destination.display()
+        )));
+    }
+    if destination.starts_with(&source) {
+        return Err(crate::Error::from_message(format!(
+            "cannot move `{}` into itself",
+            source.display()
+        )));
+    }
+
+    if let Some(parent) = destination.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    log::debug!(
+        "moving `{}` to `{}`",
+        source.display(),
+        destination.display()
+    );
+    fs_err::rename(&source, &destination)?;
+    out.writeln_message(format_args!(
+        "moved `{}` to `{}`",
+        config.get_display_path(&source).display(),
+        config.get_display_path(&destination).display()
+    ));
+
+    // Aliases to the repo or any path inside it, with their new paths
+    let mut moved_aliases = Vec::new();
+    for (name, path) in &config.aliases {
+        if let Ok(suffix) = config.root.join(path).strip_prefix(&source) {
+            let mut new_path = config.get_relative_path(&destination).to_owned();
+            if !suffix.as_os_str().is_empty() {
+                new_path.push(suffix);
+            }
+            moved_aliases.push((name, new_path));
+        }
+    }
+    if moved_aliases.is_empty() {
+        return Ok(());
+    }
+
+    let mut updated = Vec::new();
+    config::edit(|document| {
+        let aliases = match document.get_mut("aliases") {
+            Some(aliases) => aliases
+                .as_table_like_mut()
+                .ok_or_else(|| crate::Error::from_message("`aliases` is not a table"))?,
+            None => return Ok(()),
+        };
+
+        for (name, new_path) in &moved_aliases {
+            // Aliases defined by the active profile are left for the user to update
+            if aliases.contains_key(name) {
+                let value = new_path
+                    .to_str()
+                    .ok_or_else(|| crate::Error::from_message("path is invalid UTF-8"))?;
+                aliases.insert(name, toml_edit::value(value));
+                updated.push(*name);
+            }
+        }
+        Ok(())
+    })?;
+
+    for (name, new_path) in &moved_aliases {
+        if updated.contains(name) {
+            out.writeln_message(format_args!(
+                "updated alias `{} = \"{}\"`",
+                name,
+                new_path.display()
+            ));
+        } else {
+            out.writeln_warning(format_args!(
+                "alias `{}` is defined by a profile, and was not updated",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
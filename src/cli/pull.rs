@@ -1,17 +1,20 @@
-use std::borrow::Cow;
+use std::cmp;
 use std::io::{self, Write as _};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use crossterm::style::{Color, ResetColor, SetForegroundColor};
 use crossterm::terminal::{self, Clear, ClearType};
 use serde::Serialize;
 
-use crate::config::Config;
+use crate::config::{Config, Settings};
 use crate::output::{self, LineContent, Output};
 use crate::progress::ProgressBar;
-use crate::walk::{self, walk_with_output};
+use crate::walk;
 use crate::{alias, cli, git};
 
 #[derive(Debug, Parser)]
@@ -19,11 +22,88 @@ use crate::{alias, cli, git};
 pub struct PullArgs {
     #[clap(
         value_name = "TARGET",
-        help = "the path or alias of the repo(s) to pull"
+        help = "the paths, aliases or globs of the repos to pull. Repos matching more than one target are only included once"
     )]
-    target: Option<String>,
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to pull from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
     #[clap(long, help = "whether to switch to the default branch before pulling")]
     switch: bool,
+    #[clap(
+        long,
+        help = "fast-forward the current branch against its upstream, even if it is not the default branch",
+        conflicts_with = "switch"
+    )]
+    no_default_branch_check: bool,
+    #[clap(
+        long,
+        help = "don't fetch tags. This can also be set with the `fetch-tags = false` setting"
+    )]
+    no_tags: bool,
+    #[clap(
+        long,
+        help = "stash uncommitted changes before fast-forwarding or merging and reapply them afterwards. This can also be set with the `autostash = true` setting"
+    )]
+    autostash: bool,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        parse(try_from_str),
+        help = "whether to fail instead of creating a merge commit when the branch has diverged from its upstream. \
+            Pass `--ff-only=false` to merge, unless there are conflicts. This can also be set with the `ff-only = false` setting"
+    )]
+    ff_only: Option<bool>,
+    #[clap(
+        long,
+        value_name = "DEPTH",
+        help = "fetch at most this many commits from the tip of each remote branch, to deepen or shorten the history of shallow clones. \
+            Complete repos are skipped"
+    )]
+    depth: Option<u32>,
+    #[clap(
+        long,
+        help = "fetch the full history of shallow clones, converting them to complete repos",
+        conflicts_with = "depth"
+    )]
+    unshallow: bool,
+    #[clap(
+        long,
+        help = "print one line per repo in a stable format for parsing by scripts, unaffected by terminal width or color. \
+            Each line contains the path of the repo, the outcome (`up_to_date`, `created_unborn`, `fast_forwarded`, `merged` or `error`) \
+            and the branch or error message, separated by tabs"
+    )]
+    porcelain: bool,
+    #[clap(
+        long,
+        help = "show the settings applied to each repo from the config file, after merging the defaults, profile and matching globs",
+        conflicts_with = "porcelain"
+    )]
+    show_settings: bool,
+}
+
+impl PullArgs {
+    pub fn porcelain(&self) -> bool {
+        self.porcelain
+    }
+
+    /// Get the reason a repo is skipped because of the pull options, if any.
+    pub fn skip_reason(&self, repo: &git::Repository) -> Option<&'static str> {
+        if repo.is_bare() {
+            return Some("repo is bare");
+        }
+        if self.depth.is_some() && !repo.is_shallow() {
+            return Some("repo is not shallow");
+        }
+
+        None
+    }
 }
 
 pub fn run(
@@ -32,74 +112,245 @@ pub fn run(
     pull_args: &PullArgs,
     config: &Config,
 ) -> crate::Result<()> {
-    let root = if let Some(name) = &pull_args.target {
-        Cow::Owned(alias::resolve(name, args, config)?)
+    if pull_args.porcelain && out.is_json() {
+        return Err(crate::Error::from_message(
+            "the `--porcelain` option cannot be used with `--json`",
+        ));
+    }
+
+    let target = if pull_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&pull_args.targets, args, config)?
+    };
+
+    let network_limit = args.network_limit(config);
+    let options = git::PullOptions {
+        switch: pull_args.switch,
+        default_branch_check: !pull_args.no_default_branch_check,
+        no_tags: pull_args.no_tags,
+        autostash: pull_args.autostash,
+        ff_only: pull_args.ff_only,
+        network_limit: &network_limit,
+        deadline: args.deadline(),
+        depth: match (pull_args.depth, pull_args.unshallow) {
+            (_, true) => Some(git::FetchDepth::Unshallow),
+            (Some(depth), false) => Some(git::FetchDepth::Depth(depth)),
+            (None, false) => None,
+        },
+    };
+
+    let started = Utc::now();
+    let block = out.block()?;
+    let summary = if out.is_json() || out.is_porcelain() {
+        None
     } else {
-        Cow::Borrowed(&*config.root)
+        let summary = Arc::new(PullSummary::default());
+        block.set_header(PullSummaryLineContent {
+            summary: summary.clone(),
+            max_path_width: args.max_path_width,
+        });
+        Some(summary)
     };
 
-    walk_with_output(
+    walk::walk_with_block(
         args,
-        out,
+        &block,
         config,
-        root,
-        PullLineContent::build,
-        |entry, line| PullLineContent::update(entry, line, pull_args.switch),
-    )
+        &target,
+        |block, entry| PullLineContent::build(block, entry, args, pull_args, &summary),
+        |entry, line| PullLineContent::update(entry, line, &options, started),
+    );
+    walk::check_errors(args, &block)
 }
 
 pub(super) struct PullLineContent {
     relative_path: PathBuf,
+    absolute_path: PathBuf,
+    max_path_width: Option<usize>,
     state: Mutex<PullState>,
+    stats: Mutex<TransferStats>,
+    summary: Option<(Arc<PullSummary>, usize)>,
+    settings: Option<Settings>,
+    /// Whether the repo was a shallow clone before pulling, and is now complete.
+    unshallowed: AtomicBool,
+    /// Why the pull could not be recorded, if it succeeded but cannot be undone.
+    record_error: Mutex<Option<crate::Error>>,
+}
+
+/// Statistics from the last progress update of a pull, included in JSON output.
+#[derive(Default, Serialize)]
+struct TransferStats {
+    total_objects: usize,
+    received_objects: usize,
+    received_bytes: usize,
+    elapsed_ms: u128,
+}
+
+/// The combined progress of all repos being pulled.
+#[derive(Default)]
+struct PullSummary {
+    repos: Mutex<Vec<RepoProgress>>,
+}
+
+#[derive(Default)]
+struct RepoProgress {
+    received_objects: usize,
+    total_objects: usize,
+    finished: bool,
+}
+
+struct PullSummaryLineContent {
+    summary: Arc<PullSummary>,
+    max_path_width: Option<usize>,
 }
 
 enum PullState {
     Pending,
     Downloading(ProgressBar),
     Indexing(ProgressBar),
+    CheckingOut(ProgressBar),
     Finished(crate::Result<git::PullOutcome>),
 }
 
 impl PullLineContent {
-    pub fn new(relative_path: PathBuf) -> Self {
+    pub fn new(
+        relative_path: PathBuf,
+        absolute_path: PathBuf,
+        max_path_width: Option<usize>,
+    ) -> Self {
         PullLineContent {
             relative_path,
+            absolute_path,
+            max_path_width,
             state: Mutex::new(PullState::Pending),
+            stats: Mutex::new(TransferStats::default()),
+            summary: None,
+            settings: None,
+            unshallowed: AtomicBool::new(false),
+            record_error: Mutex::new(None),
         }
     }
 
     pub fn tick(&self, progress: git2::Progress<'_>) {
+        if let Some((summary, index)) = &self.summary {
+            summary.tick(*index, &progress);
+        }
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.total_objects = progress.total_objects();
+            stats.received_objects = progress.received_objects();
+            stats.received_bytes = progress.received_bytes();
+        }
         self.state.lock().unwrap().tick(progress)
     }
 
+    pub fn tick_checkout(&self, completed: usize, total: usize) {
+        self.state.lock().unwrap().tick_checkout(completed, total)
+    }
+
     fn build<'out, 'block>(
         block: &'block output::Block<'out>,
         entry: &walk::Entry,
+        args: &cli::Args,
+        pull_args: &PullArgs,
+        summary: &Option<Arc<PullSummary>>,
     ) -> output::Line<'out, 'block, Self> {
-        block.add_line(PullLineContent::new(entry.relative_path.clone()))
+        let mut content = PullLineContent::new(
+            entry.relative_path.clone(),
+            entry.path.clone(),
+            args.max_path_width,
+        );
+        if let Some(summary) = summary {
+            content.summary = Some((summary.clone(), summary.add_repo()));
+        }
+        if pull_args.show_settings {
+            content.settings = Some(entry.settings.clone());
+        }
+        block.add_line(content)
     }
 
     fn update<'out, 'block>(
         entry: &walk::Entry,
         line: &output::Line<'out, 'block, Self>,
-        switch: bool,
+        options: &git::PullOptions,
+        started: DateTime<Utc>,
     ) {
         log::debug!("pulling repo at `{}`", entry.relative_path.display());
 
+        let start = Instant::now();
+        let shallow = entry.repo.is_shallow();
+
         let outcome = entry
             .repo
-            .status(&entry.settings)
+            .status(&entry.settings, &git::StatusOptions::default())
             .map_err(|err| crate::Error::with_context(err, "failed to get repo status"))
             .and_then(|(status, remote)| {
-                entry
-                    .repo
-                    .pull(&entry.settings, &status, remote, switch, move |progress| {
+                let outcome = entry.repo.pull(
+                    &entry.settings,
+                    &status,
+                    remote,
+                    options,
+                    move |progress| {
                         line.content().tick(progress);
                         line.update();
-                    })
+                    },
+                )?;
+                if let Err(err) =
+                    entry
+                        .repo
+                        .record_pull(started, &entry.settings, options, &status, &outcome)
+                {
+                    *line.content().record_error.lock().unwrap() = Some(
+                        crate::Error::with_context(err, "failed to record pull for `mgit undo`"),
+                    );
+                }
+                Ok(outcome)
             });
 
+        if outcome.is_ok() && shallow && !entry.repo.is_shallow() {
+            line.content().unshallowed.store(true, Ordering::Relaxed);
+        }
+        line.content().stats.lock().unwrap().elapsed_ms = start.elapsed().as_millis();
         *line.content().state.lock().unwrap() = PullState::Finished(outcome);
+        if let Some((summary, index)) = &line.content().summary {
+            summary.finish(*index);
+        }
+    }
+}
+
+impl PullSummary {
+    fn add_repo(&self) -> usize {
+        let mut repos = self.repos.lock().unwrap();
+        repos.push(RepoProgress::default());
+        repos.len() - 1
+    }
+
+    fn tick(&self, index: usize, progress: &git2::Progress<'_>) {
+        let repo = &mut self.repos.lock().unwrap()[index];
+        repo.received_objects = progress.received_objects();
+        repo.total_objects = progress.total_objects();
+    }
+
+    fn finish(&self, index: usize) {
+        self.repos.lock().unwrap()[index].finished = true;
+    }
+
+    /// Get the fraction of objects received across all repos, the number of repos remaining and
+    /// the total number of repos.
+    fn progress(&self) -> (f64, usize, usize) {
+        let repos = self.repos.lock().unwrap();
+
+        let received_objects: usize = repos.iter().map(|repo| repo.received_objects).sum();
+        let total_objects: usize = repos.iter().map(|repo| repo.total_objects).sum();
+        let remaining = repos.iter().filter(|repo| !repo.finished).count();
+
+        let progress = if total_objects == 0 {
+            0.0
+        } else {
+            received_objects as f64 / total_objects as f64
+        };
+        (progress, remaining, repos.len())
     }
 }
 
@@ -120,27 +371,122 @@ impl PullState {
             PullState::Indexing(ref mut bar) => {
                 bar.set(progress.indexed_objects() as f64 / progress.total_objects() as f64);
             }
-            PullState::Finished(_) => {}
+            PullState::CheckingOut(_) | PullState::Finished(_) => {}
+        }
+    }
+
+    pub fn tick_checkout(&mut self, completed: usize, total: usize) {
+        match *self {
+            PullState::Pending | PullState::Downloading(_) | PullState::Indexing(_) => {
+                *self = PullState::CheckingOut(ProgressBar::new());
+                self.tick_checkout(completed, total);
+            }
+            PullState::CheckingOut(ref mut bar) if total != 0 => {
+                bar.set(completed as f64 / total as f64);
+            }
+            PullState::CheckingOut(_) | PullState::Finished(_) => {}
         }
     }
 }
 
-impl LineContent for PullLineContent {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
+/// The widths of each part of a line of pull output.
+struct Layout {
+    path_cols: u16,
+    status_cols: u16,
+    bar_cols: u16,
+}
+
+impl Layout {
+    const STATUS_COLS: u16 = 13;
+
+    fn new(cols: u16, max_path_width: Option<usize>) -> Self {
+        let path_cols = output::path_column_width(cols, max_path_width) as u16;
+        let remaining_cols = cols - path_cols;
+        let status_cols = cmp::min(Layout::STATUS_COLS, remaining_cols);
+        let bar_cols = remaining_cols - status_cols;
+
+        Layout {
+            path_cols,
+            status_cols,
+            bar_cols,
+        }
+    }
+}
+
+impl LineContent for PullSummaryLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
         crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
 
         let (cols, _) = terminal::size()?;
 
-        let relative_path = format!(
+        let layout = Layout::new(cols, self.max_path_width);
+        let (progress, remaining, total) = self.summary.progress();
+
+        crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+        let label = if remaining == 0 {
+            format!("pulled {} repos", total)
+        } else {
+            format!("{} of {} repos remaining", remaining, total)
+        };
+        write!(
+            stdout,
             "{:padding$}",
-            self.relative_path.display(),
-            padding = cols as usize / 2,
-        );
-        write!(stdout, "{}", relative_path)?;
+            output::truncate_start(&label, layout.path_cols as usize),
+            padding = layout.path_cols as usize,
+        )?;
+        if remaining != 0 {
+            write!(
+                stdout,
+                "{:padding$.padding$}",
+                format!("total: {:>3}%", (progress * 100.0) as u32),
+                padding = layout.status_cols as usize
+            )?;
+        }
+        crossterm::queue!(stdout, ResetColor)?;
+
+        if remaining != 0 {
+            let mut bar = ProgressBar::new();
+            bar.set(progress);
+            bar.write(stdout, layout.bar_cols)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonSummary {
+            Summary { remaining: usize, total: usize },
+        }
+
+        let (_, remaining, total) = self.summary.progress();
+        serde_json::to_writer(writer, &JsonSummary::Summary { remaining, total })
+    }
+}
+
+impl LineContent for PullLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), PullState::Finished(Err(_)))
+    }
+
+    fn is_problem(&self) -> bool {
+        self.is_error() || self.record_error.lock().unwrap().is_some()
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
 
-        let remaining_cols = cols.saturating_sub(relative_path.len() as u16);
-        let status_cols = 13;
-        let bar_cols = remaining_cols.saturating_sub(status_cols);
+        let layout = Layout::new(cols, self.max_path_width);
+        let relative_path = self.relative_path.display().to_string();
+        write!(
+            stdout,
+            "{:padding$}",
+            output::truncate_start(&relative_path, layout.path_cols as usize),
+            padding = layout.path_cols as usize,
+        )?;
 
         let state = self.state.lock().unwrap();
         match &*state {
@@ -149,25 +495,37 @@ impl LineContent for PullLineContent {
                 crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
                 write!(
                     stdout,
-                    "{:padding$}",
+                    "{:padding$.padding$}",
                     "downloading:",
-                    padding = status_cols as usize
+                    padding = layout.status_cols as usize
                 )?;
                 crossterm::queue!(stdout, ResetColor)?;
 
-                progress.write(stdout, bar_cols)?;
+                progress.write(stdout, layout.bar_cols)?;
             }
             PullState::Indexing(progress) => {
                 crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
                 write!(
                     stdout,
-                    "{:padding$}",
+                    "{:padding$.padding$}",
                     "indexing:",
-                    padding = status_cols as usize
+                    padding = layout.status_cols as usize
+                )?;
+                crossterm::queue!(stdout, ResetColor)?;
+
+                progress.write(stdout, layout.bar_cols)?;
+            }
+            PullState::CheckingOut(progress) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(
+                    stdout,
+                    "{:padding$.padding$}",
+                    "checkout:",
+                    padding = layout.status_cols as usize
                 )?;
                 crossterm::queue!(stdout, ResetColor)?;
 
-                progress.write(stdout, bar_cols)?;
+                progress.write(stdout, layout.bar_cols)?;
             }
             PullState::Finished(Ok(outcome)) => {
                 crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
@@ -182,48 +540,166 @@ impl LineContent for PullLineContent {
                     git::PullOutcome::FastForwarded(branch) => {
                         write!(stdout, "fast-forwarded branch `{}`", branch)?
                     }
+                    git::PullOutcome::Merged { branch, commits } => write!(
+                        stdout,
+                        "merged {} commit{} into branch `{}`",
+                        commits,
+                        if *commits == 1 { "" } else { "s" },
+                        branch
+                    )?,
+                }
+                if self.unshallowed.load(Ordering::Relaxed) {
+                    write!(stdout, " (unshallowed)")?;
                 }
 
                 crossterm::queue!(stdout, ResetColor)?;
+                if let Some(err) = &*self.record_error.lock().unwrap() {
+                    crossterm::queue!(stdout, SetForegroundColor(Color::Yellow))?;
+                    write!(stdout, " (cannot be undone: {})", err)?;
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
             }
             PullState::Finished(Err(err)) => err.write(stdout)?,
         }
 
+        if let (PullState::Finished(_), Some(settings)) = (&*state, &self.settings) {
+            output::write_settings(stdout, &settings.to_string())?;
+        }
         Ok(())
     }
 
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()> {
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
         #[derive(Serialize)]
         #[serde(tag = "kind", rename_all = "snake_case")]
         enum JsonPull<'a> {
             Pull {
                 path: String,
+                absolute_path: String,
                 #[serde(flatten)]
                 outcome: &'a git::PullOutcome,
+                transfer: &'a TransferStats,
+                #[serde(skip_serializing_if = "output::is_false")]
+                unshallowed: bool,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                record_error: Option<&'a crate::Error>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                settings: Option<&'a Settings>,
             },
             Error {
                 path: String,
+                absolute_path: String,
                 #[serde(flatten)]
                 error: &'a crate::Error,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                settings: Option<&'a Settings>,
             },
         }
 
         let state = self.state.lock().unwrap();
+        let stats = self.stats.lock().unwrap();
+        let record_error = self.record_error.lock().unwrap();
 
         let json = match &*state {
-            PullState::Pending | PullState::Downloading(_) | PullState::Indexing(_) => {
-                unreachable!()
-            }
+            PullState::Pending
+            | PullState::Downloading(_)
+            | PullState::Indexing(_)
+            | PullState::CheckingOut(_) => unreachable!(),
             PullState::Finished(Ok(outcome)) => JsonPull::Pull {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 outcome,
+                transfer: &stats,
+                unshallowed: self.unshallowed.load(Ordering::Relaxed),
+                record_error: record_error.as_ref(),
+                settings: self.settings.as_ref(),
             },
             PullState::Finished(Err(error)) => JsonPull::Error {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 error,
+                settings: self.settings.as_ref(),
             },
         };
 
-        serde_json::to_writer(stdout, &json)
+        serde_json::to_writer(writer, &json)
     }
+
+    fn write_porcelain(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        write!(writer, "{}\t", self.relative_path.display())?;
+
+        match &*self.state.lock().unwrap() {
+            PullState::Pending
+            | PullState::Downloading(_)
+            | PullState::Indexing(_)
+            | PullState::CheckingOut(_) => unreachable!(),
+            PullState::Finished(Ok(outcome)) => {
+                write!(writer, "{}\t{}", outcome.state(), outcome.branch())
+            }
+            PullState::Finished(Err(error)) => {
+                write!(writer, "error\t")?;
+                error.write_plain(writer)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_layout() {
+    for (cols, max_path_width) in (0..100).flat_map(|cols| {
+        vec![
+            (cols, None),
+            (cols, Some(0)),
+            (cols, Some(20)),
+            (cols, Some(200)),
+        ]
+    }) {
+        let layout = Layout::new(cols, max_path_width);
+        assert_eq!(
+            layout.path_cols + layout.status_cols + layout.bar_cols,
+            cols
+        );
+
+        let relative_path = output::truncate_start(
+            "some/very/long/path/to/a/deeply/nested/repository",
+            layout.path_cols as usize,
+        );
+        assert!(relative_path.chars().count() <= layout.path_cols as usize);
+
+        let mut bar = ProgressBar::new();
+        bar.set(0.5);
+        let mut output = Vec::new();
+        bar.write(&mut output, layout.bar_cols).unwrap();
+        assert!(crate::progress::visible_width(&output) <= layout.bar_cols as usize);
+    }
+}
+
+#[test]
+fn test_pull_summary() {
+    let summary = PullSummary::default();
+    let first = summary.add_repo();
+    let second = summary.add_repo();
+    assert_eq!(summary.progress(), (0.0, 2, 2));
+
+    {
+        let mut repos = summary.repos.lock().unwrap();
+        repos[first].received_objects = 3;
+        repos[first].total_objects = 4;
+        repos[second].received_objects = 1;
+        repos[second].total_objects = 4;
+    }
+    summary.finish(first);
+    assert_eq!(summary.progress(), (0.5, 1, 2));
+}
+
+#[test]
+fn test_pull_state_checkout() {
+    let mut state = PullState::Pending;
+    state.tick_checkout(0, 0);
+    assert!(matches!(state, PullState::CheckingOut(_)));
+    state.tick_checkout(1, 2);
+    assert!(matches!(state, PullState::CheckingOut(_)));
+
+    state = PullState::Finished(Err(crate::Error::from_message("failed")));
+    state.tick_checkout(1, 2);
+    assert!(matches!(state, PullState::Finished(_)));
 }
@@ -1,4 +1,8 @@
+use std::env;
+use std::path::Path;
+
 use clap::Parser;
+use serde::Serialize;
 
 use crate::config::Config;
 use crate::output::Output;
@@ -7,8 +11,41 @@ use crate::{alias, cli};
 #[derive(Debug, Parser)]
 #[clap(about = "Resolve a path or alias")]
 pub struct ResolveArgs {
-    #[clap(name = "TARGET", help = "the path or alias of the repo or folder")]
-    target: String,
+    #[clap(
+        name = "TARGET",
+        help = "the path or alias of the repo or folder. With --reverse, the path to find aliases for, which defaults to the current directory",
+        required_unless_present = "reverse"
+    )]
+    target: Option<String>,
+    #[clap(
+        long,
+        short,
+        help = "show the confidence of each suggestion if the target cannot be resolved"
+    )]
+    verbose: bool,
+    #[clap(
+        long,
+        help = "print the path relative to the root, or as set by --relative-to, instead of as an absolute path"
+    )]
+    relative: bool,
+    #[clap(
+        long,
+        help = "don't fail if the path does not exist, e.g. because it is about to be created",
+        overrides_with = "existing-only"
+    )]
+    allow_missing: bool,
+    #[clap(
+        long,
+        help = "fail if the path does not exist. This is the default",
+        overrides_with = "allow-missing"
+    )]
+    existing_only: bool,
+    #[clap(
+        long,
+        help = "print the aliases which resolve to a path, instead of resolving an alias",
+        conflicts_with_all = &["verbose", "relative", "allow-missing", "existing-only"]
+    )]
+    reverse: bool,
 }
 
 pub fn run(
@@ -17,7 +54,54 @@ pub fn run(
     resolve_args: &ResolveArgs,
     config: &Config,
 ) -> crate::Result<()> {
-    let path = alias::resolve(&resolve_args.target, args, config)?;
-    out.writeln_message(path.display());
+    if resolve_args.reverse {
+        return reverse(out, resolve_args.target.as_deref(), config);
+    }
+
+    let target = resolve_args.target.as_deref().unwrap_or_default();
+    let path = if resolve_args.allow_missing {
+        alias::resolve_allow_missing(target, args, config)?
+    } else if resolve_args.verbose {
+        alias::resolve_verbose(target, args, config)?
+    } else {
+        alias::resolve(target, args, config)?
+    };
+
+    if resolve_args.relative {
+        let relative_path = config.get_display_path(&path);
+        if relative_path.as_os_str().is_empty() {
+            out.writeln_value(".");
+        } else {
+            out.writeln_value(relative_path.display());
+        }
+    } else {
+        out.writeln_value(path.display());
+    }
+    Ok(())
+}
+
+fn reverse(out: &Output, target: Option<&str>, config: &Config) -> crate::Result<()> {
+    let cwd = env::current_dir()
+        .map_err(|err| crate::Error::with_context(err, "failed to get current directory"))?;
+    let path = cwd.join(target.map(Path::new).unwrap_or_else(|| Path::new("")));
+
+    for name in alias::reverse(&path, config) {
+        if out.is_json() {
+            #[derive(Serialize)]
+            struct JsonAlias<'a> {
+                kind: &'static str,
+                name: &'a str,
+                path: &'a Path,
+            }
+
+            out.writeln_json(&JsonAlias {
+                kind: "alias",
+                name,
+                path: &config.aliases[name],
+            })?;
+        } else {
+            out.writeln_value(name);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,158 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, LineContent, Output};
+use crate::walk::{self, walk_with_output};
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(
+    about = "Set the upstream of the current branch in repos which have none, to the branch of the same name on the default remote"
+)]
+pub struct SetUpstreamArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to set the upstream for. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to set the upstream for from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    set_upstream_args: &SetUpstreamArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if set_upstream_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&set_upstream_args.targets, args, config)?
+    };
+
+    walk_with_output(
+        args,
+        out,
+        config,
+        &target,
+        |block, entry| SetUpstreamLineContent::build(block, entry, args.max_path_width),
+        SetUpstreamLineContent::update,
+    )
+}
+
+struct SetUpstreamLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<git::SetUpstreamOutcome>>>,
+}
+
+impl SetUpstreamLineContent {
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(SetUpstreamLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(entry: &walk::Entry, line: &output::Line<'out, 'block, Self>) {
+        log::debug!(
+            "setting upstream for repo at `{}`",
+            entry.relative_path.display()
+        );
+
+        let outcome = entry.repo.set_upstream(&entry.settings);
+        *line.content().state.lock().unwrap() = Some(outcome);
+    }
+}
+
+impl LineContent for SetUpstreamLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {}
+            Some(Ok(git::SetUpstreamOutcome::AlreadySet)) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "upstream already set")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(git::SetUpstreamOutcome::NoRemoteBranch)) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "no matching remote branch")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(git::SetUpstreamOutcome::Updated { upstream })) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
+                write!(stdout, "tracking {}", upstream)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(git::SetUpstreamOutcome::Skipped { reason })) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "skipped: {}", reason)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonSetUpstream<'a> {
+            SetUpstream {
+                path: String,
+                #[serde(flatten)]
+                outcome: &'a git::SetUpstreamOutcome,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => unreachable!(),
+            Some(Ok(outcome)) => JsonSetUpstream::SetUpstream {
+                path: self.relative_path.display().to_string(),
+                outcome,
+            },
+            Some(Err(error)) => JsonSetUpstream::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
@@ -1,16 +1,22 @@
-use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use clap::Parser;
 use crossterm::style::{Attribute, Color, ResetColor, SetAttribute, SetForegroundColor};
 use crossterm::terminal::{self, Clear, ClearType};
 use serde::Serialize;
 
-use crate::config::Config;
+use crate::alias::Target;
+use crate::cli::FailOn;
+use crate::config::{Config, DateFormat, GlyphPreset, Settings};
+use crate::format::Template;
 use crate::output::{self, LineContent, Output};
-use crate::walk::{self, walk_with_output};
+use crate::timing::{self, Timer};
+use crate::walk;
 use crate::{alias, cli, git};
 
 #[derive(Debug, Parser)]
@@ -18,9 +24,158 @@ use crate::{alias, cli, git};
 pub struct StatusArgs {
     #[clap(
         value_name = "TARGET",
-        help = "the path or alias of the repo(s) to get status for"
+        help = "the paths, aliases or globs of the repos to get status for. Repos matching more than one target are only included once"
     )]
-    target: Option<String>,
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to get status for from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "stop counting commits ahead of or behind the upstream after this many, or don't count them at all if 0"
+    )]
+    max_ahead_behind: Option<usize>,
+    #[clap(
+        long,
+        value_name = "REF",
+        help = "show how far each repo's head is ahead of or behind this ref, such as `origin/release`, instead of its upstream"
+    )]
+    compare: Option<String>,
+    #[clap(
+        long,
+        short,
+        help = "show additional details, such as the remote url and the time of the last commit"
+    )]
+    verbose: bool,
+    #[clap(
+        long,
+        help = "show the time of the last commit relative to now, like `3 days ago`",
+        conflicts_with = "absolute-date"
+    )]
+    relative_date: bool,
+    #[clap(
+        long,
+        help = "show the time of the last commit as an ISO 8601 timestamp in UTC"
+    )]
+    absolute_date: bool,
+    #[clap(
+        long,
+        help = "show how far every local branch is ahead of or behind its upstream, not just the current branch"
+    )]
+    upstream_all: bool,
+    #[clap(
+        long,
+        help = "fetch from the default remote of each repo before getting its status"
+    )]
+    fetch: bool,
+    #[clap(
+        long,
+        value_name = "JOBS",
+        help = "the number of repos to fetch in parallel. Defaults to the value of --jobs",
+        requires = "fetch"
+    )]
+    fetch_jobs: Option<usize>,
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        help = "a template for each line of output, with placeholders {path}, {branch}, {ahead} and {behind}"
+    )]
+    format: Option<String>,
+    #[clap(
+        long,
+        help = "only show repos which could not be opened or whose upstream branch is gone"
+    )]
+    errors_only: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        help = "only show repos whose last commit is older than this, e.g. `90d` or `12w`",
+        parse(try_from_str = cli::parse_duration)
+    )]
+    older_than: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        help = "only show repos whose last commit is newer than this, e.g. `1h` or `7d`",
+        parse(try_from_str = cli::parse_duration)
+    )]
+    newer_than: Option<Duration>,
+    #[clap(
+        long,
+        help = "also show repos with no commits when filtering by --older-than or --newer-than"
+    )]
+    include_unborn: bool,
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "GROUP",
+        help = "how to group repos: by the directory containing them, the host of their default remote, or their current branch",
+        default_value = "dir"
+    )]
+    group_by: GroupBy,
+    #[clap(
+        long,
+        help = "show repos as a tree mirroring the directory structure, instead of under a header for each directory. JSON output is unaffected",
+        conflicts_with_all = &["group-by", "format"]
+    )]
+    tree: bool,
+    #[clap(
+        long,
+        help = "show clean repos as a single summary line, so only repos which need attention are listed. JSON output still includes every repo"
+    )]
+    collapse: bool,
+    #[clap(
+        long,
+        help = "show the settings applied to each repo from the config file, after merging the defaults, profile and matching globs"
+    )]
+    show_settings: bool,
+}
+
+impl StatusArgs {
+    pub fn fetch(&self) -> bool {
+        self.fetch
+    }
+
+    /// How to show the time of the last commit, or `None` if it should not be shown. The
+    /// `date-format` setting only applies when dates are shown by `--verbose`.
+    fn date_format(&self, config: &Config) -> Option<DateFormat> {
+        if self.relative_date {
+            Some(DateFormat::Relative)
+        } else if self.absolute_date {
+            Some(DateFormat::Absolute)
+        } else if self.verbose {
+            Some(config.date_format.unwrap_or_default())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ArgEnum)]
+enum GroupBy {
+    Dir,
+    Remote,
+    Branch,
+}
+
+/// How the time of the last commit is shown in the terminal.
+#[derive(Copy, Clone, Debug)]
+struct DateDisplay {
+    format: DateFormat,
+    /// The time relative dates are measured from, so every line agrees.
+    now: DateTime<Utc>,
+}
+
+/// Filters repos by the time of their last commit.
+struct AgeFilter {
+    now: DateTime<Utc>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    include_unborn: bool,
 }
 
 pub fn run(
@@ -29,93 +184,588 @@ pub fn run(
     status_args: &StatusArgs,
     config: &Config,
 ) -> crate::Result<()> {
-    let root = if let Some(name) = &status_args.target {
-        Cow::Owned(alias::resolve(name, args, config)?)
+    let target = if status_args.stdin {
+        alias::resolve_stdin_target(args, config)?
     } else {
-        Cow::Borrowed(&*config.root)
+        alias::resolve_targets(&status_args.targets, args, config)?
     };
 
-    walk_with_output(
-        args,
-        out,
-        config,
-        root,
-        StatusLineContent::build,
-        StatusLineContent::update,
-    )
+    let format = status_args
+        .format
+        .as_deref()
+        .map(|format| Template::parse(format, StatusLineContent::PLACEHOLDERS))
+        .transpose()?
+        .map(Arc::new);
+
+    let glyphs = Arc::new(Glyphs::new(config));
+    let now = Utc::now();
+    let dates = status_args
+        .date_format(config)
+        .map(|format| DateDisplay { format, now });
+
+    let options = git::StatusOptions {
+        max_ahead_behind: status_args.max_ahead_behind,
+        compare: status_args.compare.clone(),
+        offline: false,
+        upstream_all: status_args.upstream_all,
+    };
+
+    let age_filter = AgeFilter {
+        now,
+        older_than: status_args.older_than,
+        newer_than: status_args.newer_than,
+        include_unborn: status_args.include_unborn,
+    };
+
+    let fetch_errors = if status_args.fetch {
+        fetch(
+            args,
+            config,
+            &target,
+            status_args.fetch_jobs.unwrap_or_else(|| args.jobs()),
+        )
+    } else {
+        HashMap::new()
+    };
+    let fetch_errors = Mutex::new(fetch_errors);
+
+    let block = out.block()?;
+    if status_args.errors_only {
+        block.set_errors_only();
+    }
+
+    let collapse = status_args.collapse && !out.is_json();
+    let collapsed_count = AtomicUsize::new(0);
+
+    let update = |entry: &walk::Entry, line: &output::Line<StatusLineContent>| {
+        StatusLineContent::update(entry, line, &options, &age_filter, &fetch_errors);
+        if collapse && line.content().collapse() {
+            collapsed_count.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    match status_args.group_by {
+        GroupBy::Dir if status_args.tree && !out.is_json() => walk::walk_tree_with_block(
+            args,
+            &block,
+            config,
+            &target,
+            |block, entry, label| {
+                block.add_line(StatusLineContent {
+                    tree_label: Some(label),
+                    ..StatusLineContent::new(
+                        entry,
+                        args,
+                        status_args,
+                        &format,
+                        &glyphs,
+                        dates,
+                        None,
+                    )
+                })
+            },
+            update,
+        ),
+        GroupBy::Dir => walk::walk_with_block(
+            args,
+            &block,
+            config,
+            &target,
+            |block, entry| {
+                block.add_line(StatusLineContent::new(
+                    entry,
+                    args,
+                    status_args,
+                    &format,
+                    &glyphs,
+                    dates,
+                    None,
+                ))
+            },
+            update,
+        ),
+        group_by => walk::walk_grouped_with_block(
+            args,
+            &block,
+            config,
+            &target,
+            |entry| group_by.group(entry),
+            |block, entry, group| {
+                block.add_line(StatusLineContent::new(
+                    entry,
+                    args,
+                    status_args,
+                    &format,
+                    &glyphs,
+                    dates,
+                    Some(group),
+                ))
+            },
+            update,
+        ),
+    }
+
+    let collapsed_count = collapsed_count.into_inner();
+    if collapsed_count != 0 {
+        block.add_finished_line(CollapsedLineContent {
+            count: collapsed_count,
+        });
+    }
+    walk::check_errors(args, &block)
+}
+
+/// Fetch every repo matching `target` using a separate thread pool from the status computation,
+/// since fetching is network-bound. Returns the errors for any repos which failed to fetch.
+fn fetch(
+    args: &cli::Args,
+    config: &Config,
+    target: &Target,
+    jobs: usize,
+) -> HashMap<PathBuf, crate::Error> {
+    // The repos are found again by the status walk, so finding them here is counted as part of
+    // fetching, not discovery
+    let _timer = Timer::start(timing::Phase::Work);
+
+    let mut entries = Vec::new();
+    // Errors opening repos are reported by the status walk instead.
+    walk::walk(config, target, |entry| entries.push(entry), |_| (), |_| ());
+
+    let network_limit = args.network_limit(config);
+    let errors = Mutex::new(HashMap::new());
+
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .thread_name(|index| format!("rayon-fetch-thread-{}", index))
+        .build()
+        .unwrap();
+    thread_pool.scope(|scope| {
+        for entry in entries {
+            let network_limit = &network_limit;
+            let errors = &errors;
+            scope.spawn(move |_| {
+                log::debug!("fetching repo at `{}`", entry.relative_path.display());
+                if let Err(err) = entry
+                    .repo
+                    .fetch(&entry.settings, network_limit, args.deadline())
+                {
+                    errors.lock().unwrap().insert(entry.path, err);
+                }
+            });
+        }
+    });
+
+    errors.into_inner().unwrap()
+}
+
+/// Write a status line for each state a repo can be in, using made-up repos, so users can preview
+/// how each state is displayed in their terminal.
+pub(super) fn write_samples(out: &Output, args: &cli::Args, config: &Config) -> crate::Result<()> {
+    fn sample(
+        head: git::HeadStatus,
+        upstream: git::UpstreamStatus,
+        working_tree: git::WorkingTreeStatus,
+    ) -> crate::Result<git::RepositoryStatus> {
+        Ok(git::RepositoryStatus {
+            head,
+            upstream,
+            working_tree,
+            default_branch: Some("main".to_owned()),
+            remote_url: Some("https://example.com/repo.git".to_owned()),
+            compare: None,
+            branches: None,
+            last_commit_time: None,
+        })
+    }
+
+    fn branch(name: &str) -> git::HeadStatus {
+        git::HeadStatus {
+            name: name.to_owned(),
+            kind: git::HeadStatusKind::Branch,
+        }
+    }
+
+    fn working_tree(
+        working_changed: bool,
+        index_changed: bool,
+        conflicted: bool,
+    ) -> git::WorkingTreeStatus {
+        git::WorkingTreeStatus {
+            working_changed,
+            index_changed,
+            conflicted,
+        }
+    }
+
+    let upstream = |ahead, behind| {
+        git::UpstreamStatus::Upstream(git::AheadBehind {
+            ahead,
+            behind,
+            ..Default::default()
+        })
+    };
+    let samples = vec![
+        (
+            "clean",
+            sample(
+                branch("main"),
+                upstream(0, 0),
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "ahead",
+            sample(
+                branch("main"),
+                upstream(2, 0),
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "behind",
+            sample(
+                branch("main"),
+                upstream(0, 3),
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "diverged",
+            sample(
+                branch("main"),
+                upstream(2, 3),
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "upstream gone",
+            sample(
+                branch("topic"),
+                git::UpstreamStatus::Gone,
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "not on default branch",
+            sample(
+                branch("topic"),
+                upstream(0, 0),
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "detached",
+            sample(
+                git::HeadStatus {
+                    name: "v1.0.0".to_owned(),
+                    kind: git::HeadStatusKind::Detached,
+                },
+                git::UpstreamStatus::None,
+                working_tree(false, false, false),
+            ),
+        ),
+        (
+            "staged changes",
+            sample(
+                branch("main"),
+                upstream(0, 0),
+                working_tree(false, true, false),
+            ),
+        ),
+        (
+            "initial commit staged",
+            sample(
+                git::HeadStatus {
+                    name: "main".to_owned(),
+                    kind: git::HeadStatusKind::Unborn,
+                },
+                git::UpstreamStatus::None,
+                working_tree(false, true, false),
+            ),
+        ),
+        (
+            "dirty",
+            sample(
+                branch("main"),
+                upstream(0, 0),
+                working_tree(true, true, false),
+            ),
+        ),
+        (
+            "conflicted",
+            sample(
+                branch("main"),
+                upstream(0, 0),
+                working_tree(true, false, true),
+            ),
+        ),
+        (
+            "error",
+            Err(crate::Error::from_message("failed to open repo")),
+        ),
+    ];
+
+    let glyphs = Arc::new(Glyphs::new(config));
+
+    let block = out.block()?;
+    for (name, status) in samples {
+        block.add_finished_line(StatusLineContent {
+            relative_path: PathBuf::from(name),
+            absolute_path: config.root.join(name),
+            max_path_width: args.max_path_width,
+            verbose: false,
+            dates: None,
+            format: None,
+            glyphs: glyphs.clone(),
+            group: None,
+            tree_label: None,
+            settings: None,
+            hidden: AtomicBool::new(false),
+            state: Mutex::new(Some(status)),
+        });
+    }
+
+    Ok(())
+}
+
+/// The symbols used to show the state of a repo, from the `[glyphs]` config section.
+#[derive(Debug, PartialEq)]
+struct Glyphs {
+    clean: String,
+    gone: String,
+    up_to_date: String,
+    ahead: String,
+    behind: String,
+    conflicted: String,
+    working_changed: String,
+    index_changed: String,
+}
+
+impl Glyphs {
+    fn new(config: &Config) -> Self {
+        let glyphs = &config.glyphs;
+        let (clean, gone, up_to_date, ahead, behind, conflicted, working_changed, index_changed) =
+            match glyphs.preset {
+                GlyphPreset::Unicode => ("✓", "×", "≡", "↑", "↓", "U", "!", "~"),
+                GlyphPreset::Ascii => ("ok", "x", "=", "^", "v", "U", "*", "+"),
+            };
+        let choose =
+            |value: &Option<String>, default: &str| value.as_deref().unwrap_or(default).to_owned();
+
+        Glyphs {
+            // `clean-glyph` predates the `[glyphs]` section, so it is still respected
+            clean: choose(
+                &glyphs.clean,
+                config.clean_glyph.as_deref().unwrap_or(clean),
+            ),
+            gone: choose(&glyphs.gone, gone),
+            up_to_date: choose(&glyphs.up_to_date, up_to_date),
+            ahead: choose(&glyphs.ahead, ahead),
+            behind: choose(&glyphs.behind, behind),
+            conflicted: choose(&glyphs.conflicted, conflicted),
+            working_changed: choose(&glyphs.working_changed, working_changed),
+            index_changed: choose(&glyphs.index_changed, index_changed),
+        }
+    }
 }
 
 struct StatusLineContent {
     relative_path: PathBuf,
+    absolute_path: PathBuf,
+    max_path_width: Option<usize>,
+    verbose: bool,
+    dates: Option<DateDisplay>,
+    format: Option<Arc<Template>>,
+    glyphs: Arc<Glyphs>,
+    group: Option<String>,
+    /// The text shown in place of the path with `--tree`, connecting the repo to its directory.
+    tree_label: Option<walk::TreeLabel>,
+    settings: Option<Settings>,
+    hidden: AtomicBool,
     state: Mutex<Option<crate::Result<git::RepositoryStatus>>>,
 }
 
 impl StatusLineContent {
-    fn build<'out, 'block>(
-        block: &'block output::Block<'out>,
+    const PLACEHOLDERS: &'static [&'static str] = &["path", "branch", "ahead", "behind"];
+
+    fn new(
         entry: &walk::Entry,
-    ) -> output::Line<'out, 'block, Self> {
-        block.add_line(StatusLineContent {
+        args: &cli::Args,
+        status_args: &StatusArgs,
+        format: &Option<Arc<Template>>,
+        glyphs: &Arc<Glyphs>,
+        dates: Option<DateDisplay>,
+        group: Option<&str>,
+    ) -> Self {
+        StatusLineContent {
             relative_path: entry.relative_path.clone(),
+            absolute_path: entry.path.clone(),
+            max_path_width: args.max_path_width,
+            verbose: status_args.verbose,
+            dates,
+            format: format.clone(),
+            glyphs: glyphs.clone(),
+            group: group.map(ToOwned::to_owned),
+            tree_label: None,
+            settings: status_args.show_settings.then(|| entry.settings.clone()),
+            hidden: AtomicBool::new(false),
             state: Mutex::new(None),
-        })
+        }
     }
 
-    fn update<'out, 'block>(entry: &walk::Entry, line: &output::Line<'out, 'block, Self>) {
-        let status_result = entry.repo.status(&entry.settings).map(|(status, _)| status);
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        options: &git::StatusOptions,
+        age_filter: &AgeFilter,
+        fetch_errors: &Mutex<HashMap<PathBuf, crate::Error>>,
+    ) {
+        if let Some(err) = fetch_errors.lock().unwrap().remove(&entry.path) {
+            *line.content().state.lock().unwrap() =
+                Some(Err(crate::Error::with_context(err, "failed to fetch")));
+            return;
+        }
+
+        let status_result = age_filter
+            .is_match(&entry.repo)
+            .and_then(|is_match| {
+                line.content().hidden.store(!is_match, Ordering::Relaxed);
+                entry.repo.status(&entry.settings, options)
+            })
+            .map(|(status, _)| status);
         *line.content().state.lock().unwrap() = Some(status_result);
     }
+
+    /// Hide this line if the repo is clean, returning whether it was hidden.
+    fn collapse(&self) -> bool {
+        match &*self.state.lock().unwrap() {
+            Some(Ok(status)) if status.is_clean() && !self.hidden.load(Ordering::Relaxed) => {
+                self.hidden.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn render(&self, template: &Template, status: &git::RepositoryStatus) -> String {
+        template.render(|name| match (name, status.ahead_behind()) {
+            ("path", _) => self.relative_path.display().to_string(),
+            ("branch", _) => status.head.to_string(),
+            ("ahead", Some(counts)) => format_count(counts.ahead, counts.ahead_capped),
+            ("behind", Some(counts)) => format_count(counts.behind, counts.behind_capped),
+            ("ahead", None) | ("behind", None) => String::new(),
+            _ => unreachable!(),
+        })
+    }
+
+    fn format_upstream(&self, upstream: &git::UpstreamStatus) -> (String, Color) {
+        match upstream {
+            git::UpstreamStatus::None => (String::new(), Color::Reset),
+            git::UpstreamStatus::Gone => (self.glyphs.gone.clone(), Color::Red),
+            git::UpstreamStatus::Upstream(counts) => self.format_ahead_behind(counts),
+            git::UpstreamStatus::Uncounted => ("?".to_owned(), Color::Grey),
+        }
+    }
+
+    fn format_ahead_behind(&self, counts: &git::AheadBehind) -> (String, Color) {
+        let ahead = format_count(counts.ahead, counts.ahead_capped);
+        let behind = format_count(counts.behind, counts.behind_capped);
+        match (counts.ahead, counts.behind) {
+            (0, 0) => (self.glyphs.up_to_date.clone(), Color::DarkCyan),
+            (_, 0) => (format!("{}{}", ahead, self.glyphs.ahead), Color::Green),
+            (0, _) => (format!("{}{}", behind, self.glyphs.behind), Color::Red),
+            (_, _) => (
+                format!(
+                    "{}{} {}{}",
+                    behind, self.glyphs.behind, ahead, self.glyphs.ahead
+                ),
+                Color::Yellow,
+            ),
+        }
+    }
+
+    /// Write a row for each branch other than the current one, for `--upstream-all`.
+    fn write_branches(
+        &self,
+        stdout: &mut output::Writer,
+        status: &git::RepositoryStatus,
+        cols: u16,
+    ) -> crossterm::Result<()> {
+        let path_width = output::path_column_width(cols, self.max_path_width);
+        for branch in other_branches(status) {
+            output::write_row_separator(stdout)?;
+            write!(stdout, "{:width$} ", "", width = path_width)?;
+
+            let (text, color) = self.format_upstream(&branch.upstream);
+            crossterm::queue!(stdout, SetForegroundColor(color))?;
+            write!(stdout, "{:>8}   ", text)?;
+            crossterm::queue!(stdout, SetForegroundColor(Color::DarkCyan))?;
+            write!(stdout, "{}", branch.name)?;
+            crossterm::queue!(stdout, ResetColor)?;
+        }
+        Ok(())
+    }
 }
 
 impl LineContent for StatusLineContent {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
         crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
 
+        if let Some(template) = &self.format {
+            if let Some(Ok(status)) = &*self.state.lock().unwrap() {
+                write!(stdout, "{}", self.render(template, status))?;
+                return Ok(());
+            }
+        }
+
         let (cols, _) = terminal::size()?;
 
-        write!(
-            stdout,
-            "{:padding$} ",
-            self.relative_path.display(),
-            padding = cols as usize / 2
-        )?;
+        match &self.tree_label {
+            Some(label) => output::write_tree_column(
+                stdout,
+                &label.prefix,
+                &label.name,
+                cols,
+                self.max_path_width,
+            )?,
+            None => {
+                output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?
+            }
+        }
 
         let status = self.state.lock().unwrap();
         match &*status {
             Some(Ok(status)) => {
-                let (text, color) = match status.upstream {
-                    git::UpstreamStatus::None => (String::new(), Color::Reset),
-                    git::UpstreamStatus::Gone => ("×".to_owned(), Color::Red),
-                    git::UpstreamStatus::Upstream {
-                        ahead: 0,
-                        behind: 0,
-                    } => ("≡".to_owned(), Color::DarkCyan),
-                    git::UpstreamStatus::Upstream { ahead, behind: 0 } => {
-                        (format!("{}↑", ahead), Color::Green)
-                    }
-                    git::UpstreamStatus::Upstream { ahead: 0, behind } => {
-                        (format!("{}↓", behind), Color::Red)
-                    }
-                    git::UpstreamStatus::Upstream { ahead, behind } => {
-                        (format!("{}↓ {}↑", behind, ahead), Color::Yellow)
+                let (text, color) = match (&status.compare, &status.upstream) {
+                    (Some(git::CompareStatus::Missing), _) => ("n/a".to_owned(), Color::Grey),
+                    (Some(git::CompareStatus::Compared(counts)), _) => {
+                        self.format_ahead_behind(counts)
                     }
+                    (Some(git::CompareStatus::Uncounted), _) => ("?".to_owned(), Color::Grey),
+                    (None, upstream) => self.format_upstream(upstream),
                 };
                 crossterm::queue!(stdout, SetForegroundColor(color))?;
                 write!(stdout, "{:>8} ", text)?;
                 stdout.flush()?;
                 crossterm::queue!(stdout, ResetColor)?;
 
-                if status.working_tree.working_changed {
+                if status.working_tree.conflicted {
                     crossterm::queue!(
                         stdout,
                         SetForegroundColor(Color::Red),
                         SetAttribute(Attribute::Bold)
                     )?;
-                    write!(stdout, "! ")?;
+                    write!(stdout, "{} ", self.glyphs.conflicted)?;
+                    crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+                } else if status.working_tree.working_changed {
+                    crossterm::queue!(
+                        stdout,
+                        SetForegroundColor(Color::Red),
+                        SetAttribute(Attribute::Bold)
+                    )?;
+                    write!(stdout, "{} ", self.glyphs.working_changed)?;
                     crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
                 } else if status.working_tree.index_changed {
                     crossterm::queue!(stdout, SetForegroundColor(Color::Cyan),)?;
-                    write!(stdout, "~ ")?;
+                    write!(stdout, "{} ", self.glyphs.index_changed)?;
                     crossterm::queue!(stdout, ResetColor)?;
                 } else {
                     write!(stdout, "  ")?;
@@ -128,29 +778,74 @@ impl LineContent for StatusLineContent {
                 write!(stdout, "{}", status.head)?;
                 stdout.flush()?;
                 crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+
+                if status.is_initial_commit_staged() {
+                    crossterm::queue!(stdout, SetForegroundColor(Color::Cyan))?;
+                    write!(stdout, " (initial commit staged)")?;
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
+
+                if status.is_clean() {
+                    crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
+                    write!(stdout, " {}", self.glyphs.clean)?;
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
+
+                if let (Some(dates), Some(time)) = (&self.dates, status.last_commit_time) {
+                    crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                    write!(stdout, " {}", format_date(dates, time))?;
+                    crossterm::queue!(stdout, ResetColor)?;
+                }
+
+                if self.verbose {
+                    if let Some(remote_url) = &status.remote_url {
+                        crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                        write!(stdout, " {}", remote_url)?;
+                        crossterm::queue!(stdout, ResetColor)?;
+                    }
+                }
             }
             Some(Err(err)) => {
                 err.write(stdout)?;
             }
-            None => {}
+            None => return Ok(()),
         }
 
+        if let Some(settings) = &self.settings {
+            output::write_settings(stdout, &settings.to_string())?;
+        }
+        if let Some(Ok(status)) = &*status {
+            self.write_branches(stdout, status, cols)?;
+        }
         Ok(())
     }
 
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()> {
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
         #[derive(Serialize)]
         #[serde(tag = "kind", rename_all = "snake_case")]
         enum JsonStatus<'a> {
             Status {
                 path: String,
+                absolute_path: String,
                 #[serde(flatten)]
                 status: &'a git::RepositoryStatus,
+                clean: bool,
+                #[serde(skip_serializing_if = "output::is_false")]
+                initial_commit_staged: bool,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                group: Option<&'a str>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                settings: Option<&'a Settings>,
             },
             Error {
                 path: String,
+                absolute_path: String,
                 #[serde(flatten)]
                 error: &'a crate::Error,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                group: Option<&'a str>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                settings: Option<&'a Settings>,
             },
         }
 
@@ -160,14 +855,237 @@ impl LineContent for StatusLineContent {
             None => unreachable!(),
             Some(Ok(status)) => JsonStatus::Status {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 status,
+                clean: status.is_clean(),
+                initial_commit_staged: status.is_initial_commit_staged(),
+                group: self.group.as_deref(),
+                settings: self.settings.as_ref(),
             },
             Some(Err(error)) => JsonStatus::Error {
                 path: self.relative_path.display().to_string(),
+                absolute_path: self.absolute_path.display().to_string(),
                 error,
+                group: self.group.as_deref(),
+                settings: self.settings.as_ref(),
             },
         };
 
-        serde_json::to_writer(stdout, &json)
+        serde_json::to_writer(writer, &json)
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn is_problem(&self) -> bool {
+        match &*self.state.lock().unwrap() {
+            Some(Ok(status)) => matches!(status.upstream, git::UpstreamStatus::Gone),
+            Some(Err(_)) => true,
+            None => false,
+        }
+    }
+
+    fn matches_fail_on(&self, condition: FailOn) -> bool {
+        let state = self.state.lock().unwrap();
+        let status = match &*state {
+            Some(Ok(status)) => status,
+            Some(Err(_)) | None => return false,
+        };
+        match condition {
+            FailOn::RepoErrors => false,
+            FailOn::GoneUpstream => matches!(status.upstream, git::UpstreamStatus::Gone),
+            FailOn::Dirty => status.working_tree.is_dirty(),
+            FailOn::Behind => {
+                matches!(&status.upstream, git::UpstreamStatus::Upstream(counts) if counts.behind > 0)
+            }
+        }
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.hidden.load(Ordering::Relaxed)
     }
+
+    fn rows(&self) -> usize {
+        match &*self.state.lock().unwrap() {
+            Some(Ok(status)) if self.format.is_none() => 1 + other_branches(status).count(),
+            _ => 1,
+        }
+    }
+}
+
+/// A summary of the clean repos hidden by `--collapse`.
+struct CollapsedLineContent {
+    count: usize,
+}
+
+impl LineContent for CollapsedLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(
+            stdout,
+            Clear(ClearType::CurrentLine),
+            SetForegroundColor(Color::Grey)
+        )?;
+        match self.count {
+            1 => write!(stdout, "1 repo clean")?,
+            count => write!(stdout, "{} repos clean", count)?,
+        }
+        crossterm::queue!(stdout, ResetColor)?;
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonCollapsed {
+            Collapsed { count: usize },
+        }
+
+        serde_json::to_writer(writer, &JsonCollapsed::Collapsed { count: self.count })
+    }
+}
+
+/// Format a count of commits ahead or behind, with a `+` if counting stopped at
+/// `--max-ahead-behind`.
+fn format_count(count: usize, capped: bool) -> String {
+    if capped {
+        format!("{}+", count)
+    } else {
+        count.to_string()
+    }
+}
+
+/// The branches with an upstream other than the current branch, if `--upstream-all` was passed.
+fn other_branches(status: &git::RepositoryStatus) -> impl Iterator<Item = &git::BranchStatus> {
+    status
+        .branches
+        .iter()
+        .flatten()
+        .filter(move |branch| !(status.head.is_branch() && branch.name == status.head.name))
+}
+
+impl GroupBy {
+    /// Get the name of the group containing a repo, when not grouping by directory.
+    fn group(self, entry: &walk::Entry) -> String {
+        match self {
+            GroupBy::Dir => unreachable!(),
+            GroupBy::Remote => entry
+                .repo
+                .default_remote_url(&entry.settings)
+                .as_deref()
+                .and_then(git::remote_host)
+                .unwrap_or_else(|| "(no remote host)".to_owned()),
+            GroupBy::Branch => match entry.repo.head_status(&entry.settings) {
+                Ok(head) => head.to_string(),
+                Err(_) => "(unknown branch)".to_owned(),
+            },
+        }
+    }
+}
+
+fn format_date(dates: &DateDisplay, time: DateTime<Utc>) -> String {
+    match dates.format {
+        DateFormat::Relative => format_relative_date(dates.now - time),
+        DateFormat::Absolute => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+    }
+}
+
+/// Describe how long ago something happened, like `git log --date=relative`.
+fn format_relative_date(age: Duration) -> String {
+    let (count, unit) = if age.num_minutes() < 1 {
+        // Commits slightly in the future, because of clock skew, are also shown as just now
+        return "just now".to_owned();
+    } else if age.num_hours() < 1 {
+        (age.num_minutes(), "minute")
+    } else if age.num_days() < 1 {
+        (age.num_hours(), "hour")
+    } else if age.num_weeks() < 2 {
+        (age.num_days(), "day")
+    } else if age.num_days() < 60 {
+        (age.num_weeks(), "week")
+    } else if age.num_days() < 365 {
+        (age.num_days() / 30, "month")
+    } else {
+        (age.num_days() / 365, "year")
+    };
+
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
+
+impl AgeFilter {
+    fn is_match(&self, repo: &git::Repository) -> crate::Result<bool> {
+        if self.older_than.is_none() && self.newer_than.is_none() {
+            return Ok(true);
+        }
+
+        let time = match repo.head_commit_time()? {
+            Some(time) => time,
+            None => return Ok(self.include_unborn),
+        };
+        let age = self.now - time;
+
+        Ok(!matches!(self.older_than, Some(limit) if age <= limit)
+            && !matches!(self.newer_than, Some(limit) if age > limit))
+    }
+}
+
+#[test]
+fn test_glyphs() {
+    let config: Config = toml::from_str(
+        r#"
+        root = "/"
+        clean-glyph = "clean"
+
+        [glyphs]
+        preset = "ascii"
+        behind = "<"
+        "#,
+    )
+    .unwrap();
+    let glyphs = Glyphs::new(&config);
+
+    assert_eq!(glyphs.clean, "clean");
+    assert_eq!(glyphs.behind, "<");
+    assert_eq!(glyphs.ahead, "^");
+    assert_eq!(glyphs.gone, "x");
+    assert_eq!(glyphs.working_changed, "*");
+}
+
+#[test]
+fn test_format_date() {
+    let now: DateTime<Utc> = "2021-12-25T09:30:00Z".parse().unwrap();
+    let relative = DateDisplay {
+        format: DateFormat::Relative,
+        now,
+    };
+    let cases = vec![
+        (Duration::seconds(-30), "just now"),
+        (Duration::seconds(59), "just now"),
+        (Duration::minutes(1), "1 minute ago"),
+        (Duration::minutes(59), "59 minutes ago"),
+        (Duration::hours(5), "5 hours ago"),
+        (Duration::days(3), "3 days ago"),
+        (Duration::days(13), "13 days ago"),
+        (Duration::days(20), "2 weeks ago"),
+        (Duration::days(90), "3 months ago"),
+        (Duration::days(400), "1 year ago"),
+        (Duration::days(800), "2 years ago"),
+    ];
+
+    for (age, expected) in cases {
+        assert_eq!(format_date(&relative, now - age), expected, "{}", age);
+    }
+
+    let absolute = DateDisplay {
+        format: DateFormat::Absolute,
+        now,
+    };
+    assert_eq!(
+        format_date(&absolute, now - Duration::days(3)),
+        "2021-12-22T09:30:00Z"
+    );
 }
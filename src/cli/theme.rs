@@ -0,0 +1,13 @@
+use clap::Parser;
+
+use crate::cli::{self, status};
+use crate::config::Config;
+use crate::output::Output;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Preview how each repo status is displayed")]
+pub struct ThemeArgs {}
+
+pub fn run(out: &Output, args: &cli::Args, _: &ThemeArgs, config: &Config) -> crate::Result<()> {
+    status::write_samples(out, args, config)
+}
@@ -0,0 +1,248 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, LineContent, Output};
+use crate::walk;
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(
+    about = "Undo the most recent pull, resetting each branch it changed to where it was before and switching back to the previous branch"
+)]
+pub struct UndoArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to undo the pull in. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(long, short, help = "undo the pull without asking for confirmation")]
+    yes: bool,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    undo_args: &UndoArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = alias::resolve_targets(&undo_args.targets, args, config)?;
+
+    let mut pulls = Vec::new();
+    let mut read_errors = Vec::new();
+    let mut errors = Vec::new();
+    walk::walk(
+        config,
+        &target,
+        |entry| match entry.repo.last_pull() {
+            Ok(Some(record)) => pulls.push((entry, record)),
+            Ok(None) => (),
+            Err(err) => read_errors.push(walk::DiscoveryError::new(
+                &entry.path,
+                crate::Error::with_context(err, "failed to read the last pull"),
+            )),
+        },
+        |_| (),
+        |err| errors.push(err),
+    );
+    errors.extend(read_errors);
+
+    // Only the most recent pull is undone, which may have changed some repos but not others
+    let time = match pulls.iter().map(|(_, record)| record.time).max() {
+        Some(time) => time,
+        None => {
+            let block = out.block()?;
+            walk::add_discovery_errors(&block, config, errors);
+            let result = walk::check_errors(args, &block);
+            drop(block);
+            if result.is_ok() {
+                out.writeln_message("no pull to undo");
+            }
+            return result;
+        }
+    };
+    pulls.retain(|(_, record)| record.time == time);
+    pulls.sort_by(|(l, _), (r, _)| l.relative_path.cmp(&r.relative_path));
+
+    if !undo_args.yes && !out.can_prompt() {
+        return Err(crate::Error::from_message(
+            "pass `--yes` to undo the pull without confirmation",
+        ));
+    }
+
+    let block = out.block()?;
+    pulls.retain(|(entry, _)| !walk::skip(args, &block, entry));
+    if pulls.is_empty() {
+        walk::add_discovery_errors(&block, config, errors);
+        return walk::check_errors(args, &block);
+    }
+
+    let contents: Vec<_> = pulls
+        .iter()
+        .map(|(entry, record)| UndoLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width: args.max_path_width,
+            record: record.clone(),
+            state: Mutex::new(None),
+        })
+        .collect();
+
+    if !undo_args.yes {
+        block.writeln_above(
+            None,
+            contents.iter().map(|content| {
+                format!(
+                    "`{}`: will {}",
+                    walk::display_path(&content.relative_path).display(),
+                    content.describe(false)
+                )
+            }),
+        )?;
+        let question = match contents.len() {
+            1 => "undo the last pull in 1 repo?".to_owned(),
+            count => format!("undo the last pull in {} repos?", count),
+        };
+        if !block.prompt(question)? {
+            drop(block);
+            out.writeln_message("undo cancelled");
+            return Ok(());
+        }
+    }
+
+    for ((entry, record), content) in pulls.iter().zip(contents) {
+        let line = block.add_line(content);
+        log::debug!(
+            "undoing pull of repo at `{}`",
+            entry.relative_path.display()
+        );
+
+        let result = entry
+            .repo
+            .undo_pull(record)
+            .map_err(|err| crate::Error::with_context(err, "cannot undo pull"));
+        *line.content().state.lock().unwrap() = Some(result);
+        line.finish();
+    }
+
+    walk::add_discovery_errors(&block, config, errors);
+    walk::check_errors(args, &block)
+}
+
+struct UndoLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    record: git::PullRecord,
+    state: Mutex<Option<crate::Result<()>>>,
+}
+
+impl UndoLineContent {
+    /// Describe how the repo is reset, in the past tense once it is done.
+    fn describe(&self, done: bool) -> String {
+        let record = &self.record;
+        let mut actions = Vec::new();
+        if let Some(moved) = &record.moved {
+            let branch = short_name(&record.branch);
+            actions.push(match (&moved.from, done) {
+                (Some(from), false) => format!("reset branch `{}` to {}", branch, short_oid(from)),
+                (Some(from), true) => format!("branch `{}` reset to {}", branch, short_oid(from)),
+                (None, false) => format!("delete branch `{}`", branch),
+                (None, true) => format!("branch `{}` deleted", branch),
+            });
+        }
+        if let Some(switched_from) = &record.switched_from {
+            let branch = short_name(switched_from);
+            actions.push(if done {
+                format!("switched back to `{}`", branch)
+            } else {
+                format!("switch back to `{}`", branch)
+            });
+        }
+        actions.join(", ")
+    }
+}
+
+fn short_name(branch: &str) -> &str {
+    branch.strip_prefix("refs/heads/").unwrap_or(branch)
+}
+
+fn short_oid(oid: &str) -> &str {
+    oid.get(..7).unwrap_or(oid)
+}
+
+impl LineContent for UndoLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "will {}", self.describe(false))?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok(())) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Green))?;
+                write!(stdout, "{}", self.describe(true))?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonUndo<'a> {
+            /// The repo has not been reset yet.
+            Pending {
+                path: String,
+                #[serde(flatten)]
+                record: &'a git::PullRecord,
+            },
+            Undo {
+                path: String,
+                #[serde(flatten)]
+                record: &'a git::PullRecord,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => JsonUndo::Pending {
+                path: self.relative_path.display().to_string(),
+                record: &self.record,
+            },
+            Some(Ok(())) => JsonUndo::Undo {
+                path: self.relative_path.display().to_string(),
+                record: &self.record,
+            },
+            Some(Err(error)) => JsonUndo::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
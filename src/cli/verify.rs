@@ -0,0 +1,191 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, LineContent, Output};
+use crate::walk::{self, walk_with_output};
+use crate::{alias, cli, git};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Check the signatures on commits in your repos")]
+pub struct VerifyArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the paths, aliases or globs of the repos to verify. Repos matching more than one target are only included once"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to verify from stdin, one per line",
+        conflicts_with = "targets"
+    )]
+    stdin: bool,
+    #[clap(
+        long,
+        value_name = "REVISION",
+        help = "the commit to verify in each repo",
+        default_value = "HEAD"
+    )]
+    rev: String,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    verify_args: &VerifyArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if verify_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_targets(&verify_args.targets, args, config)?
+    };
+
+    walk_with_output(
+        args,
+        out,
+        config,
+        &target,
+        |block, entry| VerifyLineContent::build(block, entry, args.max_path_width),
+        |entry, line| VerifyLineContent::update(entry, line, &verify_args.rev),
+    )
+}
+
+struct VerifyLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<(String, git::SignatureStatus)>>>,
+}
+
+impl VerifyLineContent {
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(VerifyLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        rev: &str,
+    ) {
+        log::debug!(
+            "verifying `{}` in repo at `{}`",
+            rev,
+            entry.relative_path.display()
+        );
+
+        let outcome = entry
+            .repo
+            .verify_signature(rev)
+            .map_err(|err| crate::Error::with_context(err, "failed to verify signature"));
+        *line.content().state.lock().unwrap() = Some(outcome);
+    }
+}
+
+impl LineContent for VerifyLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn is_problem(&self) -> bool {
+        !matches!(
+            &*self.state.lock().unwrap(),
+            None | Some(Ok((_, git::SignatureStatus::Valid { .. })))
+        )
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None => {}
+            Some(Ok((_, git::SignatureStatus::Unsigned))) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+                write!(stdout, "unsigned")?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok((
+                _,
+                git::SignatureStatus::Valid {
+                    signer, trusted, ..
+                },
+            ))) => {
+                let color = if *trusted {
+                    Color::Green
+                } else {
+                    Color::Yellow
+                };
+                crossterm::queue!(stdout, SetForegroundColor(color))?;
+                match signer {
+                    Some(signer) => write!(stdout, "signed by {}", signer)?,
+                    None => write!(stdout, "signed")?,
+                }
+                if !trusted {
+                    write!(stdout, " (untrusted)")?;
+                }
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Ok((_, git::SignatureStatus::Invalid { reason, .. }))) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::Red))?;
+                write!(stdout, "invalid: {}", reason)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonVerify<'a> {
+            Verify {
+                path: String,
+                commit: &'a str,
+                #[serde(flatten)]
+                status: &'a git::SignatureStatus,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None => unreachable!(),
+            Some(Ok((commit, status))) => JsonVerify::Verify {
+                path: self.relative_path.display().to_string(),
+                commit,
+                status,
+            },
+            Some(Err(error)) => JsonVerify::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
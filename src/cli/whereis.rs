@@ -0,0 +1,163 @@
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::{AppSettings, Parser};
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, LineContent, Output};
+use crate::walk;
+use crate::{alias, cli};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Find which of your repos contain a commit, branch or tag")]
+#[clap(setting = AppSettings::AllowMissingPositional)]
+pub struct WhereisArgs {
+    #[clap(
+        value_name = "TARGET",
+        help = "the path, alias or glob of the repo(s) to search"
+    )]
+    target: Option<String>,
+    #[clap(
+        value_name = "REVISION",
+        help = "the branch, tag or (possibly abbreviated) commit hash to search for"
+    )]
+    revision: String,
+    #[clap(
+        long,
+        help = "read the paths or aliases of the repos to search from stdin, one per line",
+        conflicts_with = "target"
+    )]
+    stdin: bool,
+}
+
+pub fn run(
+    out: &Output,
+    args: &cli::Args,
+    whereis_args: &WhereisArgs,
+    config: &Config,
+) -> crate::Result<()> {
+    let target = if whereis_args.stdin {
+        alias::resolve_stdin_target(args, config)?
+    } else {
+        alias::resolve_target(whereis_args.target.as_deref(), args, config)?
+    };
+
+    let block = out.block()?;
+    // Only repos containing the revision, or which could not be searched, are shown
+    block.set_errors_only();
+
+    walk::walk_with_block(
+        args,
+        &block,
+        config,
+        &target,
+        |block, entry| WhereisLineContent::build(block, entry, args.max_path_width),
+        |entry, line| WhereisLineContent::update(entry, line, &whereis_args.revision),
+    );
+    walk::check_errors(args, &block)
+}
+
+struct WhereisLineContent {
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    state: Mutex<Option<crate::Result<Option<String>>>>,
+}
+
+impl WhereisLineContent {
+    fn build<'out, 'block>(
+        block: &'block output::Block<'out>,
+        entry: &walk::Entry,
+        max_path_width: Option<usize>,
+    ) -> output::Line<'out, 'block, Self> {
+        block.add_line(WhereisLineContent {
+            relative_path: entry.relative_path.clone(),
+            max_path_width,
+            state: Mutex::new(None),
+        })
+    }
+
+    fn update<'out, 'block>(
+        entry: &walk::Entry,
+        line: &output::Line<'out, 'block, Self>,
+        revision: &str,
+    ) {
+        log::debug!(
+            "searching for `{}` in repo at `{}`",
+            revision,
+            entry.relative_path.display()
+        );
+
+        let outcome = entry.repo.find_revision(revision);
+        *line.content().state.lock().unwrap() = Some(outcome);
+    }
+}
+
+impl LineContent for WhereisLineContent {
+    fn is_error(&self) -> bool {
+        matches!(&*self.state.lock().unwrap(), Some(Err(_)))
+    }
+
+    fn is_problem(&self) -> bool {
+        matches!(
+            &*self.state.lock().unwrap(),
+            Some(Ok(Some(_))) | Some(Err(_))
+        )
+    }
+
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+
+        let state = self.state.lock().unwrap();
+        match &*state {
+            None | Some(Ok(None)) => {}
+            Some(Ok(Some(oid))) => {
+                crossterm::queue!(stdout, SetForegroundColor(Color::DarkCyan))?;
+                write!(stdout, "{}", oid)?;
+                crossterm::queue!(stdout, ResetColor)?;
+            }
+            Some(Err(err)) => err.write(stdout)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonWhereis<'a> {
+            Found {
+                path: String,
+                oid: &'a str,
+            },
+            Error {
+                path: String,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        let state = self.state.lock().unwrap();
+
+        let json = match &*state {
+            None | Some(Ok(None)) => unreachable!(),
+            Some(Ok(Some(oid))) => JsonWhereis::Found {
+                path: self.relative_path.display().to_string(),
+                oid,
+            },
+            Some(Err(error)) => JsonWhereis::Error {
+                path: self.relative_path.display().to_string(),
+                error,
+            },
+        };
+
+        serde_json::to_writer(writer, &json)
+    }
+}
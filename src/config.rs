@@ -1,37 +1,124 @@
 use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::io::{Read as _, Seek as _, SeekFrom, Write as _};
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
 use std::{env, fmt};
 
 use fn_error_context::context;
+use fs2::FileExt as _;
 use globset::{Glob, GlobSet, GlobSetBuilder};
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use toml_edit::Document;
 
+use crate::interrupt;
+
 pub const FILE_PATH_VAR: &str = "MULTIGIT_CONFIG_PATH";
+pub const PROFILE_VAR: &str = "MGIT_PROFILE";
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub root: PathBuf,
     #[serde(default)]
     pub default_shell: Shell,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_retention_days: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clean_glyph: Option<String>,
+    #[serde(default, skip_serializing_if = "GlyphSettings::is_default")]
+    pub glyphs: GlyphSettings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<DateFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery_cache: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_per_remote: Option<usize>,
+    /// How paths are displayed. This is set from the command line rather than the config file.
+    #[serde(skip)]
+    pub relative_to: RelativeTo,
+    /// Whether to ignore the discovery cache and re-scan every directory. This is set from the
+    /// command line rather than the config file.
+    #[serde(skip)]
+    pub refresh_cache: bool,
 
     // Default settings. These fields are duplicated here because of the limitations of serde's #[flatten] attribute
     // https://github.com/dtolnay/serde-ignored/issues/10
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub default_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub default_remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_priority: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ssh: Option<SshSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub editor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ignore: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub prune: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_tags: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autostash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ff_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_fill: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect_default_branch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub describe_all_tags: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
 
     #[serde(default)]
     pub aliases: BTreeMap<String, PathBuf>,
+    /// Limits on concurrent network operations for specific remote hosts, overriding
+    /// `concurrency_per_remote`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub concurrency_per_host: BTreeMap<String, usize>,
     #[serde(default)]
     pub settings: SettingsMatcher,
+    /// Profiles are not serialized, since the selected profile has already been applied.
+    #[serde(default, skip_serializing)]
+    pub profiles: BTreeMap<String, Profile>,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+/// A named set of overrides for the base config, selected with `--profile` or the `MGIT_PROFILE`
+/// environment variable.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    pub root: Option<PathBuf>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, PathBuf>,
+
+    // Default settings, duplicated for the same reason as in `Config`
+    pub default_branch: Option<String>,
+    pub default_remote: Option<String>,
+    pub remote_priority: Option<Vec<String>>,
+    pub ssh: Option<SshSettings>,
+    pub editor: Option<String>,
+    pub ignore: Option<bool>,
+    pub prune: Option<bool>,
+    pub fetch_tags: Option<bool>,
+    pub autostash: Option<bool>,
+    pub ff_only: Option<bool>,
+    pub credential_fill: Option<bool>,
+    pub detect_default_branch: Option<bool>,
+    pub read_only: Option<bool>,
+    pub describe_all_tags: Option<bool>,
+    pub env: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub unset: Vec<SettingName>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Shell {
     None,
@@ -43,31 +130,132 @@ pub enum Shell {
     PowershellCore,
 }
 
-pub fn parse(on_ignored: impl FnMut(serde_ignored::Path)) -> crate::Result<Config> {
+/// The symbols `mgit status` uses to show the state of a repo. Each symbol defaults to the one from
+/// `preset`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GlyphSettings {
+    #[serde(default)]
+    pub preset: GlyphPreset,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clean: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up_to_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ahead: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub behind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_changed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_changed: Option<String>,
+}
+
+impl GlyphSettings {
+    fn is_default(&self) -> bool {
+        *self == GlyphSettings::default()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GlyphPreset {
+    #[default]
+    Unicode,
+    Ascii,
+}
+
+/// How `mgit status` shows the time of the last commit in each repo.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateFormat {
+    /// The time since the commit, like `3 days ago`.
+    #[default]
+    Relative,
+    /// An ISO 8601 timestamp in UTC, like `2021-12-25T09:30:00Z`.
+    Absolute,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, clap::ArgEnum)]
+pub enum RelativeTo {
+    #[default]
+    Root,
+    Cwd,
+    Absolute,
+}
+
+/// Parse the config file, applying the profile named by `profile` or the `MGIT_PROFILE`
+/// environment variable, if any.
+///
+/// Problems which do not prevent the config from being used, such as aliases which point to paths
+/// that do not exist, are passed to `on_warning`.
+pub fn parse(
+    profile: Option<&str>,
+    on_ignored: impl FnMut(serde_ignored::Path),
+    on_warning: impl FnMut(String),
+) -> crate::Result<Config> {
+    let profile = match profile {
+        Some(profile) => Some(profile.to_owned()),
+        None => env::var(PROFILE_VAR)
+            .ok()
+            .filter(|profile| !profile.is_empty()),
+    };
+
     match file_path() {
         Some(path) => {
-            let config = parse_file(path, on_ignored)?;
+            let mut config = parse_file(path, on_ignored)?;
+            if let Some(profile) = &profile {
+                config.apply_profile(profile)?;
+            }
             config
-                .validate()
+                .validate(on_warning)
                 .map_err(|err| crate::Error::with_context(err, "invalid config"))?;
             Ok(config)
         }
-        None => Config::default(),
+        None => match &profile {
+            Some(profile) => Err(crate::Error::from_message(format!(
+                "profile `{}` does not exist (no config file is set)",
+                profile
+            ))),
+            None => Config::default(),
+        },
     }
 }
 
 #[context("failed to edit config file")]
 pub fn edit(f: impl FnOnce(&mut Document) -> crate::Result<()>) -> crate::Result<()> {
     let path = expect_file_path()?;
-    log::debug!("Reading config from `{}`", path.display());
+    let mut file = fs_err::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)?;
+
+    // Hold a lock for the whole read-modify-write, so that concurrent edits (e.g. from several
+    // `mgit clone --alias` commands) are applied one after another instead of overwriting each other.
+    log::debug!("Locking config file `{}`", path.display());
+    file.file().lock_exclusive().map_err(|err| {
+        crate::Error::with_context(
+            err,
+            format!("failed to lock config file `{}`", path.display()),
+        )
+    })?;
 
-    let file = fs_err::read_to_string(&path)?;
-    let mut document = file.parse::<Document>()?;
+    log::debug!("Reading config from `{}`", path.display());
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let mut document = text.parse::<Document>()?;
 
     f(&mut document)?;
 
     log::debug!("Writing config to `{}`", path.display());
-    fs_err::write(path, document.to_string())?;
+    let _guard = interrupt::defer_exit();
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(document.to_string().as_bytes())?;
     Ok(())
 }
 
@@ -115,14 +303,36 @@ impl Config {
         path.strip_prefix(&self.root).unwrap_or(path)
     }
 
+    /// Get the path to show the user for `path`. This should not be used to look up settings or aliases.
+    pub fn get_display_path(&self, path: &Path) -> PathBuf {
+        match self.relative_to {
+            RelativeTo::Root => self.get_relative_path(path).to_owned(),
+            RelativeTo::Cwd => match env::current_dir() {
+                Ok(cwd) => relative_path_from(path, &cwd),
+                Err(_) => path.to_owned(),
+            },
+            RelativeTo::Absolute => path.to_owned(),
+        }
+    }
+
     fn default() -> crate::Result<Config> {
         let Settings {
             default_branch,
             default_remote,
+            remote_priority,
             ssh,
             editor,
             ignore,
             prune,
+            fetch_tags,
+            autostash,
+            ff_only,
+            credential_fill,
+            detect_default_branch,
+            read_only,
+            describe_all_tags,
+            env,
+            unset: _,
         } = Default::default();
 
         Ok(Config {
@@ -130,14 +340,34 @@ impl Config {
                 crate::Error::with_context(err, "failed to get current directory")
             })?,
             default_shell: Shell::default(),
+            log_dir: None,
+            log_retention_days: None,
+            clean_glyph: None,
+            glyphs: GlyphSettings::default(),
+            date_format: None,
+            discovery_cache: None,
+            concurrency_per_remote: None,
+            relative_to: RelativeTo::default(),
+            refresh_cache: false,
             aliases: BTreeMap::new(),
+            concurrency_per_host: BTreeMap::new(),
             settings: SettingsMatcher::default(),
+            profiles: BTreeMap::new(),
             default_branch,
             default_remote,
+            remote_priority,
             ssh,
             editor,
             ignore,
             prune,
+            fetch_tags,
+            autostash,
+            ff_only,
+            credential_fill,
+            detect_default_branch,
+            read_only,
+            describe_all_tags,
+            env,
         })
     }
 
@@ -145,14 +375,92 @@ impl Config {
         Settings {
             default_branch: self.default_branch.clone(),
             default_remote: self.default_remote.clone(),
+            remote_priority: self.remote_priority.clone(),
             ssh: self.ssh.clone(),
             editor: self.editor.clone(),
             ignore: self.ignore,
             prune: self.prune,
+            fetch_tags: self.fetch_tags,
+            autostash: self.autostash,
+            ff_only: self.ff_only,
+            credential_fill: self.credential_fill,
+            detect_default_branch: self.detect_default_branch,
+            read_only: self.read_only,
+            describe_all_tags: self.describe_all_tags,
+            env: self.env.clone(),
+            unset: Vec::new(),
         }
     }
 
-    fn validate(&self) -> crate::Result<()> {
+    fn apply_profile(&mut self, name: &str) -> crate::Result<()> {
+        let profile = self.profiles.remove(name).ok_or_else(|| {
+            crate::Error::from_message(format!("profile `{}` does not exist", name))
+        })?;
+        log::debug!("applying profile `{}`: {:?}", name, profile);
+
+        if let Some(root) = profile.root {
+            self.root = root;
+        }
+        self.aliases.extend(profile.aliases);
+
+        let mut settings = self.default_settings();
+        settings.merge(&Settings {
+            default_branch: profile.default_branch,
+            default_remote: profile.default_remote,
+            remote_priority: profile.remote_priority,
+            ssh: profile.ssh,
+            editor: profile.editor,
+            ignore: profile.ignore,
+            prune: profile.prune,
+            fetch_tags: profile.fetch_tags,
+            autostash: profile.autostash,
+            ff_only: profile.ff_only,
+            credential_fill: profile.credential_fill,
+            detect_default_branch: profile.detect_default_branch,
+            read_only: profile.read_only,
+            describe_all_tags: profile.describe_all_tags,
+            env: profile.env,
+            unset: profile.unset,
+        });
+
+        let Settings {
+            default_branch,
+            default_remote,
+            remote_priority,
+            ssh,
+            editor,
+            ignore,
+            prune,
+            fetch_tags,
+            autostash,
+            ff_only,
+            credential_fill,
+            detect_default_branch,
+            read_only,
+            describe_all_tags,
+            env,
+            unset: _,
+        } = settings;
+        self.default_branch = default_branch;
+        self.default_remote = default_remote;
+        self.remote_priority = remote_priority;
+        self.ssh = ssh;
+        self.editor = editor;
+        self.ignore = ignore;
+        self.prune = prune;
+        self.fetch_tags = fetch_tags;
+        self.autostash = autostash;
+        self.ff_only = ff_only;
+        self.credential_fill = credential_fill;
+        self.detect_default_branch = detect_default_branch;
+        self.read_only = read_only;
+        self.describe_all_tags = describe_all_tags;
+        self.env = env;
+
+        Ok(())
+    }
+
+    fn validate(&self, mut on_warning: impl FnMut(String)) -> crate::Result<()> {
         if !self.root.exists() {
             return Err(crate::Error::from_message(format!(
                 "root path `{}` is invalid",
@@ -160,12 +468,36 @@ impl Config {
             )));
         }
 
+        for (name, path) in &self.aliases {
+            // Aliases are sorted, so any aliases starting with `name` immediately follow it
+            for (other, _) in self
+                .aliases
+                .range::<str, _>((Bound::Excluded(name.as_str()), Bound::Unbounded))
+                .take_while(|(other, _)| other.starts_with(name.as_str()))
+            {
+                on_warning(format!(
+                    "alias `{}` is a prefix of alias `{}`, so `{}` cannot be abbreviated to `{}`",
+                    name, other, other, name
+                ));
+            }
+
+            if !self.root.join(path).exists() {
+                on_warning(format!(
+                    "alias `{}` points to `{}`, which does not exist",
+                    name,
+                    path.display()
+                ));
+            }
+        }
+
         Ok(())
     }
 }
 
 pub struct SettingsMatcher {
     globs: GlobSet,
+    /// The source of each glob in `globs`, in the same order.
+    patterns: Vec<String>,
     settings: Vec<Settings>,
 }
 
@@ -182,33 +514,115 @@ impl SettingsMatcher {
     }
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub default_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub default_remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_priority: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ssh: Option<SshSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub editor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ignore: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub prune: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_tags: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autostash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ff_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_fill: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect_default_branch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub describe_all_tags: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
+    /// Settings to reset to their default value, overriding any value inherited from less specific
+    /// settings. Values set alongside this are applied after clearing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unset: Vec<SettingName>,
+}
+
+/// The name of a setting, used to clear it with `unset`.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SettingName {
+    DefaultBranch,
+    DefaultRemote,
+    RemotePriority,
+    Ssh,
+    Editor,
+    Ignore,
+    Prune,
+    FetchTags,
+    Autostash,
+    FfOnly,
+    CredentialFill,
+    DetectDefaultBranch,
+    ReadOnly,
+    DescribeAllTags,
+    Env,
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct SshSettings {
+    /// The username to connect as, overriding any username in the remote url.
+    ///
+    /// Host aliases from the SSH config file are not resolved, so this must be set if an alias
+    /// specifies a different user. Defaults to `git` if the remote url has no username.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Never included in output.
+    #[serde(skip_serializing)]
     pub passphrase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub public_key_path: Option<PathBuf>,
-    pub private_key_path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key_path: Option<PathBuf>,
 }
 
 impl Settings {
     fn merge(&mut self, other: &Self) {
+        for name in &other.unset {
+            match name {
+                SettingName::DefaultBranch => self.default_branch = None,
+                SettingName::DefaultRemote => self.default_remote = None,
+                SettingName::RemotePriority => self.remote_priority = None,
+                SettingName::Ssh => self.ssh = None,
+                SettingName::Editor => self.editor = None,
+                SettingName::Ignore => self.ignore = None,
+                SettingName::Prune => self.prune = None,
+                SettingName::FetchTags => self.fetch_tags = None,
+                SettingName::Autostash => self.autostash = None,
+                SettingName::FfOnly => self.ff_only = None,
+                SettingName::CredentialFill => self.credential_fill = None,
+                SettingName::DetectDefaultBranch => self.detect_default_branch = None,
+                SettingName::ReadOnly => self.read_only = None,
+                SettingName::DescribeAllTags => self.describe_all_tags = None,
+                SettingName::Env => self.env = None,
+            }
+        }
+
         if other.default_branch.is_some() {
             self.default_branch.clone_from(&other.default_branch);
         }
         if other.default_remote.is_some() {
             self.default_remote.clone_from(&other.default_remote);
         }
+        if other.remote_priority.is_some() {
+            self.remote_priority.clone_from(&other.remote_priority);
+        }
         if other.ssh.is_some() {
             self.ssh.clone_from(&other.ssh);
         }
@@ -221,13 +635,80 @@ impl Settings {
         if other.prune.is_some() {
             self.prune.clone_from(&other.prune);
         }
+        if other.fetch_tags.is_some() {
+            self.fetch_tags.clone_from(&other.fetch_tags);
+        }
+        if other.autostash.is_some() {
+            self.autostash.clone_from(&other.autostash);
+        }
+        if other.ff_only.is_some() {
+            self.ff_only.clone_from(&other.ff_only);
+        }
+        if other.credential_fill.is_some() {
+            self.credential_fill.clone_from(&other.credential_fill);
+        }
+        if other.detect_default_branch.is_some() {
+            self.detect_default_branch
+                .clone_from(&other.detect_default_branch);
+        }
+        if other.read_only.is_some() {
+            self.read_only.clone_from(&other.read_only);
+        }
+        if other.describe_all_tags.is_some() {
+            self.describe_all_tags.clone_from(&other.describe_all_tags);
+        }
+        // Environment variables are merged, so more specific settings only override the variables
+        // they set
+        if let Some(env) = &other.env {
+            self.env
+                .get_or_insert_with(BTreeMap::new)
+                .extend(env.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
     }
 }
 
+/// Formats the settings which are set as a comma-separated list of `name = value` pairs, with values
+/// written as JSON.
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        let fields = match value.as_object() {
+            Some(fields) if !fields.is_empty() => fields,
+            _ => return write!(f, "(defaults)"),
+        };
+        for (index, (name, value)) in fields.iter().enumerate() {
+            if index != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} = {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Get a path equivalent to `path` relative to `base`, where both are absolute.
+fn relative_path_from(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    while let (Some(a), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+
+    base_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
 impl Default for SettingsMatcher {
     fn default() -> Self {
         SettingsMatcher {
             globs: GlobSet::empty(),
+            patterns: Vec::new(),
             settings: Vec::new(),
         }
     }
@@ -252,15 +733,18 @@ impl<'de> Deserialize<'de> for SettingsMatcher {
                 A: de::MapAccess<'de>,
             {
                 let mut settings = Vec::with_capacity(map.size_hint().unwrap_or(4));
+                let mut patterns = Vec::with_capacity(settings.capacity());
                 let mut globs = GlobSetBuilder::new();
 
                 while let Some((glob, entry)) = map.next_entry::<String, Settings>()? {
                     globs.add(Glob::new(&glob).map_err(de::Error::custom)?);
+                    patterns.push(glob);
                     settings.push(entry);
                 }
 
                 Ok(SettingsMatcher {
                     settings,
+                    patterns,
                     globs: globs.build().map_err(de::Error::custom)?,
                 })
             }
@@ -270,11 +754,106 @@ impl<'de> Deserialize<'de> for SettingsMatcher {
     }
 }
 
+impl Serialize for SettingsMatcher {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.patterns.iter().zip(&self.settings))
+    }
+}
+
 impl fmt::Debug for SettingsMatcher {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("SettingsMatcher")
-            .field("globs", &"GlobSet")
+            .field("globs", &self.patterns)
             .field("settings", &self.settings)
             .finish()
     }
 }
+
+#[test]
+fn test_relative_path_from() {
+    let cases = [
+        ("/a/b/c", "/a/b", "c"),
+        ("/a/b", "/a/b", ""),
+        ("/a/b", "/a/b/c", ".."),
+        ("/a/x/y", "/a/b/c", "../../x/y"),
+    ];
+
+    for (path, base, expected) in cases {
+        assert_eq!(
+            relative_path_from(Path::new(path), Path::new(base)),
+            Path::new(expected)
+        );
+    }
+}
+
+#[test]
+fn test_settings_unset() {
+    let config: Config = toml::from_str(
+        r#"
+            root = "/"
+            prune = true
+            editor = "code"
+
+            [settings]
+            "vendor/*" = { unset = ["prune", "editor"] }
+            "vendor/special" = { editor = "vim" }
+        "#,
+    )
+    .unwrap();
+
+    let settings = config.settings("work/repo");
+    assert_eq!(settings.prune, Some(true));
+    assert_eq!(settings.editor.as_deref(), Some("code"));
+
+    let settings = config.settings("vendor/repo");
+    assert_eq!(settings.prune, None);
+    assert_eq!(settings.editor, None);
+
+    let settings = config.settings("vendor/special");
+    assert_eq!(settings.prune, None);
+    assert_eq!(settings.editor.as_deref(), Some("vim"));
+
+    assert!(toml::from_str::<Settings>(r#"unset = ["unknown"]"#).is_err());
+}
+
+#[test]
+fn test_settings_env() {
+    let config: Config = toml::from_str(
+        r#"
+            root = "/"
+            env = { A = "default", B = "default" }
+
+            [settings]
+            "work/*" = { env = { B = "work", C = "work" } }
+        "#,
+    )
+    .unwrap();
+
+    let env = |path| {
+        config
+            .settings(path)
+            .env
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        env("other"),
+        [
+            ("A".to_owned(), "default".to_owned()),
+            ("B".to_owned(), "default".to_owned()),
+        ]
+    );
+    assert_eq!(
+        env("work/repo"),
+        [
+            ("A".to_owned(), "default".to_owned()),
+            ("B".to_owned(), "work".to_owned()),
+            ("C".to_owned(), "work".to_owned()),
+        ]
+    );
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Get the path of `name` in the directory where state shared between commands is kept.
+pub fn path(name: &str) -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join(env!("CARGO_PKG_NAME"))
+        .join(name)
+}
+
+/// Read a JSON file written by [`save`]. If the file is missing or invalid, the default value is
+/// returned instead, with a warning naming the file as `description` if it is invalid.
+pub fn load<T>(file_path: &Path, description: &str) -> T
+where
+    T: DeserializeOwned + Default,
+{
+    match fs::read(file_path) {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(value) => value,
+            Err(err) => {
+                log::warn!(
+                    "ignoring invalid {} `{}`: {}",
+                    description,
+                    file_path.display(),
+                    err
+                );
+                T::default()
+            }
+        },
+        Err(_) => T::default(),
+    }
+}
+
+/// Write `value` to a JSON file, warning with the file named as `description` if it fails.
+pub fn save<T>(file_path: &Path, value: &T, description: &str)
+where
+    T: Serialize,
+{
+    if let Err(err) = write(file_path, value) {
+        log::warn!(
+            "failed to write {} `{}`: {}",
+            description,
+            file_path.display(),
+            err
+        );
+    }
+}
+
+fn write<T>(file_path: &Path, value: &T) -> crate::Result<()>
+where
+    T: Serialize,
+{
+    if let Some(parent) = file_path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+
+    // Write to a temporary file first so concurrent commands never read a partial file
+    let temp_path = file_path.with_extension(format!("json.{}", process::id()));
+    fs_err::write(&temp_path, serde_json::to_vec(value)?)?;
+    fs_err::rename(&temp_path, file_path)?;
+    Ok(())
+}
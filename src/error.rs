@@ -1,5 +1,5 @@
 use std::io::Write;
-use std::{fmt, io};
+use std::{env, fmt, io};
 
 use backtrace::Backtrace;
 use crossterm::style::{Attribute, Color, ResetColor, SetAttribute, SetForegroundColor};
@@ -19,7 +19,7 @@ struct Context {
 }
 
 impl Error {
-    pub fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
+    pub fn write(&self, stdout: &mut crate::output::Writer) -> crossterm::Result<()> {
         crossterm::queue!(
             stdout,
             SetForegroundColor(Color::Red),
@@ -29,10 +29,16 @@ impl Error {
         stdout.flush()?;
         crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
 
-        write!(stdout, "{}", self)?;
+        self.write_plain(stdout)?;
+        Ok(())
+    }
+
+    /// Write this error and its sources on a single line, without any styling.
+    pub fn write_plain(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        write!(writer, "{}", self)?;
         let mut err = self as &dyn std::error::Error;
         while let Some(source) = err.source() {
-            write!(stdout, ": {}", source)?;
+            write!(writer, ": {}", source)?;
             err = source;
         }
         Ok(())
@@ -73,6 +79,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<env::JoinPathsError> for Error {
+    fn from(err: env::JoinPathsError) -> Error {
+        Error { inner: err.into() }
+    }
+}
+
 impl From<fmt::Error> for Error {
     fn from(err: fmt::Error) -> Error {
         Error { inner: err.into() }
@@ -0,0 +1,112 @@
+/// A user-provided template for a line of output, e.g. `{path}: {branch}`.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+impl Template {
+    /// Parse a template, checking that every placeholder is one of `placeholders`.
+    ///
+    /// Literal braces can be written as `{{` and `}}`.
+    pub fn parse(template: &str, placeholders: &[&'static str]) -> crate::Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars.as_str().split_once('}').ok_or_else(|| {
+                        crate::Error::from_message("unterminated placeholder in format string")
+                    })?;
+                    let placeholder = placeholders
+                        .iter()
+                        .find(|&&placeholder| placeholder == name)
+                        .ok_or_else(|| {
+                            crate::Error::from_message(format!(
+                                "unknown placeholder `{{{}}}` in format string (expected one of {})",
+                                name,
+                                placeholders
+                                    .iter()
+                                    .map(|placeholder| format!("`{{{}}}`", placeholder))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ))
+                        })?;
+                    chars = rest.chars();
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => {
+                    return Err(crate::Error::from_message(
+                        "unmatched `}` in format string (use `}}` for a literal brace)",
+                    ))
+                }
+                ch => literal.push(ch),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Render the template, calling `value` to get the value of each placeholder.
+    pub fn render<F>(&self, mut value: F) -> String
+    where
+        F: FnMut(&str) -> String,
+    {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => result.push_str(text),
+                Part::Placeholder(name) => result.push_str(&value(name)),
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn test_parse_template() {
+    let template = Template::parse("{path}: {{{code}}}", &["path", "code"]).unwrap();
+    assert_eq!(
+        template.parts,
+        vec![
+            Part::Placeholder("path"),
+            Part::Literal(": {".to_owned()),
+            Part::Placeholder("code"),
+            Part::Literal("}".to_owned()),
+        ]
+    );
+
+    let rendered = template.render(|name| match name {
+        "path" => "some/repo".to_owned(),
+        "code" => "0".to_owned(),
+        _ => unreachable!(),
+    });
+    assert_eq!(rendered, "some/repo: {0}");
+
+    assert!(Template::parse("{branch}", &["path", "code"]).is_err());
+    assert!(Template::parse("{path", &["path"]).is_err());
+    assert!(Template::parse("path}", &["path"]).is_err());
+}
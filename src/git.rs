@@ -1,13 +1,29 @@
-use std::path::Path;
-use std::{fmt, str};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, str, thread};
 
 use bstr::ByteSlice;
-use serde::Serialize;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
 
-use crate::config::Settings;
+use crate::cli;
+use crate::config::{Settings, SshSettings};
+use crate::interrupt;
+use crate::output;
 
 const HEAD_FILE: &str = "HEAD";
+/// The file in the git directory where the changes made by the last pull are recorded.
+const LAST_PULL_FILE: &str = "MGIT_LAST_PULL";
+/// The prefix of the reflog messages written by multi-git.
+const REFLOG_PREFIX: &str = "multi-git: ";
 const REFS_HEADS_NAMESPACE: &str = "refs/heads/";
+const DEFAULT_SSH_USERNAME: &str = "git";
 
 pub struct Repository {
     repo: git2::Repository,
@@ -19,6 +35,24 @@ pub struct RepositoryStatus {
     pub upstream: UpstreamStatus,
     pub working_tree: WorkingTreeStatus,
     pub default_branch: Option<String>,
+    pub remote_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compare: Option<CompareStatus>,
+    /// The upstream status of every local branch with an upstream, for `status --upstream-all`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branches: Option<Vec<BranchStatus>>,
+    /// The commit time of HEAD, or `None` if HEAD is unborn. Serialized as a Unix timestamp.
+    #[serde(
+        with = "chrono::serde::ts_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_commit_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct BranchStatus {
+    pub name: String,
+    pub upstream: UpstreamStatus,
 }
 
 #[derive(Serialize)]
@@ -33,28 +67,298 @@ pub enum HeadStatusKind {
     Unborn,
     Detached,
     Branch,
+    /// HEAD is a symbolic ref to something other than a local branch, such as a remote branch or
+    /// a tag. The name is the full name of the target ref.
+    SymbolicRef,
 }
 
 #[derive(Serialize)]
 #[serde(tag = "state", rename_all = "snake_case")]
 pub enum UpstreamStatus {
     None,
-    Upstream { ahead: usize, behind: usize },
+    Upstream(AheadBehind),
+    /// There is an upstream, but commits ahead and behind it were not counted because
+    /// `--max-ahead-behind` is 0.
+    Uncounted,
     Gone,
 }
 
+/// How far the head is from the ref passed to `status --compare`.
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CompareStatus {
+    Missing,
+    Compared(AheadBehind),
+    /// Commits ahead and behind the ref were not counted because `--max-ahead-behind` is 0.
+    Uncounted,
+}
+
+/// The number of commits a branch is ahead of and behind another ref.
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct AheadBehind {
+    pub ahead: usize,
+    pub behind: usize,
+    /// Whether counting commits ahead stopped at `--max-ahead-behind`, so there may be more.
+    #[serde(skip_serializing_if = "output::is_false")]
+    pub ahead_capped: bool,
+    /// Whether counting commits behind stopped at `--max-ahead-behind`, so there may be more.
+    #[serde(skip_serializing_if = "output::is_false")]
+    pub behind_capped: bool,
+}
+
+/// The signature on a commit.
+#[derive(Serialize)]
+#[serde(tag = "signature", rename_all = "snake_case")]
+pub enum SignatureStatus {
+    Unsigned,
+    Valid {
+        signer: Option<String>,
+        key: Option<String>,
+        /// Whether the key is trusted, as opposed to only being known to be valid.
+        trusted: bool,
+    },
+    Invalid {
+        reason: &'static str,
+        signer: Option<String>,
+        key: Option<String>,
+    },
+}
+
+/// A local branch whose upstream branch no longer exists.
+#[derive(Serialize)]
+pub struct GoneBranch {
+    pub name: String,
+    pub state: GoneBranchState,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GoneBranchState {
+    /// The branch was not deleted because deletion was not requested.
+    Gone,
+    Deleted,
+    /// The branch has commits which are not in the default branch, so it was kept.
+    Unmerged,
+    /// The branch is checked out, so it was kept.
+    CheckedOut,
+    /// The branch is the default branch, so it was kept.
+    Default,
+}
+
 #[derive(Serialize)]
 pub struct WorkingTreeStatus {
     pub working_changed: bool,
     pub index_changed: bool,
+    pub conflicted: bool,
+}
+
+#[derive(Default)]
+pub struct StatusOptions {
+    pub max_ahead_behind: Option<usize>,
+    pub compare: Option<String>,
+    /// Don't connect to the default remote to find its default branch.
+    pub offline: bool,
+    /// Get the upstream status of every local branch, not just the current one.
+    pub upstream_all: bool,
+}
+
+pub struct PullOptions<'a> {
+    pub switch: bool,
+    pub default_branch_check: bool,
+    pub no_tags: bool,
+    pub autostash: bool,
+    pub network_limit: &'a NetworkLimit,
+    /// A time after which network operations are cancelled.
+    pub deadline: Option<Instant>,
+    /// How much history to fetch for shallow clones.
+    pub depth: Option<FetchDepth>,
+    /// Whether to fail rather than create a merge commit when the branch has diverged from its
+    /// upstream. If not set, the `ff-only` setting is used.
+    pub ff_only: Option<bool>,
+}
+
+impl<'a> PullOptions<'a> {
+    /// Whether uncommitted changes are stashed around updating the branch, from `--autostash` or
+    /// the `autostash` setting.
+    fn autostash(&self, settings: &Settings) -> bool {
+        self.autostash || settings.autostash == Some(true)
+    }
+}
+
+pub struct CloneOptions<'a> {
+    pub filter: Option<&'a CloneFilter>,
+    /// Create a bare repo whose refs all mirror those of the remote, like `git clone --mirror`.
+    pub mirror: bool,
+    pub network_limit: &'a NetworkLimit,
+    /// A time after which network operations are cancelled.
+    pub deadline: Option<Instant>,
+}
+
+/// The amount of history to fetch, for deepening a shallow clone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FetchDepth {
+    /// Fetch at most this many commits from the tip of each branch.
+    Depth(u32),
+    /// Fetch all history, converting a shallow clone to a complete one.
+    Unshallow,
+}
+
+/// A filter for a partial clone, in the same format as `git clone --filter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CloneFilter {
+    /// `blob:none`: omit all blobs.
+    BlobNone,
+    /// `blob:limit=<size>`: omit blobs larger than the given number of bytes, with an optional `k`,
+    /// `m` or `g` suffix.
+    BlobLimit(u64),
+    /// `tree:<depth>`: omit trees and blobs deeper than the given depth.
+    Tree(u64),
+}
+
+/// A progress update from a clone, which first downloads objects and then checks out the working tree.
+pub enum CloneProgress<'a> {
+    Transfer(git2::Progress<'a>),
+    Checkout { completed: usize, total: usize },
+}
+
+/// Caps the number of network operations which may run at once, independently of the number of
+/// threads, both in total and against each remote host.
+pub struct NetworkLimit {
+    available: Mutex<Option<usize>>,
+    released: Condvar,
+    /// The limit for hosts without their own entry in `host_limits`, or `None` for no limit.
+    per_host: Option<usize>,
+    /// Limits for specific hosts, keyed by lowercase host name. Zero means no limit.
+    host_limits: BTreeMap<String, usize>,
+    /// The number of operations running against each host with a limit.
+    host_running: Mutex<HashMap<String, usize>>,
+    host_released: Condvar,
+}
+
+struct NetworkPermit<'a> {
+    limit: &'a NetworkLimit,
+    /// The host this operation counts against, if it has a limit.
+    host: Option<String>,
+}
+
+pub struct CommitOptions<'a> {
+    pub pathspecs: &'a [String],
+    pub sign_off: bool,
 }
 
 #[derive(Serialize)]
-#[serde(tag = "state", content = "branch", rename_all = "snake_case")]
+#[serde(tag = "state", content = "oid", rename_all = "snake_case")]
+pub enum CommitOutcome {
+    NothingToCommit,
+    Committed(String),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SetUpstreamOutcome {
+    AlreadySet,
+    NoRemoteBranch,
+    Updated {
+        upstream: String,
+    },
+    /// HEAD is not on a branch, so there is nothing to set the upstream of.
+    Skipped {
+        reason: &'static str,
+    },
+}
+
 pub enum PullOutcome {
     UpToDate(String),
     CreatedUnborn(String),
     FastForwarded(String),
+    /// The branch had diverged from its upstream, and a merge commit was created, bringing in
+    /// `commits` new commits.
+    Merged {
+        branch: String,
+        commits: usize,
+    },
+}
+
+impl PullOutcome {
+    pub fn branch(&self) -> &str {
+        match self {
+            PullOutcome::UpToDate(branch)
+            | PullOutcome::CreatedUnborn(branch)
+            | PullOutcome::FastForwarded(branch)
+            | PullOutcome::Merged { branch, .. } => branch,
+        }
+    }
+
+    /// The name of the outcome, used in JSON and porcelain output.
+    pub fn state(&self) -> &'static str {
+        match self {
+            PullOutcome::UpToDate(_) => "up_to_date",
+            PullOutcome::CreatedUnborn(_) => "created_unborn",
+            PullOutcome::FastForwarded(_) => "fast_forwarded",
+            PullOutcome::Merged { .. } => "merged",
+        }
+    }
+}
+
+/// How the current branch is updated when uncommitted changes are stashed around it.
+#[derive(Debug, Copy, Clone)]
+enum AutostashAction {
+    FastForward,
+    Merge,
+    UndoPull,
+}
+
+/// A change made to a repo by `mgit pull`, recorded in its git directory so it can be reverted by
+/// `mgit undo`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PullRecord {
+    /// When the pull started. Every repo changed by the same pull records the same time.
+    pub time: DateTime<Utc>,
+    /// The full name of the branch checked out after the pull.
+    pub branch: String,
+    /// How the pull moved the branch, or `None` if it was already up to date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved: Option<BranchMove>,
+    /// The full name of the branch checked out before the pull, if `--switch` changed it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub switched_from: Option<String>,
+    /// Whether uncommitted changes were stashed while the pull moved the branch, in which case
+    /// they are stashed again while undoing it.
+    #[serde(default, skip_serializing_if = "output::is_false")]
+    pub autostashed: bool,
+}
+
+/// The commits a branch was moved from and to by a pull.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BranchMove {
+    /// The commit the branch pointed to before the pull, or `None` if the pull created it.
+    pub from: Option<String>,
+    pub to: String,
+}
+
+impl Serialize for PullOutcome {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct JsonPullOutcome<'a> {
+            state: &'static str,
+            branch: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            commits: Option<usize>,
+        }
+
+        JsonPullOutcome {
+            state: self.state(),
+            branch: self.branch(),
+            commits: match self {
+                PullOutcome::Merged { commits, .. } => Some(*commits),
+                _ => None,
+            },
+        }
+        .serialize(serializer)
+    }
 }
 
 impl Repository {
@@ -68,15 +372,23 @@ impl Repository {
         path: &Path,
         repo: &str,
         settings: &Settings,
-        mut progress_callback: F,
+        options: &CloneOptions,
+        progress_callback: F,
     ) -> crate::Result<Self>
     where
-        F: FnMut(git2::Progress),
+        F: Fn(CloneProgress),
     {
+        let deadline = options.deadline;
+
+        // libgit2 does not yet support partial clones, so fall back to running `git clone`
+        if let Some(filter) = options.filter {
+            return Repository::clone_with_git(path, repo, options, filter);
+        }
+
         let mut callbacks = git2::RemoteCallbacks::new();
         callbacks.transfer_progress(|progress| {
-            progress_callback(progress);
-            true
+            progress_callback(CloneProgress::Transfer(progress));
+            !cli::deadline_exceeded(deadline)
         });
 
         let mut credentials_state = CredentialsState::default();
@@ -93,14 +405,100 @@ impl Repository {
         let mut fetch_options = git2::FetchOptions::new();
         fetch_options.remote_callbacks(callbacks);
 
-        let repo = git2::build::RepoBuilder::new()
-            .fetch_options(fetch_options)
-            .clone(repo, path)?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.progress(|_, completed, total| {
+            progress_callback(CloneProgress::Checkout { completed, total })
+        });
+
+        let mut builder = git2::build::RepoBuilder::new();
+        builder.fetch_options(fetch_options).with_checkout(checkout);
+        if options.mirror {
+            builder.bare(true).remote_create(|repo, name, url| {
+                let remote = repo.remote_with_fetch(name, url, "+refs/*:refs/*")?;
+                repo.config()?
+                    .set_bool(&format!("remote.{}.mirror", name), true)?;
+                Ok(remote)
+            });
+        }
+
+        let permit = options.network_limit.acquire(Some(repo));
+        let repo = builder
+            .clone(repo, path)
+            .map_err(|err| deadline_error(err, deadline))?;
+        drop(permit);
+
+        // libgit2 records the remote's HEAD as a remote-tracking ref rather than updating HEAD, so
+        // point HEAD at the same branch and remove the ref, like `git clone --mirror`
+        if options.mirror {
+            let remotes = repo.remotes()?;
+            // A new clone has a single remote, which is the one HEAD was recorded for
+            let remote_name = remotes
+                .get(0)
+                .ok_or_else(|| crate::Error::from_message("cloned repo has no remote"))?;
+            match repo.find_reference(&format!("refs/remotes/{}/HEAD", remote_name)) {
+                Ok(mut reference) => {
+                    if let Some(target) = reference.symbolic_target() {
+                        repo.set_head(target)?;
+                    }
+                    reference.delete()?;
+                }
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
 
         log::debug!("cloned repo at `{}`", path.display());
         Ok(Repository { repo })
     }
 
+    /// Clone a repo by running `git clone`, with the given partial clone filter.
+    fn clone_with_git(
+        path: &Path,
+        repo: &str,
+        options: &CloneOptions,
+        filter: &CloneFilter,
+    ) -> crate::Result<Self> {
+        let mut command = Command::new("git");
+        command
+            .arg("clone")
+            .arg("--quiet")
+            .arg(format!("--filter={}", filter));
+        if options.mirror {
+            command.arg("--mirror");
+        }
+        command.arg("--").arg(repo).arg(path);
+
+        let permit = options.network_limit.acquire(Some(repo));
+        run_git(&mut command, options.deadline)
+            .map_err(|err| crate::Error::with_context(err, "failed to clone"))?;
+        drop(permit);
+
+        log::debug!("cloned repo at `{}`", path.display());
+        Repository::open(path)
+    }
+
+    /// Find the working directory of the repo containing `path`.
+    pub fn discover_workdir(path: &Path) -> crate::Result<PathBuf> {
+        let repo = match git2::Repository::discover(path) {
+            Ok(repo) => repo,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                return Err(crate::Error::from_message(format!(
+                    "`{}` is not inside a repo",
+                    path.display()
+                )))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        match repo.workdir() {
+            Some(workdir) => Ok(workdir.components().collect()),
+            None => Err(crate::Error::from_message(format!(
+                "repo at `{}` has no working directory",
+                repo.path().display()
+            ))),
+        }
+    }
+
     pub fn try_open(path: &Path) -> crate::Result<Option<Self>> {
         match git2::Repository::open(path) {
             Ok(repo) => {
@@ -117,15 +515,40 @@ impl Repository {
         }
     }
 
+    /// Whether HEAD points directly to a commit rather than a branch.
+    pub fn is_detached(&self) -> bool {
+        matches!(self.repo.head_detached(), Ok(true))
+    }
+
     pub fn status(
         &self,
         settings: &Settings,
-    ) -> crate::Result<(RepositoryStatus, Option<git2::Remote>)> {
-        let head = self.head_status()?;
-        let upstream = self.upstream_status(&head)?;
+        options: &StatusOptions,
+    ) -> crate::Result<(RepositoryStatus, Option<git2::Remote<'_>>)> {
+        let head = self.head_status(settings)?;
+        let upstream = self.upstream_status(&head, options.max_ahead_behind)?;
         let working_tree = self.working_tree_status()?;
-
-        let (default_branch, remote) = self.try_default_branch(settings);
+        let last_commit_time = self.head_commit_time()?;
+        let branches = if options.upstream_all {
+            Some(self.branches_status(options.max_ahead_behind)?)
+        } else {
+            None
+        };
+        let compare = options
+            .compare
+            .as_deref()
+            .map(|rev| self.compare_status(rev, options.max_ahead_behind))
+            .transpose()?;
+
+        let (default_branch, remote) = if options.offline {
+            (settings.default_branch.clone(), None)
+        } else {
+            self.try_default_branch(settings)
+        };
+        let remote_url = match &remote {
+            Some(remote) => remote.url().map(ToOwned::to_owned),
+            None => self.default_remote_url(settings),
+        };
 
         Ok((
             RepositoryStatus {
@@ -133,12 +556,201 @@ impl Repository {
                 upstream,
                 working_tree,
                 default_branch,
+                remote_url,
+                compare,
+                branches,
+                last_commit_time,
             },
             remote,
         ))
     }
 
-    fn head_status(&self) -> Result<HeadStatus, git2::Error> {
+    /// Get the url of the default remote, if there is one.
+    pub fn default_remote_url(&self, settings: &Settings) -> Option<String> {
+        self.default_remote(settings)
+            .ok()
+            .and_then(|remote| remote.url().map(ToOwned::to_owned))
+    }
+
+    /// Whether this repo has no working tree, such as a mirror.
+    pub fn is_bare(&self) -> bool {
+        self.repo.is_bare()
+    }
+
+    /// Whether this repo is a shallow clone, with incomplete history.
+    pub fn is_shallow(&self) -> bool {
+        self.repo.is_shallow()
+    }
+
+    /// Find the commit named by a branch, tag or (possibly abbreviated) oid, if this repo contains it.
+    pub fn find_revision(&self, rev: &str) -> crate::Result<Option<String>> {
+        let object = match self.repo.find_branch(rev, git2::BranchType::Local) {
+            Ok(branch) => branch.get().peel(git2::ObjectType::Any)?,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                match self.repo.revparse_single(rev) {
+                    Ok(object) => object,
+                    Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let oid = match object.peel_to_commit() {
+            Ok(commit) => commit.id(),
+            Err(_) => object.id(),
+        };
+        Ok(Some(oid.to_string()))
+    }
+
+    /// Check the signature on the commit named by `rev`, returning its oid and signature status.
+    ///
+    /// Signatures are checked by git, so that `gpg.program`, `gpg.format` and the other signing
+    /// settings are respected in the same way as `git verify-commit`.
+    pub fn verify_signature(&self, rev: &str) -> crate::Result<(String, SignatureStatus)> {
+        let oid = self.repo.revparse_single(rev)?.peel_to_commit()?.id();
+
+        // Avoid running git for commits with no signature
+        match self.repo.extract_signature(&oid, None) {
+            Ok(_) => (),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                return Ok((oid.to_string(), SignatureStatus::Unsigned))
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let mut command = Command::new("git");
+        command
+            .arg("--git-dir")
+            .arg(self.repo.path())
+            .arg("show")
+            .arg("--no-patch")
+            .arg("--format=%G?%n%GS%n%GK")
+            .arg(oid.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        log::debug!("spawning command `${:?}`", command);
+
+        let output = command
+            .output()
+            .map_err(|err| crate::Error::with_context(err, "failed to run `git`"))?;
+        if !output.status.success() {
+            return Err(crate::Error::from_message(format!(
+                "failed to verify signature: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut lines = stdout.lines();
+        let code = lines.next().unwrap_or_default();
+        let mut next_field = || {
+            lines
+                .next()
+                .filter(|field| !field.is_empty())
+                .map(ToOwned::to_owned)
+        };
+        let (signer, key) = (next_field(), next_field());
+
+        let invalid = |reason| SignatureStatus::Invalid {
+            reason,
+            signer: signer.clone(),
+            key: key.clone(),
+        };
+        let status = match code {
+            "G" | "U" => SignatureStatus::Valid {
+                signer: signer.clone(),
+                key: key.clone(),
+                trusted: code == "G",
+            },
+            "B" => invalid("bad signature"),
+            "X" => invalid("signature has expired"),
+            "Y" => invalid("signing key has expired"),
+            "R" => invalid("signing key has been revoked"),
+            "E" => invalid("signature cannot be checked"),
+            "N" => SignatureStatus::Unsigned,
+            _ => {
+                return Err(crate::Error::from_message(format!(
+                    "unexpected signature status `{}`",
+                    code
+                )))
+            }
+        };
+
+        Ok((oid.to_string(), status))
+    }
+
+    /// Track the branch of the same name on the default remote, if the current branch has no upstream.
+    pub fn set_upstream(&self, settings: &Settings) -> crate::Result<SetUpstreamOutcome> {
+        let head = self.head_status(settings)?;
+        if head.is_unborn() {
+            return Ok(SetUpstreamOutcome::Skipped {
+                reason: "head is unborn",
+            });
+        } else if head.is_detached() {
+            return Ok(SetUpstreamOutcome::Skipped {
+                reason: "head is detached",
+            });
+        } else if !head.is_branch() {
+            return Ok(SetUpstreamOutcome::Skipped {
+                reason: "head is not on a branch",
+            });
+        }
+
+        let mut branch = self.head_branch()?;
+        match branch.upstream() {
+            Ok(_) => return Ok(SetUpstreamOutcome::AlreadySet),
+            // The upstream is set in the config but no longer exists.
+            Err(err)
+                if err.code() == git2::ErrorCode::NotFound
+                    && err.class() == git2::ErrorClass::Reference =>
+            {
+                return Ok(SetUpstreamOutcome::AlreadySet)
+            }
+            // No upstream is set in the config
+            Err(err)
+                if err.code() == git2::ErrorCode::NotFound
+                    && err.class() == git2::ErrorClass::Config => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let remote = self.default_remote(settings)?;
+        let remote_name = remote
+            .name()
+            .ok_or_else(|| crate::Error::from_message("remote name is invalid utf-8"))?;
+        let upstream_name = format!("{}/{}", remote_name, head.name);
+        match self
+            .repo
+            .find_branch(&upstream_name, git2::BranchType::Remote)
+        {
+            Ok(_) => {}
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                return Ok(SetUpstreamOutcome::NoRemoteBranch)
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        branch.set_upstream(Some(&upstream_name))?;
+        Ok(SetUpstreamOutcome::Updated {
+            upstream: upstream_name,
+        })
+    }
+
+    /// Get the commit time of HEAD, or `None` if HEAD is unborn.
+    pub fn head_commit_time(&self) -> crate::Result<Option<DateTime<Utc>>> {
+        let head = match self.repo.head() {
+            Ok(head) => head,
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let time = head.peel_to_commit()?.time();
+        Ok(Some(Utc.timestamp(time.seconds(), 0)))
+    }
+
+    /// Get the name and kind of HEAD. Detached HEADs are described relative to the nearest tag,
+    /// which may be lightweight unless the `describe-all-tags` setting is disabled.
+    pub fn head_status(&self, settings: &Settings) -> Result<HeadStatus, git2::Error> {
         let head = self.repo.find_reference(HEAD_FILE)?;
         match head.symbolic_target_bytes() {
             // HEAD points to a branch
@@ -161,14 +773,20 @@ impl Repository {
                     Err(err) => Err(err),
                 }
             }
+            // HEAD points to another ref, such as `refs/remotes/origin/main` or `refs/tags/v1`
+            Some(name) => Ok(HeadStatus {
+                name: name.as_bstr().to_string(),
+                kind: HeadStatusKind::SymbolicRef,
+            }),
             // HEAD points to an oid (is detached)
-            _ => {
+            None => {
                 let object = head.peel(git2::ObjectType::Any)?;
-                let description = object.describe(
-                    git2::DescribeOptions::new()
-                        .describe_tags()
-                        .show_commit_oid_as_fallback(true),
-                )?;
+                let mut options = git2::DescribeOptions::new();
+                options.show_commit_oid_as_fallback(true);
+                if settings.describe_all_tags != Some(false) {
+                    options.describe_tags();
+                }
+                let description = object.describe(&options)?;
                 let name = description.format(None)?;
                 Ok(HeadStatus {
                     name,
@@ -178,12 +796,43 @@ impl Repository {
         }
     }
 
-    fn upstream_status(&self, head_status: &HeadStatus) -> Result<UpstreamStatus, git2::Error> {
-        let local_branch = if head_status.is_branch() {
-            self.head_branch()?
+    fn upstream_status(
+        &self,
+        head_status: &HeadStatus,
+        max_ahead_behind: Option<usize>,
+    ) -> Result<UpstreamStatus, git2::Error> {
+        if head_status.is_branch() {
+            self.branch_upstream_status(&self.head_branch()?, max_ahead_behind)
         } else {
-            return Ok(UpstreamStatus::None);
-        };
+            Ok(UpstreamStatus::None)
+        }
+    }
+
+    /// Get the upstream status of each local branch which has an upstream, sorted by name.
+    fn branches_status(
+        &self,
+        max_ahead_behind: Option<usize>,
+    ) -> Result<Vec<BranchStatus>, git2::Error> {
+        let mut result = Vec::new();
+        for branch in self.repo.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            match self.branch_upstream_status(&branch, max_ahead_behind)? {
+                UpstreamStatus::None => {}
+                upstream => result.push(BranchStatus {
+                    name: branch.name_bytes()?.to_str_lossy().into_owned(),
+                    upstream,
+                }),
+            }
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    fn branch_upstream_status(
+        &self,
+        local_branch: &git2::Branch,
+        max_ahead_behind: Option<usize>,
+    ) -> Result<UpstreamStatus, git2::Error> {
         let local_oid = local_branch.get().peel_to_commit()?.id();
 
         let upstream_branch = match local_branch.upstream() {
@@ -204,12 +853,102 @@ impl Repository {
         };
         let upstream_oid = upstream_branch.get().peel_to_commit()?.id();
 
-        let (ahead, behind) = self.repo.graph_ahead_behind(local_oid, upstream_oid)?;
+        Ok(
+            match self.ahead_behind(local_oid, upstream_oid, max_ahead_behind)? {
+                Some(counts) => UpstreamStatus::Upstream(counts),
+                None => UpstreamStatus::Uncounted,
+            },
+        )
+    }
+
+    fn compare_status(
+        &self,
+        rev: &str,
+        max_ahead_behind: Option<usize>,
+    ) -> Result<CompareStatus, git2::Error> {
+        let head_oid = match self.repo.head() {
+            Ok(head) => head.peel_to_commit()?.id(),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => {
+                return Ok(CompareStatus::Missing)
+            }
+            Err(err) => return Err(err),
+        };
+        let compare_oid = match self.repo.revparse_single(rev) {
+            Ok(object) => object.peel_to_commit()?.id(),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                return Ok(CompareStatus::Missing)
+            }
+            Err(err) => return Err(err),
+        };
 
-        Ok(UpstreamStatus::Upstream { ahead, behind })
+        Ok(
+            match self.ahead_behind(head_oid, compare_oid, max_ahead_behind)? {
+                Some(counts) => CompareStatus::Compared(counts),
+                None => CompareStatus::Uncounted,
+            },
+        )
     }
 
-    fn working_tree_status(&self) -> Result<WorkingTreeStatus, git2::Error> {
+    /// Count the commits ahead and behind, or `None` if `max_ahead_behind` is 0.
+    fn ahead_behind(
+        &self,
+        local: git2::Oid,
+        upstream: git2::Oid,
+        max_ahead_behind: Option<usize>,
+    ) -> Result<Option<AheadBehind>, git2::Error> {
+        match max_ahead_behind {
+            Some(0) => Ok(None),
+            Some(limit) => {
+                let (ahead, ahead_capped) = self.count_commits(local, upstream, limit)?;
+                let (behind, behind_capped) = self.count_commits(upstream, local, limit)?;
+                Ok(Some(AheadBehind {
+                    ahead,
+                    behind,
+                    ahead_capped,
+                    behind_capped,
+                }))
+            }
+            None => {
+                let (ahead, behind) = self.repo.graph_ahead_behind(local, upstream)?;
+                Ok(Some(AheadBehind {
+                    ahead,
+                    behind,
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+
+    /// Count the commits reachable from `from` but not from `hide`, stopping at `limit`. Also
+    /// returns whether counting stopped early because there are more than `limit` commits.
+    fn count_commits(
+        &self,
+        from: git2::Oid,
+        hide: git2::Oid,
+        limit: usize,
+    ) -> Result<(usize, bool), git2::Error> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push(from)?;
+        revwalk.hide(hide)?;
+
+        let mut count = 0;
+        for oid in revwalk.by_ref().take(limit) {
+            oid?;
+            count += 1;
+        }
+        Ok((count, revwalk.next().transpose()?.is_some()))
+    }
+
+    pub fn working_tree_status(&self) -> Result<WorkingTreeStatus, git2::Error> {
+        // Bare repos, such as mirrors, have no working tree to change
+        if self.repo.is_bare() {
+            return Ok(WorkingTreeStatus {
+                working_changed: false,
+                index_changed: false,
+                conflicted: false,
+            });
+        }
+
         let statuses = self.repo.statuses(Some(
             git2::StatusOptions::new()
                 .exclude_submodules(true)
@@ -219,6 +958,7 @@ impl Repository {
         let mut result = WorkingTreeStatus {
             working_changed: false,
             index_changed: false,
+            conflicted: false,
         };
 
         let working_changed_mask = git2::Status::WT_NEW
@@ -230,14 +970,14 @@ impl Repository {
             | git2::Status::INDEX_MODIFIED
             | git2::Status::INDEX_DELETED
             | git2::Status::INDEX_RENAMED
-            | git2::Status::INDEX_TYPECHANGE
-            | git2::Status::CONFLICTED;
+            | git2::Status::INDEX_TYPECHANGE;
 
         for entry in statuses.iter() {
             let status = entry.status();
 
             result.working_changed |= status.intersects(working_changed_mask);
             result.index_changed |= status.intersects(index_changed_mask);
+            result.conflicted |= status.is_conflicted();
         }
 
         Ok(result)
@@ -248,15 +988,26 @@ impl Repository {
         settings: &Settings,
         status: &RepositoryStatus,
         remote: Option<git2::Remote>,
-        switch: bool,
+        options: &PullOptions,
         mut progress_callback: F,
     ) -> crate::Result<PullOutcome>
     where
         F: FnMut(git2::Progress),
     {
-        let mut remote = match remote {
-            Some(remote) => remote,
-            None => self.default_remote(settings)?,
+        if self.repo.is_bare() {
+            return Err(crate::Error::from_message(
+                "cannot pull into a bare repo (use `mgit status --fetch` to update it)",
+            ));
+        }
+
+        let mut remote = if options.default_branch_check {
+            match remote {
+                Some(remote) => remote,
+                None => self.default_remote(settings)?,
+            }
+        } else {
+            self.check_upstream(status)?;
+            self.upstream_remote()?
         };
 
         let repo_config = &self.repo.config()?;
@@ -275,48 +1026,665 @@ impl Repository {
 
         fetch_callbacks.transfer_progress(|progress| {
             progress_callback(progress);
-            true
+            !cli::deadline_exceeded(options.deadline)
+        });
+
+        let remote_url = remote.url().map(ToOwned::to_owned);
+        let tags = download_tags(settings, repo_config, remote.name(), options.no_tags);
+
+        let permit = options.network_limit.acquire(remote_url.as_deref());
+
+        let mut remote_connection =
+            remote.connect_auth(git2::Direction::Fetch, Some(connect_callbacks), None)?;
+
+        let branch = if options.default_branch_check {
+            let default_branch = match &status.default_branch {
+                Some(name) => Some(name.clone()),
+                None => self.default_branch_for_remote(remote_connection.remote())?,
+            };
+            if let Some(default_branch) = &default_branch {
+                self.check_default_branch(status, default_branch, options.switch)?;
+            }
+            default_branch
+        } else {
+            Some(status.head.name.clone())
+        };
+
+        // libgit2 cannot fetch into shallow clones, so git is used instead when a depth is given.
+        // Unshallowing a complete repo is an error in git, so it is fetched as normal.
+        let depth = match options.depth {
+            Some(FetchDepth::Unshallow) if !self.repo.is_shallow() => None,
+            depth => depth,
+        };
+        let fetched_with_git = if let Some(depth) = depth {
+            let remote_name = remote_connection
+                .remote()
+                .name()
+                .ok_or_else(|| crate::Error::from_message("remote name is invalid utf-8"))?
+                .to_owned();
+            drop(remote_connection);
+            self.fetch_with_git(settings, &remote_name, depth, options)?;
+            Some(remote_name)
+        } else {
+            remote_connection
+                .remote()
+                .fetch::<String>(
+                    &[],
+                    Some(
+                        git2::FetchOptions::new()
+                            .remote_callbacks(fetch_callbacks)
+                            .download_tags(tags)
+                            .update_fetchhead(true)
+                            .prune(fetch_prune(settings)),
+                    ),
+                    Some("multi-git: fetching"),
+                )
+                .map_err(|err| deadline_error(err, options.deadline))?;
+            // Report the final transfer statistics, which may not have been sent as a progress update
+            progress_callback(remote_connection.remote().stats());
+            None
+        };
+        drop(permit);
+
+        let branch = match branch {
+            Some(branch) => branch,
+            // A newly created remote has no refs at all, so use the name its first branch is
+            // likely to have
+            None if !self.has_fetch_head()? => {
+                let branch = self.initial_branch_name();
+                self.check_default_branch(status, &branch, options.switch)?;
+                branch
+            }
+            None => return Err(crate::Error::from_message("remote has no default branch")),
+        };
+
+        let fetch_head = if options.default_branch_check {
+            let fetch_head = match &fetched_with_git {
+                // git only marks the branch to merge in FETCH_HEAD if it is the upstream of the
+                // current branch, so use the remote-tracking branch instead
+                Some(remote_name) => self.remote_tracking_branch(remote_name, &branch)?,
+                None => self.fetch_head()?,
+            };
+            match fetch_head {
+                Some(fetch_head) => fetch_head,
+                // Neither the local repo nor the remote has any commits yet
+                None if status.head.is_unborn() => return Ok(PullOutcome::CreatedUnborn(branch)),
+                None => return Err(crate::Error::from_message("no branch found to merge")),
+            }
+        } else {
+            let upstream = self.head_branch()?.upstream()?;
+            self.repo.reference_to_annotated_commit(upstream.get())?
+        };
+
+        let merge_analysis = if self.repo.is_shallow() {
+            self.shallow_merge_analysis(&fetch_head)?
+        } else {
+            self.repo.merge_analysis(&[&fetch_head])?.0
+        };
+
+        let autostash = options.autostash(settings);
+        if merge_analysis.is_up_to_date() {
+            Ok(PullOutcome::UpToDate(branch))
+        } else if merge_analysis.is_unborn() {
+            self.create_unborn(status, fetch_head)?;
+            Ok(PullOutcome::CreatedUnborn(branch))
+        } else if merge_analysis.is_fast_forward() {
+            if autostash && status.working_tree.is_dirty() {
+                self.autostash(&branch, AutostashAction::FastForward, || {
+                    Ok(self.fast_forward(fetch_head)?)
+                })?;
+            } else {
+                self.fast_forward(fetch_head)?;
+            }
+            Ok(PullOutcome::FastForwarded(branch))
+        } else if !options.ff_only.or(settings.ff_only).unwrap_or(true) {
+            let merge = || self.merge(&branch, remote_url.as_deref(), &fetch_head);
+            let commits = if !status.working_tree.is_dirty() {
+                merge()?
+            } else if autostash {
+                self.autostash(&branch, AutostashAction::Merge, merge)?
+            } else {
+                return Err(crate::Error::with_context(
+                    crate::Error::from_message("branch has uncommitted changes"),
+                    "cannot merge",
+                ));
+            };
+            Ok(PullOutcome::Merged { branch, commits })
+        } else {
+            Err(self.diverged_error(&branch, &fetch_head))
+        }
+    }
+
+    /// Record the change made by a successful pull so it can be undone, or remove the record of an
+    /// earlier pull if this one changed nothing, so `mgit undo` only reverts the most recent pull.
+    pub fn record_pull(
+        &self,
+        time: DateTime<Utc>,
+        settings: &Settings,
+        options: &PullOptions,
+        before: &RepositoryStatus,
+        outcome: &PullOutcome,
+    ) -> crate::Result<()> {
+        let path = self.repo.path().join(LAST_PULL_FILE);
+        let switched = before.head.is_branch() && !before.head.on_branch(outcome.branch());
+        let moved = !matches!(outcome, PullOutcome::UpToDate(_));
+        if !switched && !moved {
+            return match fs_err::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+
+        let branch = format!("{}{}", REFS_HEADS_NAMESPACE, outcome.branch());
+        let moved = if moved {
+            // The reflog entry written by the pull holds the commit the branch was moved from
+            let reflog = self.repo.reflog(&branch)?;
+            let entry = reflog.get(0).ok_or_else(|| {
+                crate::Error::from_message(format!("no reflog found for `{}`", branch))
+            })?;
+            Some(BranchMove {
+                from: Some(entry.id_old())
+                    .filter(|oid| !oid.is_zero())
+                    .map(|oid| oid.to_string()),
+                to: entry.id_new().to_string(),
+            })
+        } else {
+            None
+        };
+
+        let record = PullRecord {
+            time,
+            branch,
+            moved,
+            switched_from: switched
+                .then(|| format!("{}{}", REFS_HEADS_NAMESPACE, before.head.name)),
+            autostashed: matches!(
+                outcome,
+                PullOutcome::FastForwarded(_) | PullOutcome::Merged { .. }
+            ) && options.autostash(settings)
+                && before.working_tree.is_dirty(),
+        };
+        fs_err::write(&path, serde_json::to_vec(&record)?)?;
+        Ok(())
+    }
+
+    /// Get the change recorded by the last pull which changed this repo, if it has not been undone.
+    pub fn last_pull(&self) -> crate::Result<Option<PullRecord>> {
+        let data = match fs_err::read(self.repo.path().join(LAST_PULL_FILE)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// Revert the change made by a pull, resetting the branch to the commit it pointed to before,
+    /// and switching back to the previous branch if the pull switched away from it.
+    ///
+    /// Fails if the branch has moved since the pull, according to its reflog. Uncommitted changes
+    /// are stashed around the reset only if the pull stashed them too. Otherwise they were made
+    /// after the pull, so it is not undone.
+    pub fn undo_pull(&self, record: &PullRecord) -> crate::Result<()> {
+        let short_name = record
+            .branch
+            .strip_prefix(REFS_HEADS_NAMESPACE)
+            .unwrap_or(&record.branch);
+        let dirty = self.working_tree_status()?.is_dirty();
+        if dirty && !record.autostashed {
+            return Err(crate::Error::from_message(format!(
+                "branch `{}` has uncommitted changes",
+                short_name
+            )));
+        }
+        let head = self.repo.find_reference(HEAD_FILE)?;
+        if head.symbolic_target() != Some(record.branch.as_str()) {
+            return Err(crate::Error::from_message(format!(
+                "branch `{}` is no longer checked out",
+                short_name
+            )));
+        }
+
+        let mut reset = None;
+        if let Some(moved) = &record.moved {
+            let reflog = self.repo.reflog(&record.branch)?;
+            // The branch must still point where the pull left it, with no other reflog entries since
+            let unchanged = match reflog.get(0) {
+                Some(entry) => {
+                    let message = entry.message_bytes().unwrap_or_default();
+                    entry.id_new().to_string() == moved.to
+                        && message.starts_with(REFLOG_PREFIX.as_bytes())
+                }
+                None => false,
+            };
+            let reference = self.repo.find_reference(&record.branch)?;
+            if !unchanged
+                || reference.target().map(|oid| oid.to_string()).as_ref() != Some(&moved.to)
+            {
+                return Err(crate::Error::from_message(format!(
+                    "branch `{}` has changed since it was pulled",
+                    short_name
+                )));
+            }
+            reset = Some((reference, moved));
+        }
+
+        let undo = || -> crate::Result<()> {
+            if let Some((mut reference, moved)) = reset {
+                self.reset_pulled_branch(&mut reference, short_name, moved)?;
+            }
+            if let Some(switched_from) = &record.switched_from {
+                let reference = self.repo.find_reference(switched_from).map_err(|err| {
+                    crate::Error::with_context(
+                        err,
+                        format!(
+                            "failed to switch back to `{}`",
+                            switched_from
+                                .strip_prefix(REFS_HEADS_NAMESPACE)
+                                .unwrap_or(switched_from)
+                        ),
+                    )
+                })?;
+                self.switch(&reference)?;
+            }
+            Ok(())
+        };
+        if dirty {
+            self.autostash(short_name, AutostashAction::UndoPull, undo)?;
+        } else {
+            undo()?;
+        }
+
+        fs_err::remove_file(self.repo.path().join(LAST_PULL_FILE))?;
+        Ok(())
+    }
+
+    /// Move a branch back to the commit it pointed to before a pull, or delete it if the pull
+    /// created it.
+    fn reset_pulled_branch(
+        &self,
+        reference: &mut git2::Reference,
+        short_name: &str,
+        moved: &BranchMove,
+    ) -> crate::Result<()> {
+        match &moved.from {
+            Some(from) => {
+                let oid = git2::Oid::from_str(from)?;
+                self.repo.checkout_tree(
+                    &self.repo.find_object(oid, None)?,
+                    Some(git2::build::CheckoutBuilder::new().safe()),
+                )?;
+                reference.set_target(
+                    oid,
+                    &format!("multi-git: undoing pull of branch {}", short_name),
+                )?;
+            }
+            None => {
+                // The branch was created from an unborn HEAD, so remove its files and make
+                // HEAD unborn again
+                let empty_tree = self.repo.find_tree(self.repo.treebuilder(None)?.write()?)?;
+                self.repo.checkout_tree(
+                    empty_tree.as_object(),
+                    Some(git2::build::CheckoutBuilder::new().safe()),
+                )?;
+                reference.delete()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the local branches whose upstream branch no longer exists. If `delete` is true, branches
+    /// which are fully merged into the default branch are deleted.
+    pub fn gone_branches(
+        &self,
+        settings: &Settings,
+        delete: bool,
+    ) -> crate::Result<Vec<GoneBranch>> {
+        let mut gone = Vec::new();
+        for branch in self.repo.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            match branch.upstream() {
+                Err(err)
+                    if err.code() == git2::ErrorCode::NotFound
+                        && err.class() == git2::ErrorClass::Reference =>
+                {
+                    let name = branch.name_bytes()?.to_str_lossy().into_owned();
+                    gone.push((name, branch));
+                }
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+                Err(err) => return Err(err.into()),
+                Ok(_) => {}
+            }
+        }
+
+        if !delete || gone.is_empty() {
+            return Ok(gone
+                .into_iter()
+                .map(|(name, _)| GoneBranch {
+                    name,
+                    state: GoneBranchState::Gone,
+                })
+                .collect());
+        }
+
+        let default_branch =
+            match self.try_default_branch(settings) {
+                (Some(default_branch), _) => default_branch,
+                (None, _) => return Err(crate::Error::from_message(
+                    "failed to find the default branch (set it with the `default-branch` setting)",
+                )),
+            };
+        let default_oid = self.default_branch_oid(settings, &default_branch)?;
+
+        let mut result = Vec::with_capacity(gone.len());
+        for (name, mut branch) in gone {
+            let state = if branch.is_head() {
+                GoneBranchState::CheckedOut
+            } else if name == default_branch {
+                GoneBranchState::Default
+            } else {
+                let oid = branch.get().peel_to_commit()?.id();
+                if oid == default_oid || self.repo.graph_descendant_of(default_oid, oid)? {
+                    log::debug!("deleting branch `{}`", name);
+                    branch.delete()?;
+                    GoneBranchState::Deleted
+                } else {
+                    GoneBranchState::Unmerged
+                }
+            };
+            result.push(GoneBranch { name, state });
+        }
+
+        Ok(result)
+    }
+
+    /// Get the commit of the default branch, preferring the remote-tracking branch, which may be
+    /// ahead of the local branch.
+    fn default_branch_oid(
+        &self,
+        settings: &Settings,
+        default_branch: &str,
+    ) -> crate::Result<git2::Oid> {
+        if let Ok(remote) = self.default_remote(settings) {
+            if let Some(remote_name) = remote.name() {
+                let name = format!("{}/{}", remote_name, default_branch);
+                if let Ok(branch) = self.repo.find_branch(&name, git2::BranchType::Remote) {
+                    return Ok(branch.get().peel_to_commit()?.id());
+                }
+            }
+        }
+
+        match self
+            .repo
+            .find_branch(default_branch, git2::BranchType::Local)
+        {
+            Ok(branch) => Ok(branch.get().peel_to_commit()?.id()),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => Err(crate::Error::from_message(
+                format!("default branch `{}` not found", default_branch),
+            )),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Fetch from the default remote, updating remote-tracking branches but not the working tree.
+    pub fn fetch(
+        &self,
+        settings: &Settings,
+        network_limit: &NetworkLimit,
+        deadline: Option<Instant>,
+    ) -> crate::Result<()> {
+        let mut remote = self.default_remote(settings)?;
+        let repo_config = &self.repo.config()?;
+
+        let mut callbacks = git2::RemoteCallbacks::new();
+        let mut credentials_state = CredentialsState::default();
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            credentials_state.get(settings, repo_config, url, username_from_url, allowed_types)
         });
+        callbacks.transfer_progress(|_| !cli::deadline_exceeded(deadline));
+
+        let permit = network_limit.acquire(remote.url());
+        remote
+            .fetch::<String>(
+                &[],
+                Some(
+                    git2::FetchOptions::new()
+                        .remote_callbacks(callbacks)
+                        .download_tags(download_tags(settings, repo_config, remote.name(), false))
+                        .prune(fetch_prune(settings)),
+                ),
+                Some("multi-git: fetching"),
+            )
+            .map_err(|err| deadline_error(err, deadline))?;
+        drop(permit);
+
+        Ok(())
+    }
+
+    /// Build the error for a branch which cannot be fast-forwarded, with a hint about how far it has
+    /// diverged. The top-level message is kept the same so it can be matched by scripts.
+    fn diverged_error(&self, branch: &str, fetch_head: &git2::AnnotatedCommit) -> crate::Error {
+        let ahead_behind = self
+            .repo
+            .head()
+            .ok()
+            .and_then(|head| head.target())
+            .and_then(|head| self.repo.graph_ahead_behind(head, fetch_head.id()).ok());
+
+        let hint = match ahead_behind {
+            Some((ahead, behind)) => format!(
+                "branch `{}` has diverged from its upstream ({} ahead, {} behind), rebase or merge it manually",
+                branch, ahead, behind
+            ),
+            None => format!(
+                "branch `{}` has diverged from its upstream, rebase or merge it manually",
+                branch
+            ),
+        };
+        crate::Error::with_context(crate::Error::from_message(hint), "cannot fast-forward")
+    }
+
+    /// Create a merge commit joining the current branch with `fetch_head`, returning the number of
+    /// commits brought in. If the merge has conflicts, nothing is changed.
+    fn merge(
+        &self,
+        branch: &str,
+        remote_url: Option<&str>,
+        fetch_head: &git2::AnnotatedCommit,
+    ) -> crate::Result<usize> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        let theirs = self.repo.find_commit(fetch_head.id())?;
+
+        let mut index = self.repo.merge_commits(&head, &theirs, None)?;
+        if index.has_conflicts() {
+            let paths = index
+                .conflicts()?
+                .filter_map(|conflict| {
+                    let conflict = conflict.ok()?;
+                    let entry = conflict.our.or(conflict.their).or(conflict.ancestor)?;
+                    Some(format!("`{}`", entry.path.as_bstr()))
+                })
+                .collect::<Vec<_>>();
+            return Err(crate::Error::with_context(
+                crate::Error::from_message(format!("conflicts in {}", paths.join(", "))),
+                "cannot merge",
+            ));
+        }
+
+        let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+        let signature = self
+            .repo
+            .signature()
+            .map_err(|err| crate::Error::with_context(err, "failed to get commit signature"))?;
+        let message = match remote_url {
+            Some(url) => format!("Merge branch '{}' of {}", branch, url),
+            None => format!("Merge branch '{}'", branch),
+        };
+        let oid = self.repo.commit(
+            None,
+            &signature,
+            &signature,
+            &message,
+            &tree,
+            &[&head, &theirs],
+        )?;
+
+        self.repo.checkout_tree(
+            tree.as_object(),
+            Some(git2::build::CheckoutBuilder::new().safe()),
+        )?;
+        self.head_branch()?
+            .get_mut()
+            .set_target(oid, &format!("multi-git: merging {}", theirs.id()))?;
+
+        let (commits, _) = self.repo.graph_ahead_behind(theirs.id(), head.id())?;
+        Ok(commits)
+    }
+
+    fn check_default_branch(
+        &self,
+        status: &RepositoryStatus,
+        default_branch: &str,
+        switch: bool,
+    ) -> crate::Result<()> {
+        if !status.head.on_branch(default_branch) {
+            if switch {
+                if status.head.is_detached() {
+                    return Err(crate::Error::from_message(
+                        "will not switch branch while detached",
+                    ));
+                } else if status.head.is_symbolic_ref() {
+                    return Err(crate::Error::from_message(format!(
+                        "will not switch branch while HEAD points to `{}`",
+                        status.head.name
+                    )));
+                } else {
+                    self.switch_branch(default_branch)?;
+                }
+            } else {
+                return Err(crate::Error::from_message("not on default branch"));
+            }
+        }
+
+        Ok(())
+    }
 
-        let prune = match settings.prune {
-            None => git2::FetchPrune::Unspecified,
-            Some(false) => git2::FetchPrune::Off,
-            Some(true) => git2::FetchPrune::On,
+    fn check_upstream(&self, status: &RepositoryStatus) -> crate::Result<()> {
+        if !status.head.is_branch() {
+            return Err(crate::Error::from_message("not on a branch"));
+        }
+
+        match status.upstream {
+            UpstreamStatus::Upstream(_) | UpstreamStatus::Uncounted => Ok(()),
+            UpstreamStatus::None => Err(crate::Error::from_message("no upstream branch")),
+            UpstreamStatus::Gone => Err(crate::Error::from_message("upstream branch is gone")),
+        }
+    }
+
+    /// Equivalent to `merge_analysis` for shallow clones, which libgit2 fails on when it reaches a
+    /// commit whose parents were not fetched.
+    fn shallow_merge_analysis(
+        &self,
+        fetch_head: &git2::AnnotatedCommit,
+    ) -> crate::Result<git2::MergeAnalysis> {
+        let head = match self.repo.head() {
+            Ok(head) => head.peel_to_commit()?.id(),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => {
+                return Ok(git2::MergeAnalysis::ANALYSIS_UNBORN)
+            }
+            Err(err) => return Err(err.into()),
         };
 
-        let mut remote_connection =
-            remote.connect_auth(git2::Direction::Fetch, Some(connect_callbacks), None)?;
+        if self.is_ancestor_in_shallow(fetch_head.id(), head)? {
+            Ok(git2::MergeAnalysis::ANALYSIS_UP_TO_DATE)
+        } else if self.is_ancestor_in_shallow(head, fetch_head.id())? {
+            Ok(git2::MergeAnalysis::ANALYSIS_FASTFORWARD | git2::MergeAnalysis::ANALYSIS_NORMAL)
+        } else {
+            Ok(git2::MergeAnalysis::ANALYSIS_NORMAL)
+        }
+    }
+
+    /// Check whether `ancestor` is reachable from `commit`, stopping at commits whose parents are
+    /// missing from a shallow clone.
+    fn is_ancestor_in_shallow(
+        &self,
+        ancestor: git2::Oid,
+        commit: git2::Oid,
+    ) -> crate::Result<bool> {
+        let mut visited = HashSet::new();
+        let mut queue = vec![commit];
+        while let Some(oid) = queue.pop() {
+            if oid == ancestor {
+                return Ok(true);
+            }
+            if !visited.insert(oid) {
+                continue;
+            }
+            match self.repo.find_commit(oid) {
+                Ok(commit) => queue.extend(commit.parent_ids()),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(false)
+    }
 
-        let default_branch = match &status.default_branch {
-            Some(name) => name.clone(),
-            None => self.default_branch_for_remote(remote_connection.remote())?,
+    /// Fetch from `remote_name` by running `git fetch`, with the given depth.
+    fn fetch_with_git(
+        &self,
+        settings: &Settings,
+        remote_name: &str,
+        depth: FetchDepth,
+        options: &PullOptions,
+    ) -> crate::Result<()> {
+        let mut command = Command::new("git");
+        command.arg("--git-dir").arg(self.repo.path()).arg("fetch");
+        match depth {
+            FetchDepth::Depth(depth) => command.arg(format!("--depth={}", depth)),
+            FetchDepth::Unshallow => command.arg("--unshallow"),
         };
-        if !status.head.on_branch(&default_branch) {
-            if switch {
-                if status.head.is_detached() {
-                    return Err(crate::Error::from_message(
-                        "will not switch branch while detached",
-                    ));
-                } else {
-                    self.switch_branch(&default_branch)?;
+        // git reads `remote.<name>.tagOpt` itself, so tags only need to be passed if set explicitly
+        match (options.no_tags, settings.fetch_tags) {
+            (true, _) | (false, Some(false)) => command.arg("--no-tags"),
+            (false, Some(true)) => command.arg("--tags"),
+            (false, None) => &mut command,
+        };
+        match fetch_prune(settings) {
+            git2::FetchPrune::On => command.arg("--prune"),
+            git2::FetchPrune::Off => command.arg("--no-prune"),
+            git2::FetchPrune::Unspecified => &mut command,
+        };
+        if let Some(ssh_command) = settings.ssh.as_ref().and_then(git_ssh_command) {
+            // A command chosen by the user takes precedence, as it does for `git` itself
+            match custom_ssh_command() {
+                Some(source) => log::warn!(
+                    "`{}` is set, so the `ssh` setting is not used when fetching with `git`",
+                    source
+                ),
+                None => {
+                    command.env("GIT_SSH_COMMAND", ssh_command);
                 }
-            } else {
-                return Err(crate::Error::from_message("not on default branch"));
             }
         }
+        command.arg(remote_name);
 
-        remote_connection.remote().fetch::<String>(
-            &[],
-            Some(
-                git2::FetchOptions::new()
-                    .remote_callbacks(fetch_callbacks)
-                    .download_tags(git2::AutotagOption::All)
-                    .update_fetchhead(true)
-                    .prune(prune),
-            ),
-            Some("multi-git: fetching"),
-        )?;
+        run_git(&mut command, options.deadline)
+            .map_err(|err| crate::Error::with_context(err, "failed to fetch"))
+    }
+
+    fn remote_tracking_branch(
+        &self,
+        remote_name: &str,
+        branch: &str,
+    ) -> crate::Result<Option<git2::AnnotatedCommit<'_>>> {
+        let name = format!("refs/remotes/{}/{}", remote_name, branch);
+        match self.repo.find_reference(&name) {
+            Ok(reference) => Ok(Some(self.repo.reference_to_annotated_commit(&reference)?)),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
 
+    fn fetch_head(&self) -> crate::Result<Option<git2::AnnotatedCommit<'_>>> {
         let mut fetch_head = None;
         self.repo
             .fetchhead_foreach(|ref_name, remote_url, oid, is_merge| {
@@ -331,24 +1699,17 @@ impl Repository {
                     true
                 }
             })?;
-        let fetch_head = match fetch_head {
-            Some(fetch_head) => fetch_head?,
-            None => return Err(crate::Error::from_message("no branch found to merge")),
-        };
-
-        let (merge_analysis, _) = self.repo.merge_analysis(&[&fetch_head])?;
+        Ok(fetch_head.transpose()?)
+    }
 
-        if merge_analysis.is_up_to_date() {
-            Ok(PullOutcome::UpToDate(default_branch))
-        } else if merge_analysis.is_unborn() {
-            self.create_unborn(status, fetch_head)?;
-            Ok(PullOutcome::CreatedUnborn(default_branch))
-        } else if merge_analysis.is_fast_forward() {
-            self.fast_forward(fetch_head)?;
-            Ok(PullOutcome::FastForwarded(default_branch))
-        } else {
-            Err(crate::Error::from_message("cannot fast-forward"))
-        }
+    /// Check whether the last fetch found any refs on the remote.
+    fn has_fetch_head(&self) -> Result<bool, git2::Error> {
+        let mut found = false;
+        self.repo.fetchhead_foreach(|_, _, _, _| {
+            found = true;
+            false
+        })?;
+        Ok(found)
     }
 
     fn create_unborn(
@@ -357,12 +1718,95 @@ impl Repository {
         fetch_commit: git2::AnnotatedCommit,
     ) -> Result<(), git2::Error> {
         debug_assert!(status.head.is_unborn());
-        let commit = self.repo.find_commit(fetch_commit.id())?;
-        let branch = self.repo.branch(&status.head.name, &commit, false)?;
-        self.switch(&branch.into_reference())?;
+        let reference = self.repo.reference(
+            &format!("{}{}", REFS_HEADS_NAMESPACE, status.head.name),
+            fetch_commit.id(),
+            false,
+            &format!(
+                "multi-git: creating branch {} at {}",
+                status.head.name,
+                fetch_commit.id()
+            ),
+        )?;
+        self.switch(&reference)?;
         Ok(())
     }
 
+    /// Update the current branch with `update`, stashing uncommitted changes first and reapplying
+    /// them afterwards. If the changes cannot be reapplied, they are left in the stash.
+    fn autostash<T>(
+        &self,
+        branch: &str,
+        action: AutostashAction,
+        update: impl FnOnce() -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        // Stashing requires a mutable repository, so use a separate handle
+        let mut repo = git2::Repository::open(self.repo.path())?;
+        let signature = match repo.signature() {
+            Ok(signature) => signature,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                git2::Signature::now("multi-git", "multi-git@localhost")?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let (updating, updated, update_name) = match action {
+            AutostashAction::FastForward => ("fast-forwarding", "fast-forwarded", "fast-forward"),
+            AutostashAction::Merge => ("merging into", "merged into", "merge"),
+            AutostashAction::UndoPull => ("undoing pull of", "reset", "undo pull"),
+        };
+        let message = format!("multi-git: autostash before {} {}", updating, branch);
+        match repo.stash_save(&signature, &message, None) {
+            Ok(_) => log::debug!("stashed changes before {} `{}`", updating, branch),
+            // There are only untracked changes, which are left in place
+            Err(err) if err.code() == git2::ErrorCode::NotFound => return update(),
+            Err(err) => {
+                return Err(crate::Error::with_context(err, "failed to stash changes"));
+            }
+        }
+
+        let result = update();
+        // The index was updated through the other handle, so reload it before reapplying changes
+        repo.index()?.read(true)?;
+
+        let mut apply_options = git2::StashApplyOptions::new();
+        apply_options.checkout_options({
+            let mut checkout = git2::build::CheckoutBuilder::new();
+            checkout.safe();
+            checkout
+        });
+        // Apply rather than pop, so the stash is kept if there are conflicts
+        let apply_result = repo
+            .stash_apply(0, Some(&mut apply_options))
+            .map_err(crate::Error::from)
+            .and_then(|()| {
+                if repo.index()?.has_conflicts() {
+                    Err(crate::Error::from_message("stashed changes conflict"))
+                } else {
+                    Ok(repo.stash_drop(0)?)
+                }
+            });
+
+        match (result, apply_result) {
+            (Ok(value), Ok(())) => Ok(value),
+            (Ok(_), Err(err)) => Err(crate::Error::with_context(
+                err,
+                format!(
+                    "{} branch `{}`, but failed to reapply uncommitted changes (they have been left in the stash)",
+                    updated, branch
+                ),
+            )),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(_)) => Err(crate::Error::with_context(
+                err,
+                format!(
+                    "failed to {} (uncommitted changes have been left in the stash)",
+                    update_name
+                ),
+            )),
+        }
+    }
+
     fn fast_forward(&self, fetch_commit: git2::AnnotatedCommit) -> Result<(), git2::Error> {
         let mut branch = self.head_branch()?;
 
@@ -383,6 +1827,86 @@ impl Repository {
         Ok(())
     }
 
+    /// Stage all changes matching `options.pathspecs` (or every change, if there are none) and
+    /// commit them to the current branch.
+    pub fn commit(&self, message: &str, options: &CommitOptions) -> crate::Result<CommitOutcome> {
+        let mut index = self.repo.index()?;
+        if index.has_conflicts() {
+            return Err(crate::Error::from_message(
+                "cannot commit with unresolved conflicts",
+            ));
+        }
+
+        let pathspecs: Vec<&str> = if options.pathspecs.is_empty() {
+            vec!["*"]
+        } else {
+            options.pathspecs.iter().map(String::as_str).collect()
+        };
+        index.add_all(&pathspecs, git2::IndexAddOption::DEFAULT, None)?;
+        index.update_all(&pathspecs, None)?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+
+        let parent = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        let unchanged = match &parent {
+            Some(parent) => parent.tree_id() == tree.id(),
+            None => tree.is_empty(),
+        };
+        if unchanged {
+            return Ok(CommitOutcome::NothingToCommit);
+        }
+
+        let signature = self
+            .repo
+            .signature()
+            .map_err(|err| crate::Error::with_context(err, "failed to get commit signature"))?;
+        let mut message = git2::message_prettify(message, None)?;
+        if message.is_empty() {
+            return Err(crate::Error::from_message("commit message is empty"));
+        }
+        if options.sign_off {
+            message = append_sign_off(&message, &signature)?;
+        }
+
+        let parents: Vec<_> = parent.iter().collect();
+        let buffer = self
+            .repo
+            .commit_create_buffer(&signature, &signature, &message, &tree, &parents)?;
+        let buffer = buffer
+            .as_str()
+            .ok_or_else(|| crate::Error::from_message("commit is invalid utf-8"))?;
+
+        let config = self.repo.config()?;
+        let oid = if config.get_bool("commit.gpgsign").unwrap_or(false) {
+            let gpg_signature = sign_commit(&config, &signature, buffer)?;
+            self.repo.commit_signed(buffer, &gpg_signature, None)?
+        } else {
+            self.repo
+                .odb()?
+                .write(git2::ObjectType::Commit, buffer.as_bytes())?
+        };
+
+        index.write()?;
+
+        let log_message = format!(
+            "multi-git: commit{}: {}",
+            if parent.is_none() { " (initial)" } else { "" },
+            message.lines().next().unwrap_or_default()
+        );
+        let head = self.repo.find_reference(HEAD_FILE)?;
+        match head.symbolic_target() {
+            Some(name) => {
+                self.repo.reference(name, oid, true, &log_message)?;
+            }
+            None => self.repo.set_head_detached(oid)?,
+        }
+
+        Ok(CommitOutcome::Committed(oid.to_string()))
+    }
+
     pub fn create_branch(&self, settings: &Settings, name: &str) -> crate::Result<()> {
         let commit = match &settings.default_branch {
             Some(default_branch) => self
@@ -405,6 +1929,57 @@ impl Repository {
         Ok(())
     }
 
+    /// Create a local branch tracking each remote branch which does not already have one. Returns
+    /// the number of branches created.
+    pub fn track_remote_branches(&self) -> crate::Result<usize> {
+        let mut count = 0;
+        for branch in self.repo.branches(Some(git2::BranchType::Remote))? {
+            let (branch, _) = branch?;
+            let reference = branch.get();
+            // Skip symbolic refs such as `refs/remotes/origin/HEAD`
+            if reference.symbolic_target_bytes().is_some() {
+                continue;
+            }
+
+            let remote_branch_name = match branch.name()? {
+                Some(name) => name,
+                None => continue,
+            };
+            let remote_name = self.repo.branch_remote_name(
+                reference
+                    .name()
+                    .ok_or_else(|| crate::Error::from_message("branch name is invalid UTF-8"))?,
+            )?;
+            let remote_name = remote_name
+                .as_str()
+                .ok_or_else(|| crate::Error::from_message("remote name is invalid UTF-8"))?;
+            let local_name = match remote_branch_name.strip_prefix(remote_name) {
+                Some(name) => name.trim_start_matches('/'),
+                None => continue,
+            };
+
+            if self
+                .repo
+                .find_branch(local_name, git2::BranchType::Local)
+                .is_ok()
+            {
+                continue;
+            }
+
+            let commit = reference.peel_to_commit()?;
+            let mut local = self.repo.branch(local_name, &commit, false)?;
+            local.set_upstream(Some(remote_branch_name))?;
+            log::debug!(
+                "created branch `{}` tracking `{}`",
+                local_name,
+                remote_branch_name
+            );
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     fn switch_branch(&self, branch_name: &str) -> Result<(), git2::Error> {
         let reference = self
             .repo
@@ -430,7 +2005,7 @@ impl Repository {
         Ok(git2::Branch::wrap(head))
     }
 
-    fn default_remote(&self, settings: &Settings) -> Result<git2::Remote, crate::Error> {
+    fn default_remote(&self, settings: &Settings) -> Result<git2::Remote<'_>, crate::Error> {
         let remote_list = self.repo.remotes()?;
         let remote_name = match &settings.default_remote {
             Some(default_branch) => default_branch,
@@ -444,36 +2019,76 @@ impl Repository {
                         ))
                     }
                 },
-                _ => return Err(crate::Error::from_message("no default remote")),
+                // Fall back to the first remote in the priority list which exists in this repo
+                _ => match settings.remote_priority.iter().flatten().find(|name| {
+                    remote_list
+                        .iter()
+                        .any(|remote| remote == Some(name.as_str()))
+                }) {
+                    Some(name) => name,
+                    None => return Err(crate::Error::from_message("no default remote")),
+                },
             },
         };
 
         Ok(self.repo.find_remote(remote_name)?)
     }
 
-    fn default_branch_for_remote(&self, remote: &git2::Remote) -> Result<String, crate::Error> {
+    fn upstream_remote(&self) -> crate::Result<git2::Remote<'_>> {
+        let head = self.repo.head()?;
+        let name = head
+            .name()
+            .ok_or_else(|| crate::Error::from_message("branch name is invalid utf-8"))?;
+        let remote_name = self.repo.branch_upstream_remote(name)?;
+        let remote_name = remote_name
+            .as_str()
+            .ok_or_else(|| crate::Error::from_message("upstream remote name is invalid utf-8"))?;
+
+        Ok(self.repo.find_remote(remote_name)?)
+    }
+
+    /// Get the branch HEAD points to on a connected remote, or `None` if the remote does not
+    /// advertise HEAD.
+    fn default_branch_for_remote(
+        &self,
+        remote: &git2::Remote,
+    ) -> Result<Option<String>, crate::Error> {
         let name = match remote.default_branch() {
             Ok(name) => name,
-            Err(err) if err.code() == git2::ErrorCode::NotFound => {
-                return Err(crate::Error::from_message("remote has no default branch"))
-            }
+            Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
             Err(err) => return Err(err.into()),
         };
         match str::from_utf8(name.as_ref()) {
-            Ok(name) => Ok(name
-                .strip_prefix(REFS_HEADS_NAMESPACE)
-                .unwrap_or(name)
-                .to_owned()),
+            Ok(name) => Ok(Some(
+                name.strip_prefix(REFS_HEADS_NAMESPACE)
+                    .unwrap_or(name)
+                    .to_owned(),
+            )),
             Err(_) => Err(crate::Error::from_message(
                 "default branch name is invalid utf-8",
             )),
         }
     }
 
-    fn try_default_branch(&self, settings: &Settings) -> (Option<String>, Option<git2::Remote>) {
+    /// Get the name of the first branch created in a new repo, from the `init.defaultBranch` config
+    /// value.
+    fn initial_branch_name(&self) -> String {
+        self.repo
+            .config()
+            .and_then(|config| config.get_string("init.defaultBranch"))
+            .unwrap_or_else(|_| "main".to_owned())
+    }
+
+    fn try_default_branch(
+        &self,
+        settings: &Settings,
+    ) -> (Option<String>, Option<git2::Remote<'_>>) {
         if let Some(name) = &settings.default_branch {
             return (Some(name.to_owned()), None);
         }
+        if settings.detect_default_branch == Some(false) {
+            return (None, None);
+        }
 
         self.default_remote(settings)
             .and_then(|mut remote| {
@@ -491,24 +2106,187 @@ impl Repository {
 
                 let _ = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
 
-                let default_branch = self.default_branch_for_remote(&remote)?;
+                let default_branch = self
+                    .default_branch_for_remote(&remote)?
+                    .ok_or_else(|| crate::Error::from_message("remote has no default branch"))?;
                 Ok((Some(default_branch), Some(remote)))
             })
             .unwrap_or((None, None))
     }
 }
 
+impl str::FromStr for CloneFilter {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            crate::Error::from_message(format!(
+                "invalid filter `{}` (expected `blob:none`, `blob:limit=<size>` or `tree:<depth>`)",
+                s
+            ))
+        };
+
+        if s == "blob:none" {
+            Ok(CloneFilter::BlobNone)
+        } else if let Some(limit) = s.strip_prefix("blob:limit=") {
+            let (number, multiplier) = match limit.char_indices().last() {
+                Some((index, 'k')) => (&limit[..index], 1 << 10),
+                Some((index, 'm')) => (&limit[..index], 1 << 20),
+                Some((index, 'g')) => (&limit[..index], 1 << 30),
+                _ => (limit, 1),
+            };
+            let number: u64 = number.parse().map_err(|_| invalid())?;
+            let limit = number.checked_mul(multiplier).ok_or_else(|| {
+                crate::Error::from_message(format!("invalid filter `{}`: limit is too large", s))
+            })?;
+            Ok(CloneFilter::BlobLimit(limit))
+        } else if let Some(depth) = s.strip_prefix("tree:") {
+            Ok(CloneFilter::Tree(depth.parse().map_err(|_| invalid())?))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl fmt::Display for CloneFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloneFilter::BlobNone => write!(f, "blob:none"),
+            CloneFilter::BlobLimit(limit) => write!(f, "blob:limit={}", limit),
+            CloneFilter::Tree(depth) => write!(f, "tree:{}", depth),
+        }
+    }
+}
+
+impl NetworkLimit {
+    /// Create a new limit, where zero means no limit.
+    pub fn new(max: usize) -> Self {
+        NetworkLimit {
+            available: Mutex::new(if max == 0 { None } else { Some(max) }),
+            released: Condvar::new(),
+            per_host: None,
+            host_limits: BTreeMap::new(),
+            host_running: Mutex::new(HashMap::new()),
+            host_released: Condvar::new(),
+        }
+    }
+
+    /// Also limit the operations running against each remote host to `per_host`, or the value in
+    /// `host_limits` for hosts listed there. Zero means no limit.
+    pub fn with_host_limits(
+        mut self,
+        per_host: usize,
+        host_limits: &BTreeMap<String, usize>,
+    ) -> Self {
+        self.per_host = if per_host == 0 { None } else { Some(per_host) };
+        self.host_limits = host_limits
+            .iter()
+            .map(|(host, &limit)| (host.to_ascii_lowercase(), limit))
+            .collect();
+        self
+    }
+
+    fn host_limit(&self, host: &str) -> Option<usize> {
+        match self.host_limits.get(host) {
+            Some(0) => None,
+            Some(&limit) => Some(limit),
+            None => self.per_host,
+        }
+    }
+
+    /// Block until a network operation against `url` is allowed to start.
+    fn acquire(&self, url: Option<&str>) -> NetworkPermit<'_> {
+        // Wait for the host first, so operations queued behind a busy host do not hold up other
+        // hosts by taking permits from the overall limit.
+        let host = url
+            .and_then(remote_host)
+            .map(|host| host.to_ascii_lowercase())
+            .and_then(|host| Some((self.host_limit(&host)?, host)));
+        let host = host.map(|(limit, host)| {
+            let mut running = self.host_running.lock().unwrap();
+            while running.get(&host).copied().unwrap_or(0) >= limit {
+                running = self.host_released.wait(running).unwrap();
+            }
+            *running.entry(host.clone()).or_default() += 1;
+            host
+        });
+
+        let mut available = self.available.lock().unwrap();
+        while *available == Some(0) {
+            available = self.released.wait(available).unwrap();
+        }
+        if let Some(count) = &mut *available {
+            *count -= 1;
+            log::debug!("starting network operation, {} more can start", count);
+        }
+        NetworkPermit { limit: self, host }
+    }
+}
+
+impl<'a> Drop for NetworkPermit<'a> {
+    fn drop(&mut self) {
+        if let Some(count) = &mut *self.limit.available.lock().unwrap() {
+            *count += 1;
+            self.limit.released.notify_one();
+        }
+        if let Some(host) = &self.host {
+            if let Some(count) = self.limit.host_running.lock().unwrap().get_mut(host) {
+                *count -= 1;
+            }
+            // Waiters may be waiting for different hosts, so wake all of them
+            self.limit.host_released.notify_all();
+        }
+    }
+}
+
 impl RepositoryStatus {
+    /// Get the commits ahead and behind the compare ref if one was given, or else the upstream.
+    pub fn ahead_behind(&self) -> Option<AheadBehind> {
+        match (&self.compare, &self.upstream) {
+            (Some(CompareStatus::Compared(counts)), _) => Some(*counts),
+            (Some(CompareStatus::Missing | CompareStatus::Uncounted), _) => None,
+            (None, UpstreamStatus::Upstream(counts)) => Some(*counts),
+            (None, _) => None,
+        }
+    }
+
     pub fn on_default_branch(&self) -> bool {
         match &self.default_branch {
-            Some(name) => self.head.on_branch(&name),
+            Some(name) => self.head.on_branch(name),
             None => false,
         }
     }
+
+    /// Whether the repo has no commits yet, but has changes staged for its first commit.
+    pub fn is_initial_commit_staged(&self) -> bool {
+        self.head.is_unborn() && self.working_tree.index_changed
+    }
+
+    /// Whether the repo has no local changes and is up-to-date with its upstream on the default branch.
+    pub fn is_clean(&self) -> bool {
+        let WorkingTreeStatus {
+            working_changed,
+            index_changed,
+            conflicted,
+        } = self.working_tree;
+
+        !working_changed
+            && !index_changed
+            && !conflicted
+            && matches!(
+                self.upstream,
+                UpstreamStatus::Upstream(AheadBehind {
+                    ahead: 0,
+                    behind: 0,
+                    ..
+                })
+            )
+            && self.on_default_branch()
+    }
 }
 
 impl HeadStatus {
-    fn is_branch(&self) -> bool {
+    pub fn is_branch(&self) -> bool {
         matches!(self.kind, HeadStatusKind::Branch)
     }
 
@@ -520,12 +2298,16 @@ impl HeadStatus {
         matches!(self.kind, HeadStatusKind::Detached)
     }
 
+    fn is_symbolic_ref(&self) -> bool {
+        matches!(self.kind, HeadStatusKind::SymbolicRef)
+    }
+
     pub fn on_branch(&self, name: impl AsRef<[u8]>) -> bool {
         match &self.kind {
             HeadStatusKind::Branch | HeadStatusKind::Unborn => {
                 self.name.as_bytes() == name.as_ref()
             }
-            HeadStatusKind::Detached => false,
+            HeadStatusKind::Detached | HeadStatusKind::SymbolicRef => false,
         }
     }
 }
@@ -535,14 +2317,203 @@ impl fmt::Display for HeadStatus {
         match self.kind {
             HeadStatusKind::Unborn | HeadStatusKind::Branch => write!(f, "{}", self.name),
             HeadStatusKind::Detached => write!(f, "({})", self.name),
+            HeadStatusKind::SymbolicRef => write!(f, "(ref: {})", self.name),
         }
     }
 }
 
 impl WorkingTreeStatus {
     pub fn is_dirty(&self) -> bool {
-        self.index_changed || self.working_changed
+        self.index_changed || self.working_changed || self.conflicted
+    }
+}
+
+/// Whether to prune remote-tracking branches when fetching. If the `prune` setting is not set, this
+/// is left to the repo's `fetch.prune` and `remote.<name>.prune` git config, which both libgit2 and
+/// git read when it is unspecified.
+fn fetch_prune(settings: &Settings) -> git2::FetchPrune {
+    match settings.prune {
+        None => git2::FetchPrune::Unspecified,
+        Some(false) => git2::FetchPrune::Off,
+        Some(true) => git2::FetchPrune::On,
+    }
+}
+
+/// Get the host from a remote url, which may be a full url or an scp-like `user@host:path`.
+pub fn remote_host(url: &str) -> Option<String> {
+    // An scp-like url such as `host.xz:path` also parses as a url with the scheme `host.xz`
+    match Url::parse(url) {
+        Ok(url) if url.has_host() => return url.host_str().map(ToOwned::to_owned),
+        Ok(url) if url.scheme() == "file" => return None,
+        _ => (),
+    }
+
+    let (host, _) = url.split_once(':')?;
+    let host = match host.split_once('@') {
+        Some((_, host)) => host,
+        None => host,
+    };
+    // Exclude local paths, including windows paths with a drive letter such as `C:\path`
+    if host.len() <= 1 || host.contains(['/', '\\']) {
+        None
+    } else {
+        Some(host.to_owned())
+    }
+}
+
+/// Whether to fetch tags. If the `fetch-tags` setting is not set, tags are not fetched from remotes
+/// with `tagOpt = --no-tags` in the repo's git config, like `git fetch`. Otherwise all tags are
+/// fetched.
+fn download_tags(
+    settings: &Settings,
+    repo_config: &git2::Config,
+    remote_name: Option<&str>,
+    no_tags: bool,
+) -> git2::AutotagOption {
+    let tag_opt = match (settings.fetch_tags, remote_name) {
+        (None, Some(remote_name)) => repo_config
+            .get_string(&format!("remote.{}.tagOpt", remote_name))
+            .ok(),
+        _ => None,
+    };
+
+    if no_tags || settings.fetch_tags == Some(false) || tag_opt.as_deref() == Some("--no-tags") {
+        git2::AutotagOption::None
+    } else {
+        git2::AutotagOption::All
+    }
+}
+
+/// Run a `git` command, killing it if it is interrupted or the deadline passes.
+fn run_git(command: &mut Command, deadline: Option<Instant>) -> crate::Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    log::debug!("spawning command `${:?}`", command);
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| crate::Error::with_context(err, "failed to run `git`"))?;
+    loop {
+        if child.try_wait()?.is_some() {
+            break;
+        }
+
+        if cli::deadline_exceeded(deadline) {
+            child.kill()?;
+            child.wait()?;
+            return Err(crate::Error::from_message(if interrupt::is_interrupted() {
+                "interrupted"
+            } else {
+                "deadline exceeded"
+            }));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(crate::Error::from_message(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Add context to an error from a network operation which may have been cancelled because the
+/// deadline passed.
+fn deadline_error(err: git2::Error, deadline: Option<Instant>) -> crate::Error {
+    if interrupt::is_interrupted() {
+        crate::Error::with_context(err, "interrupted")
+    } else if cli::deadline_exceeded(deadline) {
+        crate::Error::with_context(err, "deadline exceeded")
+    } else {
+        err.into()
+    }
+}
+
+/// Append a `Signed-off-by` trailer for `signature` to a commit message, unless it is already present.
+fn append_sign_off(message: &str, signature: &git2::Signature) -> crate::Result<String> {
+    let sign_off = format!(
+        "Signed-off-by: {} <{}>",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes())
+    );
+
+    let message = message.trim_end();
+    let trailers = git2::message_trailers_strs(message)?;
+    if trailers
+        .iter()
+        .any(|(key, value)| sign_off == format!("{}: {}", key, value))
+    {
+        return Ok(format!("{}\n", message));
+    }
+
+    // Add the sign-off to an existing block of trailers, or start a new paragraph
+    let separator = if trailers.len() == 0 { "\n\n" } else { "\n" };
+    Ok(format!("{}{}{}\n", message, separator, sign_off))
+}
+
+/// Sign a commit with `gpg`, in the same way `git commit` does when `commit.gpgsign` is set.
+fn sign_commit(
+    config: &git2::Config,
+    signature: &git2::Signature,
+    buffer: &str,
+) -> crate::Result<String> {
+    match config.get_string("gpg.format") {
+        Ok(format) if format != "openpgp" => {
+            return Err(crate::Error::from_message(format!(
+                "signing commits with `gpg.format = {}` is not supported",
+                format
+            )))
+        }
+        _ => {}
     }
+
+    let program = config
+        .get_string("gpg.openpgp.program")
+        .or_else(|_| config.get_string("gpg.program"))
+        .unwrap_or_else(|_| "gpg".to_owned());
+    let key = config.get_string("user.signingkey").unwrap_or_else(|_| {
+        format!(
+            "{} <{}>",
+            String::from_utf8_lossy(signature.name_bytes()),
+            String::from_utf8_lossy(signature.email_bytes())
+        )
+    });
+
+    let mut command = Command::new(&program);
+    command
+        .arg("--status-fd=2")
+        .arg("-bsau")
+        .arg(&key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    log::debug!("spawning command `${:?}`", command);
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| crate::Error::with_context(err, format!("failed to run `{}`", program)))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(buffer.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(crate::Error::from_message(format!(
+            "failed to sign commit: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| crate::Error::from_message("commit signature is invalid utf-8"))
 }
 
 #[derive(Debug, Default)]
@@ -550,6 +2521,7 @@ struct CredentialsState {
     tried_ssh_key_from_agent: bool,
     tried_ssh_key_from_config: bool,
     ssh_username_requested: bool,
+    tried_credential_fill: bool,
     tried_cred_helper: bool,
 }
 
@@ -562,38 +2534,55 @@ impl CredentialsState {
         username_from_url: Option<&str>,
         allowed_types: git2::CredentialType,
     ) -> Result<git2::Cred, git2::Error> {
+        let ssh_username = ssh_username(settings, username_from_url);
+
         if allowed_types.contains(git2::CredentialType::USERNAME) {
             debug_assert!(username_from_url.is_none());
-            self.ssh_username_requested = true;
+            if !self.ssh_username_requested {
+                self.ssh_username_requested = true;
+                return git2::Cred::username(ssh_username);
+            }
         }
 
         if allowed_types.contains(git2::CredentialType::SSH_KEY) {
-            debug_assert!(!self.ssh_username_requested);
-            let username = username_from_url.unwrap();
-
             if !self.tried_ssh_key_from_config {
                 self.tried_ssh_key_from_config = true;
                 if let Some(ssh) = &settings.ssh {
-                    return git2::Cred::ssh_key(
-                        username,
-                        ssh.public_key_path.as_deref(),
-                        &ssh.private_key_path,
-                        ssh.passphrase.as_deref(),
-                    );
+                    if let Some(private_key_path) = &ssh.private_key_path {
+                        return git2::Cred::ssh_key(
+                            ssh_username,
+                            ssh.public_key_path.as_deref(),
+                            private_key_path,
+                            ssh.passphrase.as_deref(),
+                        );
+                    }
                 }
             }
 
             if !self.tried_ssh_key_from_agent {
                 self.tried_ssh_key_from_agent = true;
-                return git2::Cred::ssh_key_from_agent(username);
+                return git2::Cred::ssh_key_from_agent(ssh_username);
             }
         }
 
-        if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT)
-            && !self.tried_cred_helper
-        {
-            self.tried_cred_helper = true;
-            return git2::Cred::credential_helper(repo_config, url, username_from_url);
+        if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+            if settings.credential_fill == Some(true) && !self.tried_credential_fill {
+                self.tried_credential_fill = true;
+                match credential_fill(url, username_from_url) {
+                    Ok((username, password)) => {
+                        return git2::Cred::userpass_plaintext(&username, &password)
+                    }
+                    Err(err) => log::warn!(
+                        "falling back to credential helpers found by libgit2: {}",
+                        err
+                    ),
+                }
+            }
+
+            if !self.tried_cred_helper {
+                self.tried_cred_helper = true;
+                return git2::Cred::credential_helper(repo_config, url, username_from_url);
+            }
         }
 
         if allowed_types.contains(git2::CredentialType::DEFAULT) {
@@ -603,3 +2592,320 @@ impl CredentialsState {
         Err(git2::Error::from_str("no credentials found"))
     }
 }
+
+/// Get a username and password for `url` by running `git credential fill`, which asks the credential
+/// helpers configured for git. Prompting is disabled, since the terminal is used for output.
+fn credential_fill(url: &str, username: Option<&str>) -> crate::Result<(String, String)> {
+    let mut input = format!("url={}\n", url);
+    if let Some(username) = username {
+        input.push_str(&format!("username={}\n", username));
+    }
+    input.push('\n');
+
+    let mut command = Command::new("git");
+    command
+        .arg("credential")
+        .arg("fill")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    log::debug!("spawning command `${:?}`", command);
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| crate::Error::with_context(err, "failed to run `git`"))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(crate::Error::from_message(format!(
+            "`git credential fill` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_credentials(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the username and password from the output of `git credential fill`, which is a list of
+/// `key=value` lines.
+fn parse_credentials(output: &str) -> crate::Result<(String, String)> {
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value),
+            Some(("password", value)) => password = Some(value),
+            _ => (),
+        }
+    }
+
+    match (username, password) {
+        (Some(username), Some(password)) => Ok((username.to_owned(), password.to_owned())),
+        _ => Err(crate::Error::from_message(
+            "`git credential fill` did not return a username and password",
+        )),
+    }
+}
+
+/// Get the name of the environment variable or git config key that sets a custom SSH command, if
+/// any. libgit2 always uses its built-in SSH transport, so these are only used by operations which
+/// run `git` itself, such as shallow fetches and partial clones.
+pub fn custom_ssh_command() -> Option<&'static str> {
+    for &var in &["GIT_SSH_COMMAND", "GIT_SSH"] {
+        match env::var_os(var) {
+            Some(value) if !value.is_empty() => return Some(var),
+            _ => (),
+        }
+    }
+
+    let config = git2::Config::open_default().ok()?;
+    match config.get_string("core.sshCommand") {
+        Ok(value) if !value.is_empty() => Some("core.sshCommand"),
+        _ => None,
+    }
+}
+
+/// Get an SSH command for `git` which uses the username and key from the `ssh` settings, or `None`
+/// if neither is set. The passphrase cannot be passed to `ssh`, so encrypted keys must be added to
+/// the SSH agent.
+fn git_ssh_command(ssh: &SshSettings) -> Option<String> {
+    fn quote(arg: &str) -> String {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+
+    if ssh.username.is_none() && ssh.private_key_path.is_none() {
+        return None;
+    }
+
+    let mut command = "ssh".to_owned();
+    if let Some(username) = &ssh.username {
+        command.push_str(&format!(" -o User={}", quote(username)));
+    }
+    if let Some(private_key_path) = &ssh.private_key_path {
+        command.push_str(&format!(
+            " -o IdentitiesOnly=yes -i {}",
+            quote(&private_key_path.to_string_lossy())
+        ));
+    }
+    Some(command)
+}
+
+/// Get the username to use for SSH connections.
+fn ssh_username<'a>(settings: &'a Settings, username_from_url: Option<&'a str>) -> &'a str {
+    settings
+        .ssh
+        .as_ref()
+        .and_then(|ssh| ssh.username.as_deref())
+        .or(username_from_url)
+        .unwrap_or(DEFAULT_SSH_USERNAME)
+}
+
+#[test]
+fn test_git_ssh_command() {
+    assert_eq!(git_ssh_command(&SshSettings::default()), None);
+    assert_eq!(
+        git_ssh_command(&SshSettings {
+            username: Some("user".to_owned()),
+            private_key_path: Some(PathBuf::from("/home/user's/.ssh/id_rsa")),
+            ..Default::default()
+        })
+        .as_deref(),
+        Some(r"ssh -o User='user' -o IdentitiesOnly=yes -i '/home/user'\''s/.ssh/id_rsa'")
+    );
+}
+
+#[test]
+fn test_ssh_credentials_without_username() {
+    let repo_config = git2::Config::new().unwrap();
+
+    let mut state = CredentialsState::default();
+    let cred = state
+        .get(
+            &Settings::default(),
+            &repo_config,
+            "ssh://example.com/repo.git",
+            None,
+            git2::CredentialType::USERNAME,
+        )
+        .unwrap();
+    assert_eq!(cred.credtype(), git2::CredentialType::USERNAME.bits());
+
+    let mut state = CredentialsState::default();
+    let cred = state
+        .get(
+            &Settings::default(),
+            &repo_config,
+            "ssh://example.com/repo.git",
+            None,
+            git2::CredentialType::SSH_KEY,
+        )
+        .unwrap();
+    assert_eq!(cred.credtype(), git2::CredentialType::SSH_KEY.bits());
+
+    assert_eq!(ssh_username(&Settings::default(), None), "git");
+    assert_eq!(ssh_username(&Settings::default(), Some("user")), "user");
+
+    let settings = Settings {
+        ssh: Some(SshSettings {
+            username: Some("alias-user".to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert_eq!(ssh_username(&settings, Some("user")), "alias-user");
+}
+
+#[test]
+fn test_parse_credentials() {
+    assert_eq!(
+        parse_credentials("protocol=https\nhost=example.com\nusername=user\npassword=pass=word\n")
+            .unwrap(),
+        ("user".to_owned(), "pass=word".to_owned())
+    );
+    assert!(parse_credentials("protocol=https\nhost=example.com\nusername=user\n").is_err());
+}
+
+#[test]
+fn test_append_sign_off() {
+    let signature = git2::Signature::now("Test User", "test@example.com").unwrap();
+    let sign_off = "Signed-off-by: Test User <test@example.com>";
+
+    assert_eq!(
+        append_sign_off("Subject\n", &signature).unwrap(),
+        format!("Subject\n\n{}\n", sign_off)
+    );
+    assert_eq!(
+        append_sign_off(
+            "Subject\n\nBody\n\nReviewed-by: Someone <someone@example.com>\n",
+            &signature
+        )
+        .unwrap(),
+        format!(
+            "Subject\n\nBody\n\nReviewed-by: Someone <someone@example.com>\n{}\n",
+            sign_off
+        )
+    );
+    assert_eq!(
+        append_sign_off(&format!("Subject\n\n{}\n", sign_off), &signature).unwrap(),
+        format!("Subject\n\n{}\n", sign_off)
+    );
+}
+
+#[test]
+fn test_network_limit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{thread, time::Duration};
+
+    let limit = NetworkLimit::new(2);
+    let running = AtomicUsize::new(0);
+    let max_running = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                let _permit = limit.acquire(None);
+                let count = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(count, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    assert!(max_running.load(Ordering::SeqCst) <= 2);
+    assert_eq!(*limit.available.lock().unwrap(), Some(2));
+}
+
+#[test]
+fn test_network_limit_per_host() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{thread, time::Duration};
+
+    let mut host_limits = BTreeMap::new();
+    host_limits.insert("GitLab.com".to_owned(), 2);
+    host_limits.insert("example.com".to_owned(), 0);
+    let limit = NetworkLimit::new(0).with_host_limits(1, &host_limits);
+    let urls = [
+        "https://github.com/user/repo.git",
+        "git@gitlab.com:user/repo.git",
+        "https://example.com/repo.git",
+        "/path/to/repo",
+    ];
+    let running: Vec<_> = urls.iter().map(|_| AtomicUsize::new(0)).collect();
+    let max_running: Vec<_> = urls.iter().map(|_| AtomicUsize::new(0)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            for (index, url) in urls.iter().enumerate() {
+                let (running, max_running, limit) = (&running[index], &max_running[index], &limit);
+                scope.spawn(move || {
+                    let _permit = limit.acquire(Some(url));
+                    let count = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(count, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        }
+    });
+
+    assert_eq!(max_running[0].load(Ordering::SeqCst), 1);
+    assert!(max_running[1].load(Ordering::SeqCst) <= 2);
+    assert!(limit
+        .host_running
+        .lock()
+        .unwrap()
+        .values()
+        .all(|&count| count == 0));
+    assert_eq!(limit.host_limit("example.com"), None);
+    assert_eq!(limit.host_limit("localhost"), Some(1));
+}
+
+#[test]
+fn test_remote_host() {
+    let cases = vec![
+        ("https://github.com/user/repo.git", Some("github.com")),
+        ("ssh://git@host.xz:2222/path/to/repo.git", Some("host.xz")),
+        ("git@github.com:user/repo.git", Some("github.com")),
+        ("host.xz:path/to/repo.git", Some("host.xz")),
+        ("file:///path/to/repo.git", None),
+        ("/path/to/repo.git", None),
+        ("../repo", None),
+        (r"C:\path\to\repo", None),
+    ];
+
+    for (url, host) in cases {
+        assert_eq!(remote_host(url).as_deref(), host, "{}", url);
+    }
+}
+
+#[test]
+fn test_parse_clone_filter() {
+    assert_eq!(
+        "blob:none".parse::<CloneFilter>().unwrap(),
+        CloneFilter::BlobNone
+    );
+    assert_eq!(
+        "blob:limit=100".parse::<CloneFilter>().unwrap(),
+        CloneFilter::BlobLimit(100)
+    );
+    assert_eq!(
+        "blob:limit=2k".parse::<CloneFilter>().unwrap(),
+        CloneFilter::BlobLimit(2048)
+    );
+    assert_eq!(
+        "tree:0".parse::<CloneFilter>().unwrap(),
+        CloneFilter::Tree(0)
+    );
+    assert!("blob:limit=".parse::<CloneFilter>().is_err());
+    assert!("blob:limit=18446744073709551615k"
+        .parse::<CloneFilter>()
+        .is_err());
+    assert!("tree:".parse::<CloneFilter>().is_err());
+    assert!("sparse:oid=abc".parse::<CloneFilter>().is_err());
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::data_dir;
+
+const FILE_NAME: &str = "exec-history.json";
+
+/// Remembers when each command run by `mgit exec` last succeeded in each repo, so repos which ran
+/// it recently can be skipped with `--skip-if-newer-than`.
+///
+/// Times are recorded separately for each command, so running one command does not cause another to
+/// be skipped.
+#[derive(Debug)]
+pub struct ExecHistory {
+    file_path: PathBuf,
+    /// The command times are looked up and recorded for, as a JSON array of its arguments.
+    command: String,
+    commands: Mutex<BTreeMap<String, BTreeMap<PathBuf, SystemTime>>>,
+}
+
+impl ExecHistory {
+    /// Load the times recorded for the command with arguments `command`.
+    pub fn load(command: &[String]) -> Self {
+        let file_path = data_dir::path(FILE_NAME);
+        let commands = data_dir::load(&file_path, "exec history");
+
+        ExecHistory {
+            file_path,
+            command: serde_json::to_string(command).expect("failed to serialize command"),
+            commands: Mutex::new(commands),
+        }
+    }
+
+    /// Whether the command last succeeded in the repo at `path` less than `window` ago.
+    pub fn ran_within(&self, path: &Path, window: Duration) -> bool {
+        let commands = self.commands.lock().unwrap();
+        let last_run = match commands
+            .get(&self.command)
+            .and_then(|repos| repos.get(path))
+        {
+            Some(last_run) => *last_run,
+            None => return false,
+        };
+
+        match SystemTime::now().duration_since(last_run) {
+            Ok(elapsed) => elapsed < window,
+            // The clock has gone backwards since the command ran, so treat it as recent
+            Err(_) => true,
+        }
+    }
+
+    /// Record that the command just succeeded in the repo at `path`.
+    pub fn record(&self, path: &Path) {
+        self.commands
+            .lock()
+            .unwrap()
+            .entry(self.command.clone())
+            .or_default()
+            .insert(path.to_owned(), SystemTime::now());
+    }
+
+    /// Write the history back to disk.
+    pub fn save(&self) {
+        let commands = self.commands.lock().unwrap();
+        data_dir::save(&self.file_path, &*commands, "exec history");
+    }
+}
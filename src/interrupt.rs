@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use std::{cmp, thread};
+
+/// The exit code used when the command is interrupted, following the shell convention of 128 plus
+/// the signal number of `SIGINT`.
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Held for reading by work which must not be cut short, and for writing by the signal handler
+/// before it exits the process.
+static EXIT_LOCK: RwLock<()> = RwLock::new(());
+
+/// Whether Ctrl-C has been pressed, or the process was asked to terminate. Remaining work should
+/// be cancelled.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Sleep for `duration`, waking early if interrupted. Returns `false` if interrupted.
+pub fn sleep(duration: Duration) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    let end = Instant::now() + duration;
+    loop {
+        if is_interrupted() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= end {
+            return true;
+        }
+        thread::sleep(cmp::min(POLL_INTERVAL, end - now));
+    }
+}
+
+/// Prevent the process from exiting immediately on a signal until the returned guard is dropped,
+/// such as while a file is being rewritten.
+pub fn defer_exit() -> RwLockReadGuard<'static, ()> {
+    EXIT_LOCK.read().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Handle `SIGINT` and `SIGTERM` by cancelling in-flight work, so the command can exit normally
+/// and restore the terminal. If it has not exited after a grace period, or a second signal is
+/// received, the terminal is restored and the process exits immediately, once any work guarded by
+/// [`defer_exit`] has finished.
+#[cfg(unix)]
+pub fn install() {
+    use std::process;
+
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    const GRACE_PERIOD: Duration = Duration::from_secs(3);
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            log::warn!("failed to install signal handler: {}", err);
+            return;
+        }
+    };
+
+    thread::Builder::new()
+        .name("signal-handler".to_owned())
+        .spawn(move || {
+            if signals.forever().next().is_none() {
+                return;
+            }
+            log::info!("interrupted, cancelling remaining work");
+            INTERRUPTED.store(true, Ordering::Relaxed);
+
+            let deadline = Instant::now() + GRACE_PERIOD;
+            while Instant::now() < deadline && signals.pending().next().is_none() {
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            log::warn!("exiting without waiting for remaining work to be cancelled");
+            let _lock = EXIT_LOCK.write().unwrap_or_else(|err| err.into_inner());
+            restore_terminal();
+            process::exit(EXIT_CODE);
+        })
+        .ok();
+}
+
+/// On Windows, raw mode reports Ctrl-C as a key press, so there is nothing to handle.
+#[cfg(windows)]
+pub fn install() {}
+
+/// Turn signal generation from Ctrl-C back on after entering raw mode, which disables it, so that
+/// interactive output can still be interrupted. Raw mode is left as normal by restoring the
+/// original terminal attributes.
+#[cfg(unix)]
+pub fn enable_in_raw_mode() {
+    use std::fs::File;
+    use std::mem::MaybeUninit;
+    use std::os::unix::io::AsRawFd;
+
+    // crossterm changes the attributes of the controlling terminal, even if stdin is redirected
+    let tty = match File::open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => return,
+    };
+
+    unsafe {
+        let mut termios = MaybeUninit::uninit();
+        if libc::tcgetattr(tty.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+            return;
+        }
+        let mut termios = termios.assume_init();
+        termios.c_lflag |= libc::ISIG;
+        libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &termios);
+    }
+}
+
+#[cfg(windows)]
+pub fn enable_in_raw_mode() {}
+
+/// Undo the changes made to the terminal by interactive output, for when the process exits before
+/// they can be undone normally.
+#[cfg(unix)]
+fn restore_terminal() {
+    use std::io;
+
+    use crossterm::{cursor, terminal, tty::IsTty};
+
+    let mut stdout = io::stdout();
+    if stdout.is_tty() {
+        crossterm::execute!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::FromCursorDown),
+            cursor::Show,
+            cursor::EnableBlinking
+        )
+        .ok();
+    }
+    terminal::disable_raw_mode().ok();
+}
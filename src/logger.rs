@@ -2,7 +2,8 @@ use std::{
     env,
     fmt::Arguments,
     io::{self, LineWriter, Write},
-    path::Path,
+    mem,
+    path::{Path, PathBuf},
     sync::Mutex,
     time::Duration,
 };
@@ -12,19 +13,96 @@ use fs_err::File;
 use log::Log;
 use serde::Serialize;
 
-pub fn init() -> io::Result<()> {
-    let logger = Logger::new()?;
+use crate::data_dir;
 
-    log::set_max_level(log::LevelFilter::Trace);
-    log::set_boxed_logger(Box::new(logger)).unwrap();
+pub const LOG_DIR_VAR: &str = "MGIT_LOG_DIR";
+pub const LEVEL_VAR: &str = "MGIT_LOG";
 
+const DEFAULT_RETENTION_DAYS: u64 = 7;
+
+/// Where log records are written. Records are kept in memory until the log file is opened, since
+/// its location is only known once the config file has been parsed.
+enum Sink {
+    Buffer(Vec<u8>),
+    File(LineWriter<File>),
+    Closed,
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink::Buffer(Vec::new()));
+static LOGGER: Logger = Logger;
+
+/// Start logging. Messages are buffered until `open` is called.
+///
+/// The maximum level of messages to log is `debug` if `verbose` is 1 and `trace` if it is more,
+/// otherwise it is taken from the `MGIT_LOG` environment variable, and defaults to `info`. If it
+/// is set to `off`, nothing is logged.
+pub fn init(verbose: u64) -> crate::Result<()> {
+    let level = match (verbose, env::var(LEVEL_VAR)) {
+        (1, _) => log::LevelFilter::Debug,
+        (2.., _) => log::LevelFilter::Trace,
+        (0, Ok(level)) => level.parse::<log::LevelFilter>().map_err(|_| {
+            crate::Error::from_message(format!(
+                "invalid value `{}` for the `{}` environment variable",
+                level, LEVEL_VAR
+            ))
+        })?,
+        (0, Err(_)) => log::LevelFilter::Info,
+    };
+    if level == log::LevelFilter::Off {
+        return Ok(());
+    }
+
+    log::set_max_level(level);
+    log::set_logger(&LOGGER).unwrap();
     Ok(())
 }
 
-struct Logger {
-    file: Mutex<LineWriter<File>>,
+/// Start writing logs to a new file in the log directory, including any messages logged since
+/// `init`, and remove log files older than the retention period. Nothing is written if logging is
+/// off, or `init` failed.
+///
+/// The log directory is taken from the `MGIT_LOG_DIR` environment variable if set, then
+/// `log_dir`, and defaults to a directory in the user's data directory.
+pub fn open(log_dir: Option<&Path>, retention_days: Option<u64>) -> crate::Result<()> {
+    let mut sink = SINK.lock().unwrap();
+    let buffer = match &mut *sink {
+        Sink::Buffer(buffer) if log::max_level() != log::LevelFilter::Off => mem::take(buffer),
+        _ => {
+            *sink = Sink::Closed;
+            return Ok(());
+        }
+    };
+    // If the log file cannot be created, stop buffering messages which will never be written
+    *sink = Sink::Closed;
+
+    let log_dir = match env::var_os(LOG_DIR_VAR) {
+        Some(log_dir) => PathBuf::from(log_dir),
+        None => match log_dir {
+            Some(log_dir) => log_dir.to_owned(),
+            None => data_dir::path("logs"),
+        },
+    };
+    let retention = retention_days
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+        .checked_mul(24 * 60 * 60)
+        .map(Duration::from_secs)
+        .ok_or_else(|| crate::Error::from_message("`log-retention-days` is too large"))?;
+
+    let mut file = create_log_file(&log_dir, retention).map_err(|err| {
+        crate::Error::from_message(format!(
+            "failed to create log file in `{}`: {}",
+            log_dir.display(),
+            err
+        ))
+    })?;
+    file.write_all(&buffer)?;
+    *sink = Sink::File(file);
+
+    Ok(())
 }
 
+struct Logger;
+
 #[derive(Serialize)]
 struct JsonRecord<'a> {
     timestamp: DateTime<Utc>,
@@ -33,33 +111,24 @@ struct JsonRecord<'a> {
     message: &'a Arguments<'a>,
 }
 
-impl Logger {
-    fn new() -> io::Result<Self> {
-        let log_dir = dirs::data_dir()
-            .unwrap_or_else(env::temp_dir)
-            .join(env!("CARGO_PKG_NAME"))
-            .join("logs");
-
-        fs_err::create_dir_all(&log_dir)?;
-        clean_log_dir(&log_dir)?;
-
-        Ok(Logger {
-            file: Mutex::new(LineWriter::new(File::create(log_dir.join(format!(
-                "{}-{}.log",
-                env!("CARGO_PKG_NAME"),
-                Utc::now().format("%Y%m%d-%H%M%S")
-            )))?)),
-        })
-    }
+fn create_log_file(log_dir: &Path, retention: Duration) -> io::Result<LineWriter<File>> {
+    fs_err::create_dir_all(log_dir)?;
+    clean_log_dir(log_dir, retention)?;
+
+    Ok(LineWriter::new(File::create(log_dir.join(format!(
+        "{}-{}.log",
+        env!("CARGO_PKG_NAME"),
+        Utc::now().format("%Y%m%d-%H%M%S")
+    )))?))
 }
 
-fn clean_log_dir(log_dir: &Path) -> io::Result<()> {
+fn clean_log_dir(log_dir: &Path, retention: Duration) -> io::Result<()> {
     for entry in fs_err::read_dir(log_dir)? {
         let entry = entry?;
         let meta = entry.metadata()?;
 
         if meta.is_file()
-            && matches!(meta.modified()?.elapsed(), Ok(elapsed) if elapsed > Duration::from_secs(604800))
+            && matches!(meta.modified()?.elapsed(), Ok(elapsed) if elapsed > retention)
         {
             fs_err::remove_file(entry.path())?;
         }
@@ -75,9 +144,14 @@ impl Log for Logger {
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            let mut file = self.file.lock().unwrap();
+            let mut sink = SINK.lock().unwrap();
+            let writer: &mut dyn Write = match &mut *sink {
+                Sink::Buffer(buffer) => buffer,
+                Sink::File(file) => file,
+                Sink::Closed => return,
+            };
             serde_json::to_writer(
-                &mut *file,
+                &mut *writer,
                 &JsonRecord {
                     timestamp: Utc::now(),
                     level: record.metadata().level(),
@@ -86,11 +160,13 @@ impl Log for Logger {
                 },
             )
             .ok();
-            writeln!(&mut *file).ok();
+            writeln!(writer).ok();
         }
     }
 
     fn flush(&self) {
-        self.file.lock().unwrap().flush().ok();
+        if let Sink::File(file) = &mut *SINK.lock().unwrap() {
+            file.flush().ok();
+        }
     }
 }
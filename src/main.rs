@@ -1,42 +1,130 @@
 mod alias;
+mod cache;
 mod cli;
 mod config;
+mod data_dir;
 mod error;
+mod format;
 mod git;
+mod history;
+mod interrupt;
 mod logger;
 mod output;
+mod predicate;
 mod progress;
+mod pty;
+mod timing;
 mod walk;
 
 pub use crate::error::{Error, Result};
 
+use std::path::Path;
 use std::process;
+use std::time::Instant;
 
 use crate::output::Output;
 
+#[allow(deprecated)]
 fn main() {
     human_panic::setup_panic!();
 
     let args = cli::parse_args();
 
-    logger::init().unwrap();
-    log::trace!("{:?}", args);
-
-    let out = Output::new(args.json);
+    // If the output file cannot be created, the error is written to stdout instead
+    let (file, file_err) = match args.output.as_deref().map(fs_err::File::create).transpose() {
+        Ok(file) => (file.map(|file| file.into_parts().0), None),
+        Err(err) => (None, Some(err)),
+    };
+    let out = Output::new(
+        args.json,
+        args.json_pretty,
+        args.porcelain(),
+        args.keep_order,
+        args.null,
+        file,
+    );
+    if let Some(err) = file_err {
+        out.writeln_error(&Error::with_context(err, "failed to create output file"));
+        drop(out);
+        process::exit(1);
+    }
 
-    if let Err(err) = run(&out, &args) {
+    interrupt::install();
+    let start = Instant::now();
+    let result = run(&out, &args);
+    if args.time {
+        timing::write(&out, start.elapsed());
+    }
+    if interrupt::is_interrupted() {
+        out.writeln_error(&Error::from_message("interrupted"));
+        drop(out);
+        process::exit(interrupt::EXIT_CODE);
+    }
+    if let Err(err) = result {
         out.writeln_error(&err);
+        // Flush any buffered output, since exiting skips destructors
+        drop(out);
         process::exit(1);
     }
 }
 
 fn run(out: &Output, args: &cli::Args) -> Result<()> {
-    let config = config::parse(|ignored_path| {
-        out.writeln_warning(format_args!("unused configuration key: {}", ignored_path))
-    })
-    .map_err(|err| Error::with_context(err, "failed to get config"))?;
+    if let Err(err) = logger::init(args.verbose) {
+        out.writeln_warning(format_args!("failed to initialize logging: {}", err));
+    }
+    log::trace!("{:?}", args);
+    args.check_fail_on()?;
+
+    // The config file may not exist yet, so this command must run before parsing it
+    if let cli::Command::Init(init_args) = &args.command {
+        open_log_file(out, None, None);
+        return cli::init(out, init_args);
+    }
+
+    let config = config::parse(
+        args.profile.as_deref(),
+        |ignored_path| {
+            out.writeln_warning(format_args!("unused configuration key: {}", ignored_path))
+        },
+        |warning| out.writeln_warning(warning),
+    );
+    // The log file location is configured by the config file, so messages logged while parsing it
+    // are buffered until it is opened
+    let mut config = match config {
+        Ok(config) => {
+            open_log_file(out, config.log_dir.as_deref(), config.log_retention_days);
+            config
+        }
+        Err(err) => {
+            open_log_file(out, None, None);
+            return Err(Error::with_context(err, "failed to get config"));
+        }
+    };
+    config.relative_to = args.relative_to;
+    config.refresh_cache = args.refresh_cache;
     log::trace!("{:#?}", config);
 
+    if args.here {
+        if let Some(path) = alias::here_outside_root(&config) {
+            out.writeln_warning(format_args!(
+                "the current repo `{}` is outside the root `{}`",
+                path.display(),
+                config.root.display()
+            ));
+        }
+    }
+
+    if cli::connects_to_remotes(&args.command) {
+        if let Some(source) = git::custom_ssh_command() {
+            out.writeln_warning(format_args!(
+                "`{}` is set, but is only used when running `git` for `pull --depth` or \
+                    `clone --filter`: other SSH connections use keys from the `ssh` setting or \
+                    ssh-agent instead",
+                source
+            ));
+        }
+    }
+
     match &args.command {
         cli::Command::Edit(edit_args) => cli::edit(args, edit_args, &config),
         cli::Command::Status(status_args) => cli::status(out, args, status_args, &config),
@@ -44,5 +132,25 @@ fn run(out: &Output, args: &cli::Args) -> Result<()> {
         cli::Command::Resolve(resolve_args) => cli::resolve(out, args, resolve_args, &config),
         cli::Command::Exec(exec_args) => cli::exec(out, args, exec_args, &config),
         cli::Command::Clone(clone_args) => cli::clone(out, args, clone_args, &config),
+        cli::Command::Fsck(fsck_args) => cli::fsck(out, args, fsck_args, &config),
+        cli::Command::Commit(commit_args) => cli::commit(out, args, commit_args, &config),
+        cli::Command::Whereis(whereis_args) => cli::whereis(out, args, whereis_args, &config),
+        cli::Command::SetUpstream(set_upstream_args) => {
+            cli::set_upstream(out, args, set_upstream_args, &config)
+        }
+        cli::Command::Verify(verify_args) => cli::verify(out, args, verify_args, &config),
+        cli::Command::Gone(gone_args) => cli::gone(out, args, gone_args, &config),
+        cli::Command::Move(move_args) => cli::move_repo(out, args, move_args, &config),
+        cli::Command::Config(config_args) => cli::config(out, args, config_args, &config),
+        cli::Command::Theme(theme_args) => cli::theme(out, args, theme_args, &config),
+        cli::Command::Undo(undo_args) => cli::undo(out, args, undo_args, &config),
+        cli::Command::Init(_) => unreachable!(),
+    }
+}
+
+/// Start writing logs to a file, continuing without it if that fails.
+fn open_log_file(out: &Output, log_dir: Option<&Path>, retention_days: Option<u64>) {
+    if let Err(err) = logger::open(log_dir, retention_days) {
+        out.writeln_warning(format_args!("failed to initialize logging: {}", err));
     }
 }
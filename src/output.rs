@@ -1,23 +1,48 @@
+use std::borrow::Cow;
 use std::cmp;
 use std::fmt::Display;
+use std::fs;
 use std::io::{self, Write as _};
 use std::ops::Range;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use serde::Serialize;
 
+use crate::cli::FailOn;
+use crate::interrupt;
+
 use crossterm::{
     cursor::{self, MoveToColumn, MoveUp},
+    event::{self, Event, KeyCode, KeyModifiers},
     style::{SetAttribute, SetForegroundColor},
 };
 use crossterm::{
     style::{Attribute, Color, ResetColor},
-    terminal,
+    terminal::{self, Clear, ClearType},
+    tty::IsTty as _,
 };
 
+/// The destination for output, which is stdout unless `--output` is passed.
+pub type Writer = Box<dyn io::Write + Send>;
+
 pub struct Output {
-    stdout: io::Stdout,
+    /// Where output is written: stdout, or the file passed with `--output`.
+    writer: Mutex<Writer>,
+    /// Whether output is written to a file, in which case blocks are written once each line
+    /// finishes, without styling.
+    to_file: bool,
     json: bool,
+    /// Whether to write each line in a stable plain text format. Other messages are written to
+    /// stderr.
+    porcelain: bool,
+    /// Whether records are written in the order lines were added, rather than as they finish.
+    keep_order: bool,
+    /// Whether paths and porcelain records are terminated by a NUL character instead of a newline.
+    null: bool,
+    /// When pretty-printing, records are collected and written as a single array on drop.
+    pretty_records: Option<Mutex<Vec<serde_json::Value>>>,
 }
 
 pub struct Block<'out> {
@@ -28,18 +53,88 @@ pub struct Block<'out> {
 struct BlockInner<'out> {
     rows: usize,
     range: Range<usize>,
+    header: Option<Box<dyn LineContent + 'out>>,
     entries: Vec<BlockEntry<'out>>,
+    errors_only: bool,
+    /// Whether lines are shown while they are running, but only kept once finished if they are
+    /// problems.
+    finished_errors_only: bool,
+    has_hidden: bool,
+    written_rows: usize,
+    error_count: usize,
+    cancelled_count: usize,
+    /// Whether records are written in the order of their lines, even without `--keep-order`.
+    keep_order: bool,
+    /// The number of lines at the start of the block which have been written as records, when
+    /// keeping them in order.
+    written_records: usize,
+    /// Whether remaining lines should be cancelled instead of run, after an earlier failure.
+    aborted: bool,
 }
 
 struct BlockEntry<'out> {
     content: Arc<dyn LineContent + 'out>,
     finished: bool,
+    /// Whether the line was finished without running, and so is not shown.
+    cancelled: bool,
 }
 
 /// A single line of output
 pub trait LineContent: Send + Sync {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()>;
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()>;
+    fn write(&self, stdout: &mut Writer) -> crossterm::Result<()>;
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()>;
+
+    /// Write this line in a stable format for parsing by scripts, unaffected by terminal width or
+    /// color. Only called for commands which support porcelain output. Lines which write nothing,
+    /// such as headers, are left out of the output.
+    fn write_porcelain(&self, _: &mut dyn io::Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether this line finished with an error, which causes the command to fail.
+    fn is_error(&self) -> bool {
+        false
+    }
+
+    /// Whether this line represents a problem, which should still be shown when only errors are
+    /// requested.
+    fn is_problem(&self) -> bool {
+        self.is_error()
+    }
+
+    /// Whether this line finished in a state which fails the command when passed to `--fail-on`,
+    /// such as a repo with uncommitted changes. Errors are reported by `is_error` instead.
+    fn matches_fail_on(&self, _: FailOn) -> bool {
+        false
+    }
+
+    /// Whether this line should be left out of the output once it has finished.
+    fn is_hidden(&self) -> bool {
+        false
+    }
+
+    /// The number of terminal rows written by `write`. Lines which write more than one row must
+    /// separate them with `write_row_separator`.
+    fn rows(&self) -> usize {
+        1
+    }
+}
+
+/// Start a new row within a line which takes up several rows of the terminal.
+pub fn write_row_separator(stdout: &mut Writer) -> crossterm::Result<()> {
+    writeln!(stdout)?;
+    crossterm::queue!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+    Ok(())
+}
+
+/// Write a header or footer delimiting output written while a block is suspended, on a row of its
+/// own. See [`Block::suspend`].
+pub fn write_delimiter(stdout: &mut Writer, content: &dyn LineContent) -> crossterm::Result<()> {
+    crossterm::queue!(stdout, MoveToColumn(0))?;
+    content.write(stdout)?;
+    writeln!(stdout)?;
+    crossterm::queue!(stdout, MoveToColumn(0))?;
+    Ok(())
 }
 
 pub struct Line<'out, 'block, C> {
@@ -48,28 +143,176 @@ pub struct Line<'out, 'block, C> {
     content: Arc<C>,
 }
 
+/// Truncate `text` to at most `width` characters, replacing the start with an ellipsis if needed.
+pub fn truncate_start(text: &str, width: usize) -> Cow<'_, str> {
+    let len = text.chars().count();
+    if len <= width {
+        Cow::Borrowed(text)
+    } else if width == 0 {
+        Cow::Borrowed("")
+    } else {
+        let skip = len - (width - 1);
+        Cow::Owned(format!("…{}", text.chars().skip(skip).collect::<String>()))
+    }
+}
+
+/// Get the width of the column repo paths are written in: `max_width` if set and it fits in the
+/// terminal, otherwise half the terminal width.
+pub fn path_column_width(cols: u16, max_width: Option<usize>) -> usize {
+    match max_width {
+        Some(max_width) => cmp::min(max_width, cols as usize),
+        None => cols as usize / 2,
+    }
+}
+
+/// Write `path` padded to the width of the path column, followed by a space. Paths are only
+/// truncated if `max_width` is set, to keep the columns aligned.
+pub fn write_path_column(
+    stdout: &mut dyn io::Write,
+    path: &Path,
+    cols: u16,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let width = path_column_width(cols, max_width);
+    let path = path.display().to_string();
+    if max_width.is_some() {
+        write!(
+            stdout,
+            "{:width$} ",
+            truncate_start(&path, width),
+            width = width
+        )
+    } else {
+        write!(stdout, "{:width$} ", path, width = width)
+    }
+}
+
+/// Write the label of a repo in a tree in place of its path, as with [`write_path_column`]. Only
+/// `name` is truncated, so the lines connecting the repo to its directory in `prefix` are kept.
+pub fn write_tree_column(
+    stdout: &mut dyn io::Write,
+    prefix: &str,
+    name: &str,
+    cols: u16,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let width = path_column_width(cols, max_width).saturating_sub(prefix.chars().count());
+    if max_width.is_some() {
+        write!(
+            stdout,
+            "{}{:width$} ",
+            prefix,
+            truncate_start(name, cmp::max(width, 1)),
+            width = width
+        )
+    } else {
+        write!(stdout, "{}{:width$} ", prefix, name, width = width)
+    }
+}
+
+/// Write the settings applied to a repo after the rest of its line, for `--show-settings`.
+///
+/// The settings can be long, so line wrapping is disabled while writing them and the terminal cuts
+/// them off at the last column, instead of wrapping onto the next row and breaking block redraws.
+pub fn write_settings(stdout: &mut impl io::Write, settings: &str) -> crossterm::Result<()> {
+    crossterm::queue!(
+        stdout,
+        terminal::DisableLineWrap,
+        SetForegroundColor(Color::Grey)
+    )?;
+    write!(stdout, " [{}]", settings)?;
+    crossterm::queue!(stdout, ResetColor, terminal::EnableLineWrap)?;
+    Ok(())
+}
+
+/// Used with `skip_serializing_if` to leave flags out of JSON records unless they are set.
+pub fn is_false(value: &bool) -> bool {
+    !value
+}
+
 impl Output {
-    pub fn new(json: bool) -> Self {
+    pub fn new(
+        json: bool,
+        json_pretty: bool,
+        porcelain: bool,
+        keep_order: bool,
+        null: bool,
+        file: Option<fs::File>,
+    ) -> Self {
+        let json = json || json_pretty;
+        let to_file = file.is_some();
+        let writer: Writer = match file {
+            Some(file) if json || porcelain => Box::new(io::BufWriter::new(file)),
+            Some(file) => Box::new(StripStyle::new(io::BufWriter::new(file))),
+            None => Box::new(io::stdout()),
+        };
+
         Output {
-            stdout: io::stdout(),
+            writer: Mutex::new(writer),
+            to_file,
             json,
+            porcelain,
+            keep_order,
+            null,
+            pretty_records: if json_pretty {
+                Some(Mutex::new(Vec::new()))
+            } else {
+                None
+            },
         }
     }
 
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    pub fn is_porcelain(&self) -> bool {
+        self.porcelain
+    }
+
+    /// Whether blocks are drawn on the terminal and updated as they progress.
+    pub fn is_interactive(&self) -> bool {
+        !self.json && !self.porcelain && !self.to_file
+    }
+
+    /// Whether the user can be asked questions, which requires both interactive output and a
+    /// terminal to read the answer from.
+    pub fn can_prompt(&self) -> bool {
+        self.is_interactive() && io::stdin().is_tty() && io::stdout().is_tty()
+    }
+
     fn writeln<F>(&self, write: F) -> crate::Result<()>
     where
-        F: FnOnce(&mut io::StdoutLock) -> crossterm::Result<()>,
+        F: FnOnce(&mut Writer) -> crossterm::Result<()>,
     {
-        let mut stdout = self.stdout.lock();
+        let mut stdout = self.writer.lock().unwrap();
         write(&mut stdout)?;
         writeln!(stdout)?;
         Ok(())
     }
 
-    fn writeln_json(&self, msg: &impl Serialize) -> io::Result<()> {
-        let mut stdout = self.stdout.lock();
-        serde_json::to_writer(&mut stdout, msg)?;
-        writeln!(stdout)?;
+    /// Write a JSON record. The record should have a `kind` field like other records.
+    pub fn writeln_json(&self, msg: &impl Serialize) -> io::Result<()> {
+        let mut stdout = self.writer.lock().unwrap();
+        self.write_record(&mut stdout, |writer| serde_json::to_writer(writer, msg))
+    }
+
+    /// Write a single JSON record, either as a line of output or buffered for pretty-printing.
+    fn write_record<F>(&self, stdout: &mut Writer, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut dyn io::Write) -> serde_json::Result<()>,
+    {
+        match &self.pretty_records {
+            Some(records) => {
+                let mut buf = Vec::new();
+                write(&mut buf)?;
+                records.lock().unwrap().push(serde_json::from_slice(&buf)?);
+            }
+            None => {
+                write(stdout)?;
+                writeln!(stdout)?;
+            }
+        }
         Ok(())
     }
 
@@ -86,6 +329,8 @@ impl Output {
                 message: msg.to_string(),
             })
             .ok();
+        } else if self.porcelain {
+            eprintln!("{}", msg);
         } else {
             self.writeln(|stdout| {
                 write!(stdout, "{}", msg)?;
@@ -95,7 +340,33 @@ impl Output {
         }
     }
 
+    /// Write a value for parsing by scripts, such as a path, followed by a newline or a NUL
+    /// character.
+    pub fn writeln_value(&self, value: impl Display) {
+        if self.json {
+            return self.writeln_message(value);
+        }
+
+        let mut stdout = self.writer.lock().unwrap();
+        write!(stdout, "{}", value).ok();
+        self.write_terminator(&mut stdout).ok();
+    }
+
+    fn write_terminator(&self, stdout: &mut Writer) -> io::Result<()> {
+        if self.null {
+            stdout.write_all(b"\0")
+        } else {
+            writeln!(stdout)
+        }
+    }
+
     pub fn writeln_warning(&self, msg: impl Display) {
+        // Keep warnings out of the records on stdout, so they can still be parsed
+        if self.json || self.porcelain {
+            eprintln!("warning: {}", msg);
+            return;
+        }
+
         self.writeln(|stdout| {
             crossterm::queue!(
                 stdout,
@@ -113,13 +384,27 @@ impl Output {
     }
 
     pub fn writeln_error(&self, err: &crate::Error) {
-        self.writeln(|stdout| err.write(stdout)).ok();
+        if self.json {
+            self.writeln_json(&JsonError::new(err)).ok();
+        } else if self.porcelain {
+            let mut stderr = io::stderr();
+            write!(stderr, "error: ").ok();
+            err.write_plain(&mut stderr).ok();
+            writeln!(stderr).ok();
+        } else {
+            self.writeln(|stdout| err.write(stdout)).ok();
+        }
     }
 
     pub fn block(&self) -> crate::Result<Block<'_>> {
-        if !self.json {
+        if self.is_interactive() {
             terminal::enable_raw_mode()?;
-            crossterm::queue!(self.stdout.lock(), cursor::Hide, cursor::DisableBlinking)?;
+            interrupt::enable_in_raw_mode();
+            crossterm::queue!(
+                self.writer.lock().unwrap(),
+                cursor::Hide,
+                cursor::DisableBlinking
+            )?;
         }
 
         let (_, rows) = terminal::size()?;
@@ -128,7 +413,17 @@ impl Output {
             output: self,
             inner: Mutex::new(BlockInner {
                 rows: rows as usize,
+                header: None,
                 entries: vec![],
+                errors_only: false,
+                finished_errors_only: false,
+                has_hidden: false,
+                written_rows: 0,
+                error_count: 0,
+                cancelled_count: 0,
+                keep_order: false,
+                written_records: 0,
+                aborted: false,
                 range: 0..0,
             }),
         })
@@ -137,7 +432,12 @@ impl Output {
 
 impl Drop for Output {
     fn drop(&mut self) {
-        self.stdout.flush().ok();
+        if let Some(records) = &self.pretty_records {
+            let mut stdout = self.writer.lock().unwrap();
+            serde_json::to_writer_pretty(&mut *stdout, &*records.lock().unwrap()).ok();
+            writeln!(stdout).ok();
+        }
+        self.writer.lock().unwrap().flush().ok();
     }
 }
 
@@ -163,14 +463,231 @@ impl<'out> Block<'out> {
         self.add_line(content).finish();
     }
 
-    pub fn add_error_line(&self, error: crate::Error) {
-        self.add_finished_line(ErrorLineContent { error })
+    /// Get the number of lines which have finished with an error.
+    pub fn error_count(&self) -> usize {
+        self.inner.lock().unwrap().error_count
+    }
+
+    /// Get the number of lines which were cancelled before they ran.
+    pub fn cancelled_count(&self) -> usize {
+        self.inner.lock().unwrap().cancelled_count
+    }
+
+    /// Get the number of shown lines which have finished in a state matching `condition`.
+    pub fn fail_on_count(&self, condition: FailOn) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.finished
+                    && !entry.cancelled
+                    && !entry.content.is_hidden()
+                    && entry.content.matches_fail_on(condition)
+            })
+            .count()
+    }
+
+    /// Cancel lines which have not started running yet. Lines which are already running are
+    /// allowed to finish.
+    pub fn abort(&self) {
+        self.inner.lock().unwrap().aborted = true;
+    }
+
+    /// Whether `abort` has been called.
+    pub fn is_aborted(&self) -> bool {
+        self.inner.lock().unwrap().aborted
+    }
+
+    /// Write lines of text above the block, optionally after a header line, such that they are not
+    /// interleaved with other output.
+    ///
+    /// In JSON mode, nothing is written.
+    pub fn writeln_above<I>(
+        &self,
+        header: Option<&dyn LineContent>,
+        lines: I,
+    ) -> crossterm::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Display,
+    {
+        if self.output.json {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let mut stdout = self.output.writer.lock().unwrap();
+
+        if self.output.is_interactive() {
+            crossterm::queue!(stdout, Clear(ClearType::FromCursorDown))?;
+            if let Some(header) = header {
+                crossterm::queue!(stdout, MoveToColumn(0))?;
+                header.write(&mut stdout)?;
+                writeln!(stdout)?;
+            }
+            for line in lines {
+                crossterm::queue!(stdout, MoveToColumn(0))?;
+                writeln!(stdout, "{}", line)?;
+            }
+            inner.write_all(&mut stdout)?;
+            inner.reset_cursor(&mut stdout)?;
+        } else {
+            if let Some(header) = header {
+                header.write(&mut stdout)?;
+                writeln!(stdout)?;
+            }
+            for line in lines {
+                writeln!(stdout, "{}", line)?;
+            }
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Pause updates to the block and ask the user a yes or no question, returning their answer.
+    ///
+    /// Returns `false` when the user cannot be asked.
+    pub fn prompt(&self, question: impl Display) -> crate::Result<bool> {
+        if !self.output.can_prompt() {
+            return Ok(false);
+        }
+
+        // Lines are not finished while the lock is held, so the block stays paused until answered
+        let mut inner = self.inner.lock().unwrap();
+        let mut stdout = self.output.writer.lock().unwrap();
+
+        crossterm::queue!(stdout, Clear(ClearType::FromCursorDown))?;
+        write!(stdout, "{} [y/N] ", question)?;
+        crossterm::queue!(stdout, cursor::Show)?;
+        stdout.flush()?;
+
+        // Ctrl-C is usually handled as an interrupt, rather than read as a key press
+        let answer = loop {
+            if interrupt::is_interrupted() {
+                break false;
+            }
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => break true,
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        break false
+                    }
+                    KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Enter | KeyCode::Esc => {
+                        break false
+                    }
+                    _ => (),
+                }
+            }
+        };
+
+        crossterm::queue!(
+            stdout,
+            cursor::Hide,
+            MoveToColumn(0),
+            Clear(ClearType::CurrentLine)
+        )?;
+        inner.write_all(&mut stdout)?;
+        inner.reset_cursor(&mut stdout)?;
+        stdout.flush()?;
+
+        Ok(answer)
+    }
+
+    /// Pause updates to the block and hand the terminal over to `run`, such as for a command which
+    /// interacts with the user directly. `header` is written first, then the footer returned by
+    /// `footer` for the result of `run`, if any, and the block is redrawn below them.
+    ///
+    /// The terminal is in its normal mode while `run` is called, and is reset afterwards in case
+    /// `run` left it in a different mode or style. When the output is not interactive, `run` is
+    /// called without writing the header or footer.
+    pub fn suspend<T, F>(
+        &self,
+        header: &dyn LineContent,
+        run: impl FnOnce() -> T,
+        footer: impl FnOnce(&T) -> Option<F>,
+    ) -> crate::Result<T>
+    where
+        F: LineContent,
+    {
+        if !self.output.is_interactive() {
+            return Ok(run());
+        }
+
+        // Lines are not finished while the lock is held, so the block stays paused until `run` returns
+        let mut inner = self.inner.lock().unwrap();
+        let mut stdout = self.output.writer.lock().unwrap();
+
+        crossterm::queue!(stdout, Clear(ClearType::FromCursorDown))?;
+        write_delimiter(&mut stdout, header)?;
+        crossterm::queue!(stdout, cursor::Show, cursor::EnableBlinking)?;
+        stdout.flush()?;
+        terminal::disable_raw_mode()?;
+        let mode = TerminalMode::save();
+
+        let result = run();
+
+        if let Some(mode) = mode {
+            mode.restore();
+        }
+        terminal::enable_raw_mode()?;
+        interrupt::enable_in_raw_mode();
+        crossterm::queue!(
+            stdout,
+            ResetColor,
+            SetAttribute(Attribute::Reset),
+            cursor::Hide,
+            cursor::DisableBlinking
+        )?;
+        // Start the block on a new line if the output did not end with one. Filling the rest of the
+        // row only wraps onto the next one if the cursor was not already at the start of a row.
+        let (cols, _) = terminal::size()?;
+        write!(stdout, "{:1$}\r", "", cols.saturating_sub(1) as usize)?;
+        crossterm::queue!(stdout, Clear(ClearType::CurrentLine))?;
+        if let Some(footer) = footer(&result) {
+            write_delimiter(&mut stdout, &footer)?;
+        }
+        inner.write_all(&mut stdout)?;
+        inner.reset_cursor(&mut stdout)?;
+        stdout.flush()?;
+
+        Ok(result)
+    }
+
+    /// Only show lines which have finished with an error.
+    pub fn set_errors_only(&self) {
+        self.inner.lock().unwrap().errors_only = true;
+    }
+
+    /// Show every line while it is running, but remove lines which finish without a problem.
+    pub fn set_finished_errors_only(&self) {
+        self.inner.lock().unwrap().finished_errors_only = true;
+    }
+
+    /// Write records in the order their lines were added, as with `--keep-order`, so that records
+    /// for header lines are kept next to the lines under them.
+    pub fn set_keep_order(&self) {
+        self.inner.lock().unwrap().keep_order = true;
+    }
+
+    /// Set a line which is always shown above the unfinished lines of this block.
+    ///
+    /// This should be called before any lines are added. The header is not included in JSON output.
+    pub fn set_header<C>(&self, content: C)
+    where
+        C: LineContent + 'out,
+    {
+        self.inner.lock().unwrap().header = Some(Box::new(content));
     }
 
     pub fn update_all(&self) -> crossterm::Result<()> {
-        if !self.output.json {
+        if self.output.is_interactive() {
             let mut inner = self.inner.lock().unwrap();
-            let mut stdout = self.output.stdout.lock();
+            let mut stdout = self.output.writer.lock().unwrap();
 
             inner.write_all(&mut stdout)?;
             inner.reset_cursor(&mut stdout)?;
@@ -180,9 +697,9 @@ impl<'out> Block<'out> {
     }
 
     fn update(&self, index: usize) -> crossterm::Result<()> {
-        if !self.output.json {
+        if self.output.is_interactive() {
             if let Ok(mut inner) = self.inner.try_lock() {
-                let mut stdout = self.output.stdout.lock();
+                let mut stdout = self.output.writer.lock().unwrap();
 
                 inner.update(&mut stdout, index)?;
             }
@@ -193,12 +710,12 @@ impl<'out> Block<'out> {
 
     fn finish(&self, index: usize) -> crossterm::Result<()> {
         let mut inner = self.inner.lock().unwrap();
-        let mut stdout = self.output.stdout.lock();
+        let mut stdout = self.output.writer.lock().unwrap();
 
-        if self.output.json {
-            inner.finish_json(&mut stdout, index)?;
-        } else {
+        if self.output.is_interactive() {
             inner.finish(&mut stdout, index)?;
+        } else {
+            inner.finish_records(self.output, &mut stdout, index)?;
         }
 
         Ok(())
@@ -211,25 +728,26 @@ impl<'out> BlockInner<'out> {
         self.entries.push(BlockEntry {
             content,
             finished: false,
+            cancelled: false,
         });
 
-        if (self.range.len() + 1) < self.rows {
+        if (self.height() + 1) < self.rows {
             self.range.end += 1;
         }
 
         index
     }
 
-    fn update(&mut self, stdout: &mut io::StdoutLock, index: usize) -> crossterm::Result<()> {
+    fn update(&mut self, stdout: &mut Writer, index: usize) -> crossterm::Result<()> {
         if self.range.contains(&index) {
             self.write_all(stdout)?;
-            crossterm::queue!(stdout, MoveUp(self.range.len() as u16))?;
+            self.reset_cursor(stdout)?;
         }
         Ok(())
     }
 
-    fn finish(&mut self, stdout: &mut io::StdoutLock, index: usize) -> crossterm::Result<()> {
-        self.entries[index].finished = true;
+    fn finish(&mut self, stdout: &mut Writer, index: usize) -> crossterm::Result<()> {
+        self.set_finished(index);
 
         let shift = if index == self.range.start {
             self.entries[index..]
@@ -241,49 +759,159 @@ impl<'out> BlockInner<'out> {
         };
 
         self.range.end = cmp::min(self.range.end + shift, self.entries.len());
-        self.write_all(stdout)?;
+        self.fit_range();
+        // Write lines which are scrolling out of the block first, so they end up above the header
+        for index in self.range.start..(self.range.start + shift) {
+            if self.is_visible(index) {
+                self.entries[index].content.write(stdout)?;
+                writeln!(stdout)?;
+            }
+        }
         self.range.start += shift;
+        self.write_all(stdout)?;
 
         self.reset_cursor(stdout)?;
 
         Ok(())
     }
 
-    fn finish_json(&mut self, stdout: &mut io::StdoutLock, index: usize) -> io::Result<()> {
-        self.entries[index].finished = true;
+    /// Write a line which has just finished as a JSON or porcelain record, or as plain text when
+    /// writing to a file.
+    fn finish_records(
+        &mut self,
+        output: &Output,
+        stdout: &mut Writer,
+        index: usize,
+    ) -> io::Result<()> {
+        self.set_finished(index);
 
-        for entry in self.entries[index..]
-            .iter()
-            .take_while(|entry| entry.finished)
-        {
-            entry.content.write_json(stdout)?;
-            writeln!(stdout)?;
+        if output.keep_order || self.keep_order {
+            // Like the terminal output, only write lines once every line before them has finished
+            while self.written_records < self.entries.len()
+                && self.entries[self.written_records].finished
+            {
+                self.write_record(output, stdout, self.written_records)?;
+                self.written_records += 1;
+            }
+        } else {
+            // Each line is written exactly once, when it finishes
+            self.write_record(output, stdout, index)?;
         }
         Ok(())
     }
 
-    fn write_all(&mut self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
-        for index in self.range.clone() {
-            self.entries[index].content.write(stdout)?;
+    fn write_record(&self, output: &Output, stdout: &mut Writer, index: usize) -> io::Result<()> {
+        if self.is_visible(index) {
+            let content = &self.entries[index].content;
+            if output.json {
+                output.write_record(stdout, |writer| content.write_json(writer))?;
+            } else if output.porcelain {
+                let mut record = Vec::new();
+                content.write_porcelain(&mut record)?;
+                if !record.is_empty() {
+                    stdout.write_all(&record)?;
+                    output.write_terminator(stdout)?;
+                }
+            } else {
+                content.write(stdout)?;
+                writeln!(stdout)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all(&mut self, stdout: &mut Writer) -> crossterm::Result<()> {
+        let mut rows = 0;
+        if let Some(header) = &self.header {
+            header.write(stdout)?;
             writeln!(stdout)?;
+            rows += 1;
+        }
+        for index in self.range.clone() {
+            if self.is_visible(index) {
+                self.entries[index].content.write(stdout)?;
+                writeln!(stdout)?;
+                rows += self.entries[index].content.rows();
+            }
+        }
+
+        // Hidden lines may leave behind stale output from a previous write
+        if self.errors_only || self.finished_errors_only || self.has_hidden {
+            crossterm::queue!(stdout, Clear(ClearType::FromCursorDown))?;
         }
 
+        self.written_rows = rows;
         Ok(())
     }
 
-    fn reset_cursor(&mut self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
-        if !self.range.is_empty() {
-            crossterm::queue!(stdout, MoveUp(self.range.len() as u16))?;
+    fn reset_cursor(&mut self, stdout: &mut Writer) -> crossterm::Result<()> {
+        if self.written_rows != 0 {
+            crossterm::queue!(stdout, MoveUp(self.written_rows as u16))?;
         }
         Ok(())
     }
+
+    fn set_finished(&mut self, index: usize) {
+        let entry = &mut self.entries[index];
+        if !entry.finished {
+            entry.finished = true;
+            if entry.content.is_error() {
+                self.error_count += 1;
+            }
+            if entry.content.is_hidden() {
+                self.has_hidden = true;
+            }
+        }
+    }
+
+    fn cancel(&mut self, index: usize) {
+        let entry = &mut self.entries[index];
+        if !entry.finished && !entry.cancelled {
+            entry.cancelled = true;
+            self.cancelled_count += 1;
+            self.has_hidden = true;
+        }
+    }
+
+    fn is_visible(&self, index: usize) -> bool {
+        let entry = &self.entries[index];
+        if entry.cancelled || (entry.finished && entry.content.is_hidden()) {
+            return false;
+        }
+        if self.finished_errors_only && entry.finished && !entry.content.is_problem() {
+            return false;
+        }
+        !self.errors_only || (entry.finished && entry.content.is_problem())
+    }
+
+    /// The number of rows currently taken up by this block.
+    fn height(&self) -> usize {
+        let lines: usize = self
+            .range
+            .clone()
+            .map(|index| self.entries[index].content.rows())
+            .sum();
+        lines + self.header.is_some() as usize
+    }
+
+    /// Show as many lines as fit in the terminal, accounting for lines which take up several rows.
+    fn fit_range(&mut self) {
+        while self.range.end < self.entries.len()
+            && self.height() + self.entries[self.range.end].content.rows() < self.rows
+        {
+            self.range.end += 1;
+        }
+        while self.range.len() > 1 && self.height() >= self.rows {
+            self.range.end -= 1;
+        }
+    }
 }
 
 impl<'out> Drop for Block<'out> {
     fn drop(&mut self) {
-        if !self.output.json {
+        if self.output.is_interactive() {
             let mut inner = self.inner.lock().unwrap();
-            let mut stdout = self.output.stdout.lock();
+            let mut stdout = self.output.writer.lock().unwrap();
 
             inner.write_all(&mut stdout).ok();
 
@@ -311,31 +939,234 @@ impl<'out, 'block, C> Line<'out, 'block, C> {
     pub fn finish(&self) {
         self.block.finish(self.index).ok();
     }
+
+    /// Pause updates to the block containing this line while `run` uses the terminal. See
+    /// [`Block::suspend`].
+    pub fn suspend<T, F>(
+        &self,
+        header: &dyn LineContent,
+        run: impl FnOnce() -> T,
+        footer: impl FnOnce(&T) -> Option<F>,
+    ) -> crate::Result<T>
+    where
+        F: LineContent,
+    {
+        self.block.suspend(header, run, footer)
+    }
+
+    /// Finish this line without running it, removing it from the output.
+    pub fn cancel(&self) {
+        self.block.inner.lock().unwrap().cancel(self.index);
+        self.finish();
+    }
 }
 
-struct ErrorLineContent {
-    error: crate::Error,
+/// The attributes of the controlling terminal, saved so they can be restored after running a
+/// program which may change them, such as by turning off echo.
+#[cfg(unix)]
+struct TerminalMode {
+    tty: fs::File,
+    termios: libc::termios,
 }
 
-impl LineContent for ErrorLineContent {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
-        self.error.write(stdout)
+#[cfg(unix)]
+impl TerminalMode {
+    fn save() -> Option<Self> {
+        use std::mem::MaybeUninit;
+        use std::os::unix::io::AsRawFd;
+
+        let tty = fs::File::open("/dev/tty").ok()?;
+        unsafe {
+            let mut termios = MaybeUninit::uninit();
+            if libc::tcgetattr(tty.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+                return None;
+            }
+            Some(TerminalMode {
+                tty,
+                termios: termios.assume_init(),
+            })
+        }
     }
 
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()> {
-        #[derive(Serialize)]
-        struct JsonError<'a> {
-            kind: &'static str,
-            #[serde(flatten)]
-            error: &'a crate::Error,
+    fn restore(&self) {
+        use std::os::unix::io::AsRawFd;
+
+        unsafe {
+            libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.termios);
         }
+    }
+}
 
-        serde_json::to_writer(
-            stdout,
-            &JsonError {
-                kind: "error",
-                error: &self.error,
-            },
-        )
+#[cfg(windows)]
+struct TerminalMode;
+
+#[cfg(windows)]
+impl TerminalMode {
+    fn save() -> Option<Self> {
+        None
+    }
+
+    fn restore(&self) {}
+}
+
+/// A writer which removes terminal escape sequences, such as colors and cursor movement, so
+/// plain output written to a file is readable.
+struct StripStyle<W> {
+    inner: W,
+    state: EscapeState,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EscapeState {
+    Text,
+    /// After an escape character.
+    Escape,
+    /// Inside a control sequence, which ends with a byte in the range `@` to `~`.
+    ControlSequence,
+}
+
+impl<W> StripStyle<W> {
+    fn new(inner: W) -> Self {
+        StripStyle {
+            inner,
+            state: EscapeState::Text,
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for StripStyle<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut text = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            self.state = match (self.state, byte) {
+                (EscapeState::Text, b'\x1b') => EscapeState::Escape,
+                (EscapeState::Text, _) => {
+                    text.push(byte);
+                    EscapeState::Text
+                }
+                (EscapeState::Escape, b'[') => EscapeState::ControlSequence,
+                (EscapeState::Escape, _) => EscapeState::Text,
+                (EscapeState::ControlSequence, b'@'..=b'~') => EscapeState::Text,
+                (EscapeState::ControlSequence, _) => EscapeState::ControlSequence,
+            };
+        }
+        self.inner.write_all(&text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    kind: &'static str,
+    #[serde(flatten)]
+    error: &'a crate::Error,
+}
+
+impl<'a> JsonError<'a> {
+    fn new(error: &'a crate::Error) -> Self {
+        JsonError {
+            kind: "error",
+            error,
+        }
+    }
+}
+
+/// A writer which keeps its output in memory, for testing how lines are written.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl TestWriter {
+    pub fn writer(&self) -> Writer {
+        Box::new(self.clone())
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl io::Write for TestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_truncate_start() {
+    assert_eq!(truncate_start("path/to/repo", 20), "path/to/repo");
+    assert_eq!(truncate_start("path/to/repo", 12), "path/to/repo");
+    assert_eq!(truncate_start("path/to/repo", 8), "…to/repo");
+    assert_eq!(truncate_start("path/to/repo", 1), "…");
+    assert_eq!(truncate_start("path/to/repo", 0), "");
+}
+
+#[test]
+fn test_write_path_column() {
+    let write = |cols, max_width| {
+        let mut output = Vec::new();
+        write_path_column(&mut output, Path::new("path/to/repo"), cols, max_width).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(write(20, None), "path/to/repo ");
+    assert_eq!(write(40, None), "path/to/repo         ");
+    assert_eq!(write(10, None), "path/to/repo ");
+    assert_eq!(write(100, Some(15)), "path/to/repo    ");
+    assert_eq!(write(100, Some(8)), "…to/repo ");
+    assert_eq!(write(6, Some(8)), "…/repo ");
+}
+
+#[test]
+fn test_write_tree_column() {
+    let write = |cols, max_width| {
+        let mut output = Vec::new();
+        write_tree_column(&mut output, "│   └── ", "path/to/repo", cols, max_width).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(write(40, None), "│   └── path/to/repo ");
+    assert_eq!(write(60, None), "│   └── path/to/repo           ");
+    assert_eq!(write(100, Some(22)), "│   └── path/to/repo   ");
+    assert_eq!(write(100, Some(16)), "│   └── …to/repo ");
+    assert_eq!(write(100, Some(4)), "│   └── … ");
+}
+
+#[test]
+fn test_write_settings() {
+    let mut writer = Vec::new();
+    write_settings(&mut writer, "read-only = true").unwrap();
+    let text = String::from_utf8(writer).unwrap();
+    assert!(text.starts_with("\x1b[?7l"), "{:?}", text);
+    assert!(text.contains(" [read-only = true]"), "{:?}", text);
+    assert!(text.ends_with("\x1b[?7h"), "{:?}", text);
+}
+
+#[test]
+fn test_strip_style() {
+    let mut writer = StripStyle::new(Vec::new());
+    crossterm::queue!(
+        writer,
+        Clear(ClearType::CurrentLine),
+        SetForegroundColor(Color::Green)
+    )
+    .unwrap();
+    write!(writer, "clean").unwrap();
+    crossterm::queue!(writer, ResetColor, MoveUp(2)).unwrap();
+    // Escape sequences may be split across writes
+    writer.write_all(b" \x1b[").unwrap();
+    writer.write_all(b"1mrepo\x1b").unwrap();
+    writer.write_all(b"[0m").unwrap();
+
+    assert_eq!(writer.inner, b"clean repo");
+}
@@ -0,0 +1,317 @@
+use crate::git;
+
+/// A user-provided condition on the status of a repo, e.g. `behind > 0 && !dirty`.
+#[derive(Debug, PartialEq)]
+pub enum Predicate {
+    Flag(Flag),
+    Compare(Count, Comparison, usize),
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flag {
+    Dirty,
+    Detached,
+    Gone,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Count {
+    Ahead,
+    Behind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'a> {
+    Ident(&'a str),
+    Number(usize),
+    Comparison(Comparison),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+const FIELDS: &[&str] = &["ahead", "behind", "dirty", "detached", "gone"];
+
+impl Predicate {
+    /// Parse a predicate, checking that every field is known.
+    ///
+    /// Predicates are made up of the flags `dirty`, `detached` and `gone`, comparisons of the
+    /// counts `ahead` and `behind` with a number, and the operators `!`, `&&`, `||` and parentheses.
+    pub fn parse(text: &str) -> crate::Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+
+        let predicate = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(predicate),
+            Some(token) => Err(crate::Error::from_message(format!(
+                "unexpected {} in predicate",
+                token
+            ))),
+        }
+    }
+
+    /// Check whether the status of a repo matches this predicate.
+    pub fn is_match(&self, status: &git::RepositoryStatus) -> bool {
+        match self {
+            Predicate::Flag(Flag::Dirty) => status.working_tree.is_dirty(),
+            Predicate::Flag(Flag::Detached) => {
+                matches!(status.head.kind, git::HeadStatusKind::Detached)
+            }
+            Predicate::Flag(Flag::Gone) => matches!(status.upstream, git::UpstreamStatus::Gone),
+            Predicate::Compare(count, comparison, value) => {
+                let (ahead, behind) = match status.upstream {
+                    git::UpstreamStatus::Upstream(counts) => (counts.ahead, counts.behind),
+                    git::UpstreamStatus::None
+                    | git::UpstreamStatus::Uncounted
+                    | git::UpstreamStatus::Gone => (0, 0),
+                };
+                let actual = match count {
+                    Count::Ahead => ahead,
+                    Count::Behind => behind,
+                };
+                match comparison {
+                    Comparison::Less => actual < *value,
+                    Comparison::LessOrEqual => actual <= *value,
+                    Comparison::Greater => actual > *value,
+                    Comparison::GreaterOrEqual => actual >= *value,
+                    Comparison::Equal => actual == *value,
+                    Comparison::NotEqual => actual != *value,
+                }
+            }
+            Predicate::Not(inner) => !inner.is_match(status),
+            Predicate::And(lhs, rhs) => lhs.is_match(status) && rhs.is_match(status),
+            Predicate::Or(lhs, rhs) => lhs.is_match(status) || rhs.is_match(status),
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn parse_or(&mut self) -> crate::Result<Predicate> {
+        let mut lhs = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            lhs = Predicate::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> crate::Result<Predicate> {
+        let mut lhs = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            lhs = Predicate::And(Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> crate::Result<Predicate> {
+        match self.next()? {
+            Token::Not => Ok(Predicate::Not(Box::new(self.parse_unary()?))),
+            Token::Open => {
+                let inner = self.parse_or()?;
+                match self.next()? {
+                    Token::Close => Ok(inner),
+                    token => Err(crate::Error::from_message(format!(
+                        "expected `)` in predicate, found {}",
+                        token
+                    ))),
+                }
+            }
+            Token::Ident(name) => self.parse_field(name),
+            token => Err(crate::Error::from_message(format!(
+                "expected a field in predicate, found {}",
+                token
+            ))),
+        }
+    }
+
+    fn parse_field(&mut self, name: &str) -> crate::Result<Predicate> {
+        let count = match name {
+            "dirty" => return Ok(Predicate::Flag(Flag::Dirty)),
+            "detached" => return Ok(Predicate::Flag(Flag::Detached)),
+            "gone" => return Ok(Predicate::Flag(Flag::Gone)),
+            "ahead" => Count::Ahead,
+            "behind" => Count::Behind,
+            _ => {
+                return Err(crate::Error::from_message(format!(
+                    "unknown field `{}` in predicate (expected one of {})",
+                    name,
+                    FIELDS
+                        .iter()
+                        .map(|field| format!("`{}`", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )))
+            }
+        };
+
+        let comparison = match self.next()? {
+            Token::Comparison(comparison) => comparison,
+            token => {
+                return Err(crate::Error::from_message(format!(
+                    "expected a comparison after `{}` in predicate, found {}",
+                    name, token
+                )))
+            }
+        };
+        match self.next()? {
+            Token::Number(value) => Ok(Predicate::Compare(count, comparison, value)),
+            token => Err(crate::Error::from_message(format!(
+                "expected a number in predicate, found {}",
+                token
+            ))),
+        }
+    }
+
+    fn next(&mut self) -> crate::Result<Token<'a>> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => Err(crate::Error::from_message("unexpected end of predicate")),
+        }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn tokenize(text: &str) -> crate::Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        let (token, len) = if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest
+                .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+                .unwrap_or(rest.len());
+            (Token::Ident(&rest[..len]), len)
+        } else if ch.is_ascii_digit() {
+            let len = rest
+                .find(|ch: char| !ch.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let value = rest[..len].parse().map_err(|_| {
+                crate::Error::from_message(format!("number `{}` is too large", &rest[..len]))
+            })?;
+            (Token::Number(value), len)
+        } else {
+            const OPERATORS: &[(&str, Token<'static>)] = &[
+                ("&&", Token::And),
+                ("||", Token::Or),
+                ("<=", Token::Comparison(Comparison::LessOrEqual)),
+                (">=", Token::Comparison(Comparison::GreaterOrEqual)),
+                ("==", Token::Comparison(Comparison::Equal)),
+                ("!=", Token::Comparison(Comparison::NotEqual)),
+                ("<", Token::Comparison(Comparison::Less)),
+                (">", Token::Comparison(Comparison::Greater)),
+                ("!", Token::Not),
+                ("(", Token::Open),
+                (")", Token::Close),
+            ];
+
+            match OPERATORS.iter().find(|(op, _)| rest.starts_with(op)) {
+                Some((op, token)) => (token.clone(), op.len()),
+                None => {
+                    return Err(crate::Error::from_message(format!(
+                        "unexpected character `{}` in predicate",
+                        ch
+                    )))
+                }
+            }
+        };
+
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+impl<'a> std::fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(value) => write!(f, "`{}`", value),
+            Token::Comparison(Comparison::Less) => write!(f, "`<`"),
+            Token::Comparison(Comparison::LessOrEqual) => write!(f, "`<=`"),
+            Token::Comparison(Comparison::Greater) => write!(f, "`>`"),
+            Token::Comparison(Comparison::GreaterOrEqual) => write!(f, "`>=`"),
+            Token::Comparison(Comparison::Equal) => write!(f, "`==`"),
+            Token::Comparison(Comparison::NotEqual) => write!(f, "`!=`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+#[test]
+fn test_parse_predicate() {
+    assert_eq!(
+        Predicate::parse("behind>0").unwrap(),
+        Predicate::Compare(Count::Behind, Comparison::Greater, 0)
+    );
+    assert_eq!(
+        Predicate::parse("dirty || !detached && ahead >= 2").unwrap(),
+        Predicate::Or(
+            Box::new(Predicate::Flag(Flag::Dirty)),
+            Box::new(Predicate::And(
+                Box::new(Predicate::Not(Box::new(Predicate::Flag(Flag::Detached)))),
+                Box::new(Predicate::Compare(
+                    Count::Ahead,
+                    Comparison::GreaterOrEqual,
+                    2
+                )),
+            )),
+        )
+    );
+    assert_eq!(
+        Predicate::parse("!(gone)").unwrap(),
+        Predicate::Not(Box::new(Predicate::Flag(Flag::Gone)))
+    );
+
+    let cases = vec![
+        "",
+        "unknown",
+        "behind",
+        "dirty > 0",
+        "behind > dirty",
+        "(gone",
+        "gone)",
+        "gone &&",
+        "ahead = 1",
+    ];
+    for case in cases {
+        assert!(Predicate::parse(case).is_err(), "{}", case);
+    }
+}
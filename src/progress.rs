@@ -1,4 +1,5 @@
-use std::io::{self, Write as _};
+use std::cmp;
+use std::io::Write;
 
 use crossterm::cursor::MoveRight;
 use crossterm::style::{Attribute, SetAttribute};
@@ -13,20 +14,25 @@ impl ProgressBar {
         ProgressBar { progress: 0.0 }
     }
 
-    pub fn write(&self, stdout: &mut io::StdoutLock, width: u16) -> crossterm::Result<()> {
+    pub fn write(&self, stdout: &mut impl Write, width: u16) -> crossterm::Result<()> {
         if width <= 2 {
             return Ok(());
         }
         let bar_width = (width - 2) as usize;
-        let progress_width = (bar_width as f64 * self.progress) as usize;
+        let progress_width = cmp::min(
+            (bar_width as f64 * self.progress.max(0.0)) as usize,
+            bar_width,
+        );
 
         crossterm::queue!(stdout, SetAttribute(Attribute::Dim))?;
         write!(stdout, "[")?;
         stdout.flush()?;
 
-        crossterm::queue!(stdout, SetAttribute(Attribute::Bold))?;
-        write!(stdout, "{:=>width$}", ">", width = progress_width)?;
-        crossterm::queue!(stdout, SetAttribute(Attribute::Reset))?;
+        if progress_width > 0 {
+            crossterm::queue!(stdout, SetAttribute(Attribute::Bold))?;
+            write!(stdout, "{:=>width$}", ">", width = progress_width)?;
+            crossterm::queue!(stdout, SetAttribute(Attribute::Reset))?;
+        }
 
         if progress_width < bar_width {
             crossterm::queue!(stdout, MoveRight((bar_width - progress_width) as u16))?;
@@ -43,3 +49,45 @@ impl ProgressBar {
         self.progress = progress;
     }
 }
+
+/// Get the number of columns taken up by some terminal output, skipping escape sequences.
+#[cfg(test)]
+pub fn visible_width(output: &[u8]) -> usize {
+    let output = std::str::from_utf8(output).unwrap();
+    let mut width = 0;
+    let mut chars = output.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            assert_eq!(chars.next(), Some('['));
+            let mut param = String::new();
+            for ch in chars.by_ref() {
+                if ch.is_ascii_alphabetic() {
+                    if ch == 'C' {
+                        width += param.parse::<usize>().unwrap_or(1);
+                    }
+                    break;
+                }
+                param.push(ch);
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+#[test]
+fn test_progress_bar_width() {
+    for width in 0..20 {
+        for &progress in &[0.0, 0.01, 0.5, 0.99, 1.0, 1.5] {
+            let mut bar = ProgressBar::new();
+            bar.set(progress);
+
+            let mut output = Vec::new();
+            bar.write(&mut output, width).unwrap();
+
+            let expected = if width <= 2 { 0 } else { width as usize };
+            assert_eq!(visible_width(&output), expected, "{} {}", width, progress);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Read};
+use std::process::{Command, ExitStatus};
+
+use crossterm::terminal;
+use portable_pty::{CommandBuilder, PtySize};
+
+/// A child process whose standard streams are connected to a pseudo-terminal, for programs which
+/// only use colors or prompts when writing to a terminal.
+pub struct PtyChild {
+    child: Box<dyn portable_pty::Child + Send + Sync>,
+    master: Box<dyn portable_pty::MasterPty + Send>,
+}
+
+impl PtyChild {
+    /// Spawn `command` in a new pseudo-terminal with the same size as the current terminal.
+    pub fn spawn(command: &Command) -> crate::Result<Self> {
+        let size = match terminal::size() {
+            Ok((cols, rows)) => PtySize {
+                rows,
+                cols,
+                ..PtySize::default()
+            },
+            Err(_) => PtySize::default(),
+        };
+
+        let pair = portable_pty::native_pty_system()
+            .openpty(size)
+            .map_err(|err| {
+                crate::Error::with_context(
+                    crate::Error::from_message(err),
+                    "failed to allocate a pseudo-terminal",
+                )
+            })?;
+
+        let child = pair
+            .slave
+            .spawn_command(command_builder(command))
+            .map_err(|err| {
+                crate::Error::with_context(
+                    crate::Error::from_message(err),
+                    "failed to spawn command",
+                )
+            })?;
+
+        Ok(PtyChild {
+            child,
+            master: pair.master,
+        })
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.child.process_id()
+    }
+
+    /// Get a reader for the output of the command, which reaches the end once the command exits.
+    pub fn reader(&self) -> crate::Result<Box<dyn Read + Send>> {
+        self.master.try_clone_reader().map_err(|err| {
+            crate::Error::with_context(
+                crate::Error::from_message(err),
+                "failed to read from pseudo-terminal",
+            )
+        })
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        Ok(self.child.try_wait()?.map(exit_status))
+    }
+
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().map(exit_status)
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+}
+
+fn command_builder(command: &Command) -> CommandBuilder {
+    let mut builder = CommandBuilder::new(command.get_program());
+    builder.args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => builder.env(key, value),
+            None => builder.env_remove(key),
+        }
+    }
+    if let Some(dir) = command.get_current_dir() {
+        builder.cwd(dir);
+    }
+    builder
+}
+
+#[cfg(unix)]
+fn exit_status(status: portable_pty::ExitStatus) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    // The exit code is stored in the second byte of the raw wait status
+    ExitStatus::from_raw((status.exit_code() as i32 & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(status: portable_pty::ExitStatus) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(status.exit_code())
+}
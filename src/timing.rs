@@ -0,0 +1,103 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::output::Output;
+
+/// The time spent finding repos, in nanoseconds.
+static DISCOVERY: AtomicU64 = AtomicU64::new(0);
+/// The time spent running the command in repos, in nanoseconds.
+static WORK: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Whether a timer is running on this thread.
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Phase {
+    Discovery,
+    Work,
+}
+
+/// Adds the time from when it was started until it is dropped to the total for a phase.
+///
+/// A timer started while another is running on the same thread does nothing, so the time is only
+/// counted once, for the outer phase.
+pub struct Timer {
+    phase: Phase,
+    start: Option<Instant>,
+}
+
+impl Timer {
+    pub fn start(phase: Phase) -> Self {
+        let nested = RUNNING.with(|running| running.replace(true));
+        Timer {
+            phase,
+            start: (!nested).then(Instant::now),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        RUNNING.with(|running| running.set(false));
+
+        let total = match self.phase {
+            Phase::Discovery => &DISCOVERY,
+            Phase::Work => &WORK,
+        };
+        let elapsed = start.elapsed().as_nanos() as u64;
+        total.fetch_add(elapsed, Ordering::Relaxed);
+    }
+}
+
+/// Write the total time taken by the command, for `--time`, along with the time spent in each
+/// phase.
+pub fn write(out: &Output, total: Duration) {
+    let discovery = Duration::from_nanos(DISCOVERY.load(Ordering::Relaxed));
+    let work = Duration::from_nanos(WORK.load(Ordering::Relaxed));
+
+    if out.is_json() {
+        #[derive(Serialize)]
+        struct JsonTiming {
+            kind: &'static str,
+            total_ms: u128,
+            discovery_ms: u128,
+            work_ms: u128,
+        }
+
+        out.writeln_json(&JsonTiming {
+            kind: "timing",
+            total_ms: total.as_millis(),
+            discovery_ms: discovery.as_millis(),
+            work_ms: work.as_millis(),
+        })
+        .ok();
+    } else {
+        out.writeln_message(format_args!(
+            "finished in {:.3}s (discovery {:.3}s, work {:.3}s)",
+            total.as_secs_f64(),
+            discovery.as_secs_f64(),
+            work.as_secs_f64()
+        ));
+    }
+}
+
+#[test]
+fn test_nested_timer() {
+    let outer = Timer::start(Phase::Work);
+    let inner = Timer::start(Phase::Discovery);
+    assert!(outer.start.is_some());
+    assert!(inner.start.is_none());
+
+    drop(inner);
+    drop(outer);
+    assert!(Timer::start(Phase::Discovery).start.is_some());
+}
@@ -1,16 +1,25 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Write as _};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use crossterm::style::{Attribute, Color, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal;
 use serde::Serialize;
 
+use crate::alias::Target;
+use crate::cache::{self, DiscoveryCache};
+use crate::cli::FailOn;
 use crate::config::{Config, Settings};
-use crate::output::{Block, Line, LineContent, Output};
-use crate::{cli, git};
+use crate::output::{self, Block, Line, LineContent, Output};
+use crate::timing::{self, Timer};
+use crate::{cli, git, interrupt};
 
 pub struct Entry {
     pub path: PathBuf,
+    /// The path shown to the user, which is relative to the root unless `--relative-to` is set.
     pub relative_path: PathBuf,
     pub repo: git::Repository,
     pub settings: Settings,
@@ -20,7 +29,7 @@ pub fn walk_with_output<'out, C, B, U>(
     args: &cli::Args,
     output: &'out Output,
     config: &Config,
-    path: impl Into<PathBuf> + AsRef<Path>,
+    target: &Target,
     build: B,
     update: U,
 ) -> crate::Result<()>
@@ -30,102 +39,466 @@ where
     U: for<'block> Fn(&Entry, &Line<'out, 'block, C>) + Sync,
 {
     let block = output.block()?;
-    let mut lines = walk_build(&block, config, path, build);
-    walk_update(args, &block, &mut lines, update);
-    Ok(())
+    walk_with_block(args, &block, config, target, build, update);
+    check_errors(args, &block)
 }
 
-pub fn walk<F, G, H>(
+/// Fail the command if any lines in the block finished with an error, unless `--ignore-errors`
+/// was passed.
+pub fn check_errors(args: &cli::Args, block: &Block) -> crate::Result<()> {
+    match block.cancelled_count() {
+        0 => (),
+        1 if block.is_aborted() => {
+            return Err(crate::Error::from_message(
+                "aborted after a failure, before 1 repo could run",
+            ))
+        }
+        count if block.is_aborted() => {
+            return Err(crate::Error::from_message(format!(
+                "aborted after a failure, before {} repos could run",
+                count
+            )))
+        }
+        1 => {
+            return Err(crate::Error::from_message(
+                "deadline exceeded before 1 repo could run",
+            ))
+        }
+        count => {
+            return Err(crate::Error::from_message(format!(
+                "deadline exceeded before {} repos could run",
+                count
+            )))
+        }
+    }
+
+    let mut failures = Vec::new();
+    if args.fails_on(FailOn::RepoErrors) {
+        match block.error_count() {
+            0 => (),
+            1 => failures.push("1 repo failed".to_owned()),
+            count => failures.push(format!("{} repos failed", count)),
+        }
+    }
+    for (condition, singular, plural) in [
+        (
+            FailOn::GoneUpstream,
+            "has a gone upstream",
+            "have a gone upstream",
+        ),
+        (
+            FailOn::Dirty,
+            "has uncommitted changes",
+            "have uncommitted changes",
+        ),
+        (
+            FailOn::Behind,
+            "is behind its upstream",
+            "are behind their upstream",
+        ),
+    ] {
+        if args.fails_on(condition) {
+            match block.fail_on_count(condition) {
+                0 => (),
+                1 => failures.push(format!("1 repo {}", singular)),
+                count => failures.push(format!("{} repos {}", count, plural)),
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(crate::Error::from_message(failures.join(", ")))
+    }
+}
+
+/// Like `walk_with_output`, but adds lines to an existing block.
+pub fn walk_with_block<'out, 'block, C, B, U>(
+    args: &cli::Args,
+    block: &'block Block<'out>,
+    config: &Config,
+    target: &Target,
+    build: B,
+    update: U,
+) where
+    C: LineContent + 'out,
+    B: FnMut(&'block Block<'out>, &Entry) -> Line<'out, 'block, C>,
+    U: Fn(&Entry, &Line<'out, 'block, C>) + Sync,
+{
+    let mut errors = Vec::new();
+    let mut lines = walk_build(args, block, config, target, &mut errors, build);
+    walk_update(args, block, &mut lines, update);
+    add_discovery_errors(block, config, errors);
+}
+
+pub fn walk<F, G, H>(config: &Config, target: &Target, visit_repo: F, visit_dir: G, visit_err: H)
+where
+    F: FnMut(Entry),
+    G: FnMut(&Path),
+    H: FnMut(DiscoveryError),
+{
+    match target {
+        Target::Union(targets) => walk_union(config, targets, visit_repo, visit_dir, visit_err),
+        target => walk_target(config, target, visit_repo, visit_dir, visit_err),
+    }
+}
+
+fn walk_target<F, G, H>(
     config: &Config,
-    path: impl Into<PathBuf> + AsRef<Path>,
+    target: &Target,
     mut visit_repo: F,
     mut visit_dir: G,
     mut visit_err: H,
 ) where
     F: FnMut(Entry),
     G: FnMut(&Path),
-    H: FnMut(crate::Error),
+    H: FnMut(DiscoveryError),
 {
-    match git::Repository::try_open(path.as_ref()) {
+    let _timer = Timer::start(timing::Phase::Discovery);
+
+    if let Target::List(paths) = target {
+        return walk_list(config, paths, visit_repo, visit_err);
+    }
+
+    let path = target.path(config);
+    match git::Repository::try_open(path) {
         Ok(Some(repo)) => {
-            visit_repo(Entry::from_path(config, path.into(), repo));
+            if target.is_match(config.get_relative_path(path)) {
+                visit_repo(Entry::from_path(config, path.to_owned(), repo));
+            }
         }
         Ok(None) => {
+            let mut cache = DiscoveryCache::load(config);
             walk_inner(
                 config,
-                path.as_ref(),
+                target,
+                path,
+                &mut cache,
                 &mut visit_repo,
                 &mut visit_dir,
                 &mut visit_err,
             );
+            cache.save();
         }
         Err(err) => {
-            visit_err(err);
+            visit_err(DiscoveryError::new(path, err));
+        }
+    }
+}
+
+/// Like `walk_with_block`, but clusters repos under a header line for each group returned by
+/// `group`, instead of under the directory containing them. Groups are sorted by name.
+pub fn walk_grouped_with_block<'out, 'block, C, G, B, U>(
+    args: &cli::Args,
+    block: &'block Block<'out>,
+    config: &Config,
+    target: &Target,
+    mut group: G,
+    mut build: B,
+    update: U,
+) where
+    C: LineContent + 'out,
+    G: FnMut(&Entry) -> String,
+    B: FnMut(&'block Block<'out>, &Entry, &str) -> Line<'out, 'block, C>,
+    U: Fn(&Entry, &Line<'out, 'block, C>) + Sync,
+{
+    let mut groups = BTreeMap::<String, Vec<Entry>>::new();
+    let mut errors = Vec::new();
+
+    walk(
+        config,
+        target,
+        |repo| {
+            if !skip(args, block, &repo) {
+                groups.entry(group(&repo)).or_default().push(repo);
+            }
+        },
+        |_| (),
+        |err| errors.push(err),
+    );
+
+    block.set_keep_order();
+    let mut lines = Vec::new();
+    for (name, repos) in groups {
+        block.add_finished_line(GroupLineContent::new(&name));
+        for repo in repos {
+            let line = build(block, &repo, &name);
+            lines.push((repo, line));
+        }
+    }
+
+    walk_update(args, block, &mut lines, update);
+    add_discovery_errors(block, config, errors);
+}
+
+/// Like `walk_with_block`, but lays out repos as a tree mirroring the directory structure, instead
+/// of under a header for each directory. `build` is passed the label to show in place of the path of
+/// the repo.
+pub fn walk_tree_with_block<'out, 'block, C, B, U>(
+    args: &cli::Args,
+    block: &'block Block<'out>,
+    config: &Config,
+    target: &Target,
+    mut build: B,
+    update: U,
+) where
+    C: LineContent + 'out,
+    B: FnMut(&'block Block<'out>, &Entry, TreeLabel) -> Line<'out, 'block, C>,
+    U: Fn(&Entry, &Line<'out, 'block, C>) + Sync,
+{
+    let mut root = TreeNode::default();
+    let mut errors = Vec::new();
+
+    walk(
+        config,
+        target,
+        |repo| {
+            if !skip(args, block, &repo) {
+                root.insert(repo);
+            }
+        },
+        |_| (),
+        |err| errors.push(err),
+    );
+
+    let mut lines = Vec::new();
+    let mut visit_repo = |repo: Entry, label: TreeLabel| {
+        let line = build(block, &repo, label);
+        lines.push((repo, line));
+    };
+    match root.repo {
+        Some(repo) => visit_repo(
+            repo,
+            TreeLabel {
+                prefix: String::new(),
+                name: ".".to_owned(),
+            },
+        ),
+        None => block.add_finished_line(TreeDirectoryLineContent {
+            prefix: String::new(),
+            name: ".".to_owned(),
+        }),
+    }
+    add_tree_lines(block, root.children, "", &mut visit_repo);
+
+    walk_update(args, block, &mut lines, update);
+    add_discovery_errors(block, config, errors);
+}
+
+/// The text shown in place of the path of a repo in the tree shown by `walk_tree_with_block`.
+pub struct TreeLabel {
+    /// The lines connecting the repo to its directory.
+    pub prefix: String,
+    pub name: String,
+}
+
+/// A directory in the tree of repos shown by `walk_tree_with_block`.
+#[derive(Default)]
+struct TreeNode {
+    /// The repo at this path, if it is a repo.
+    repo: Option<Entry>,
+    children: BTreeMap<OsString, TreeNode>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, repo: Entry) {
+        let names: Vec<OsString> = repo.relative_path.iter().map(ToOwned::to_owned).collect();
+
+        let mut node = self;
+        for name in names {
+            node = node.children.entry(name).or_default();
+        }
+        node.repo = Some(repo);
+    }
+}
+
+/// Add lines for each node in `children`, and their children in turn, prefixed by `indent` and
+/// the lines connecting them to their parent.
+fn add_tree_lines(
+    block: &Block,
+    children: BTreeMap<OsString, TreeNode>,
+    indent: &str,
+    visit_repo: &mut dyn FnMut(Entry, TreeLabel),
+) {
+    let count = children.len();
+    for (index, (name, mut node)) in children.into_iter().enumerate() {
+        let mut name = PathBuf::from(name);
+        // Directories containing nothing but another directory are shown on one line, like `a/b`
+        while node.repo.is_none()
+            && node.children.len() == 1
+            && node.children.values().all(|child| child.repo.is_none())
+        {
+            let (child_name, child) = node.children.into_iter().next().unwrap();
+            name.push(child_name);
+            node = child;
+        }
+
+        let (connector, child_indent) = if index + 1 == count {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let prefix = format!("{}{}", indent, connector);
+        match node.repo {
+            Some(repo) => visit_repo(
+                repo,
+                TreeLabel {
+                    prefix,
+                    name: name.display().to_string(),
+                },
+            ),
+            None => block.add_finished_line(TreeDirectoryLineContent {
+                prefix,
+                name: name.display().to_string(),
+            }),
+        }
+
+        add_tree_lines(
+            block,
+            node.children,
+            &format!("{}{}", indent, child_indent),
+            visit_repo,
+        );
+    }
+}
+
+/// Walk each of `targets` in turn, visiting repos and directories reachable from more than one of
+/// them only once.
+fn walk_union<F, G, H>(
+    config: &Config,
+    targets: &[Target],
+    mut visit_repo: F,
+    mut visit_dir: G,
+    mut visit_err: H,
+) where
+    F: FnMut(Entry),
+    G: FnMut(&Path),
+    H: FnMut(DiscoveryError),
+{
+    let canonicalize = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+
+    let mut visited_repos = HashSet::new();
+    let mut visited_dirs = HashSet::new();
+    for target in targets {
+        walk_target(
+            config,
+            target,
+            |entry| {
+                if visited_repos.insert(canonicalize(&entry.path)) {
+                    visit_repo(entry);
+                }
+            },
+            |path| {
+                if visited_dirs.insert(canonicalize(path)) {
+                    visit_dir(path);
+                }
+            },
+            &mut visit_err,
+        );
+    }
+}
+
+fn walk_list<F, H>(
+    config: &Config,
+    paths: &[Result<PathBuf, String>],
+    mut visit_repo: F,
+    mut visit_err: H,
+) where
+    F: FnMut(Entry),
+    H: FnMut(DiscoveryError),
+{
+    for path in paths {
+        let path = match path {
+            Ok(path) => path,
+            Err(message) => {
+                visit_err(DiscoveryError {
+                    path: None,
+                    error: crate::Error::from_message(message),
+                });
+                continue;
+            }
+        };
+
+        match git::Repository::try_open(path) {
+            Ok(Some(repo)) => visit_repo(Entry::from_path(config, path.to_owned(), repo)),
+            Ok(None) => visit_err(DiscoveryError::new(
+                path,
+                crate::Error::from_message(format!("`{}` is not a repo", path.display())),
+            )),
+            Err(err) => visit_err(DiscoveryError::new(
+                path,
+                crate::Error::with_context(
+                    err,
+                    format!("failed to open repo at `{}`", path.display()),
+                ),
+            )),
         }
     }
 }
 
 fn walk_inner<F, G, H>(
     config: &Config,
+    target: &Target,
     path: &Path,
+    cache: &mut DiscoveryCache,
     visit_repo: &mut F,
     visit_dir: &mut G,
     visit_err: &mut H,
 ) where
     F: FnMut(Entry),
     G: FnMut(&Path),
-    H: FnMut(crate::Error),
+    H: FnMut(DiscoveryError),
 {
-    let entries = match fs::read_dir(path) {
-        Ok(entries) => entries,
-        Err(err) => {
-            return visit_err(crate::Error::with_context(
-                err,
-                format!("failed to read directory `{}`", path.display()),
-            ))
+    let names = match cache.get(path) {
+        Some(cached) => cached.subdirectories.clone(),
+        None => {
+            let modified = cache::modified(path);
+            let names = match read_subdirectories(config, path, visit_err) {
+                Some(names) => names,
+                None => return,
+            };
+            cache.insert(path, modified, false, names.clone());
+            names
         }
     };
 
     let mut repos = Vec::new();
     let mut subdirectories = Vec::new();
 
-    for entry in entries {
-        match entry {
-            Ok(entry) => {
-                let sub_path = entry.path();
-                let relative_path = config.get_relative_path(&sub_path);
-                let settings = config.settings(relative_path);
+    for name in names {
+        let sub_path = path.join(name);
+        let relative_path = config.get_relative_path(&sub_path);
+        let settings = config.settings(relative_path);
 
-                if settings.ignore == Some(true) {
-                    continue;
-                }
+        if settings.ignore == Some(true) {
+            continue;
+        }
 
-                match entry.file_type() {
-                    Ok(file_type) if file_type.is_dir() => {
-                        match git::Repository::try_open(&sub_path) {
-                            Ok(Some(repo)) => {
-                                let relative_path = relative_path.to_owned();
-                                repos.push(Entry::new(sub_path, relative_path, repo, settings));
-                            }
-                            Ok(None) => {
-                                subdirectories.push(sub_path);
-                            }
-                            Err(err) => visit_err(crate::Error::with_context(
-                                err,
-                                format!("failed to open repo at `{}`", sub_path.display()),
-                            )),
-                        }
-                    }
-                    Err(err) => visit_err(crate::Error::with_context(
-                        err,
-                        format!("failed to get metadata for `{}`", sub_path.display()),
-                    )),
-                    _ => (),
+        if let Some(cached) = cache.get(&sub_path) {
+            if !cached.is_repo {
+                subdirectories.push(sub_path);
+                continue;
+            }
+        }
+
+        let modified = cache::modified(&sub_path);
+        match git::Repository::try_open(&sub_path) {
+            Ok(Some(repo)) => {
+                cache.insert(&sub_path, modified, true, Vec::new());
+                if target.is_match(relative_path) {
+                    repos.push(Entry::new(config, sub_path, repo, settings));
                 }
             }
-            Err(err) => visit_err(crate::Error::with_context(
-                err,
-                format!("failed to read entry in `{}`", path.display()),
+            Ok(None) => {
+                subdirectories.push(sub_path);
+            }
+            Err(err) => visit_err(DiscoveryError::new(
+                &sub_path,
+                crate::Error::with_context(
+                    err,
+                    format!("failed to open repo at `{}`", sub_path.display()),
+                ),
             )),
         }
     }
@@ -138,14 +511,75 @@ fn walk_inner<F, G, H>(
     }
 
     for subdirectory in subdirectories {
-        walk_inner(config, &subdirectory, visit_repo, visit_dir, visit_err);
+        walk_inner(
+            config,
+            target,
+            &subdirectory,
+            cache,
+            visit_repo,
+            visit_dir,
+            visit_err,
+        );
+    }
+}
+
+/// Get the names of the directories within `path`, including ignored ones so the result can be
+/// cached independently of the settings. Returns `None` if the directory could not be read.
+fn read_subdirectories<H>(config: &Config, path: &Path, visit_err: &mut H) -> Option<Vec<PathBuf>>
+where
+    H: FnMut(DiscoveryError),
+{
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            visit_err(DiscoveryError::new(
+                path,
+                crate::Error::with_context(
+                    err,
+                    format!("failed to read directory `{}`", path.display()),
+                ),
+            ));
+            return None;
+        }
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => names.push(PathBuf::from(entry.file_name())),
+                Ok(_) => (),
+                Err(err) => {
+                    let sub_path = entry.path();
+                    if config.settings(config.get_relative_path(&sub_path)).ignore != Some(true) {
+                        visit_err(DiscoveryError::new(
+                            &sub_path,
+                            crate::Error::with_context(
+                                err,
+                                format!("failed to get metadata for `{}`", sub_path.display()),
+                            ),
+                        ))
+                    }
+                }
+            },
+            Err(err) => visit_err(DiscoveryError::new(
+                path,
+                crate::Error::with_context(
+                    err,
+                    format!("failed to read entry in `{}`", path.display()),
+                ),
+            )),
+        }
     }
+    Some(names)
 }
 
 fn walk_build<'out, 'block, C, B>(
+    args: &cli::Args,
     block: &'block Block<'out>,
     config: &Config,
-    path: impl Into<PathBuf> + AsRef<Path>,
+    target: &Target,
+    errors: &mut Vec<DiscoveryError>,
     mut build: B,
 ) -> Vec<(Entry, Line<'out, 'block, C>)>
 where
@@ -156,33 +590,73 @@ where
 
     walk(
         config,
-        path,
+        target,
         |repo| {
-            let line = build(block, &repo);
-            result.push((repo, line));
+            if !skip(args, block, &repo) {
+                let line = build(block, &repo);
+                result.push((repo, line));
+            }
         },
         |path| {
             block.add_finished_line(DirectoryLineContent::new(path));
         },
-        |err| {
-            block.add_error_line(err);
-        },
+        |err| errors.push(err),
     );
 
     result
 }
 
-fn walk_update<'out, 'block, C, U>(
+/// Add lines for errors encountered while searching for repos. These are collected and added
+/// together once every repo has finished, sorted by path, so they are not lost among repo lines.
+pub fn add_discovery_errors(block: &Block, config: &Config, mut errors: Vec<DiscoveryError>) {
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    for DiscoveryError { path, error } in errors {
+        block.add_finished_line(DiscoveryErrorLineContent {
+            relative_path: path.as_deref().map(|path| config.get_display_path(path)),
+            path,
+            error,
+        });
+    }
+}
+
+/// Check whether a repo should be skipped according to the command line arguments, and if so, add
+/// a line to the output explaining why.
+pub fn skip(args: &cli::Args, block: &Block, entry: &Entry) -> bool {
+    if args.skip_detached && entry.repo.is_detached() {
+        block.add_finished_line(SkippedLineContent::new(args, entry, "head is detached"));
+        return true;
+    }
+    if entry.settings.read_only == Some(true) && cli::writes_to_repos(&args.command) {
+        block.add_finished_line(SkippedLineContent::new(args, entry, "repo is read-only"));
+        return true;
+    }
+    if let cli::Command::Pull(pull_args) = &args.command {
+        if let Some(reason) = pull_args.skip_reason(&entry.repo) {
+            block.add_finished_line(SkippedLineContent::new(args, entry, reason));
+            return true;
+        }
+    }
+
+    false
+}
+
+pub fn walk_update<'out, 'block, T, C, U>(
     args: &cli::Args,
     block: &'block Block<'out>,
-    lines: &mut [(Entry, Line<'out, 'block, C>)],
+    lines: &mut [(T, Line<'out, 'block, C>)],
     update: U,
 ) where
+    T: WorkItem + Send,
     C: LineContent,
-    U: Fn(&Entry, &Line<'out, 'block, C>) + Sync,
+    U: Fn(&T, &Line<'out, 'block, C>) + Sync,
 {
+    let _timer = Timer::start(timing::Phase::Work);
+    let cancelled = &Mutex::new(Vec::new());
+
+    let jobs = args.jobs();
+    log::debug!("running with {} jobs", jobs);
     let thread_pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(args.jobs)
+        .num_threads(jobs)
         .thread_name(|index| format!("rayon-work-thread-{}", index))
         .build()
         .unwrap();
@@ -192,47 +666,110 @@ fn walk_update<'out, 'block, C, U>(
         block.update_all().ok();
         for (entry, line) in lines {
             scope.spawn_fifo(move |_| {
+                let reason = if block.is_aborted() {
+                    Some("aborted after a failure")
+                } else if interrupt::is_interrupted() {
+                    Some("interrupted")
+                } else if args.deadline_exceeded() {
+                    Some("deadline exceeded")
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    line.cancel();
+                    cancelled
+                        .lock()
+                        .unwrap()
+                        .push(SkippedLineContent::new(args, &*entry, reason));
+                    return;
+                }
+
                 update(&*entry, line);
                 line.finish();
             });
         }
     });
+
+    let mut cancelled = std::mem::take(&mut *cancelled.lock().unwrap());
+    cancelled.sort_by(|l, r| l.relative_path.cmp(&r.relative_path));
+    for content in cancelled {
+        block.add_finished_line(content);
+    }
+}
+
+/// An error encountered while searching for repos, such as a directory which could not be read.
+pub struct DiscoveryError {
+    /// The directory or repo which caused the error, if known.
+    path: Option<PathBuf>,
+    error: crate::Error,
+}
+
+impl DiscoveryError {
+    pub fn new(path: impl Into<PathBuf>, error: crate::Error) -> Self {
+        DiscoveryError {
+            path: Some(path.into()),
+            error,
+        }
+    }
+}
+
+/// An item processed by `walk_update`.
+pub trait WorkItem {
+    /// The absolute path of this item.
+    fn path(&self) -> &Path;
+    /// The path shown to the user for this item.
+    fn relative_path(&self) -> &Path;
+}
+
+impl WorkItem for Entry {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn relative_path(&self) -> &Path {
+        &self.relative_path
+    }
 }
 
 impl Entry {
-    fn new(
-        path: PathBuf,
-        relative_path: PathBuf,
-        repo: git::Repository,
-        settings: Settings,
-    ) -> Self {
+    fn new(config: &Config, path: PathBuf, repo: git::Repository, settings: Settings) -> Self {
         Entry {
+            relative_path: config.get_display_path(&path),
             path,
-            relative_path,
             settings,
             repo,
         }
     }
 
     fn from_path(config: &Config, path: PathBuf, repo: git::Repository) -> Self {
-        let relative_path = config.get_relative_path(&path).to_owned();
-        let settings = config.settings(&relative_path);
-        Entry::new(path, relative_path, repo, settings)
+        let settings = config.settings(config.get_relative_path(&path));
+        Entry::new(config, path, repo, settings)
+    }
+}
+
+/// Get the path to show alongside the output for a repo at `relative_path`, which is `.` for the
+/// root rather than an empty path.
+pub fn display_path(relative_path: &Path) -> &Path {
+    if relative_path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        relative_path
     }
 }
 
-struct DirectoryLineContent {
+/// A header line showing the path of a directory.
+pub struct DirectoryLineContent {
     path: PathBuf,
 }
 
 impl DirectoryLineContent {
-    fn new(path: impl Into<PathBuf>) -> Self {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
         DirectoryLineContent { path: path.into() }
     }
 }
 
 impl LineContent for DirectoryLineContent {
-    fn write(&self, stdout: &mut io::StdoutLock) -> crossterm::Result<()> {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
         crossterm::queue!(
             stdout,
             SetForegroundColor(Color::Yellow),
@@ -244,7 +781,7 @@ impl LineContent for DirectoryLineContent {
         Ok(())
     }
 
-    fn write_json(&self, stdout: &mut io::StdoutLock) -> serde_json::Result<()> {
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
         #[derive(Serialize)]
         #[serde(tag = "kind", rename_all = "snake_case")]
         enum JsonDirectory {
@@ -252,10 +789,183 @@ impl LineContent for DirectoryLineContent {
         }
 
         serde_json::to_writer(
-            stdout,
+            writer,
             &JsonDirectory::Directory {
                 path: self.path.display().to_string(),
             },
         )
     }
 }
+
+/// A line for a directory in the tree shown by `walk_tree_with_block`.
+struct TreeDirectoryLineContent {
+    /// The lines connecting the directory to its parent.
+    prefix: String,
+    name: String,
+}
+
+impl LineContent for TreeDirectoryLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        write!(stdout, "{}", self.prefix)?;
+        crossterm::queue!(
+            stdout,
+            SetForegroundColor(Color::Yellow),
+            SetAttribute(Attribute::Underlined)
+        )?;
+        write!(stdout, "{}", self.name)?;
+        stdout.flush()?;
+        crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        DirectoryLineContent::new(&self.name).write_json(writer)
+    }
+}
+
+struct GroupLineContent {
+    name: String,
+}
+
+impl GroupLineContent {
+    fn new(name: impl Into<String>) -> Self {
+        GroupLineContent { name: name.into() }
+    }
+}
+
+impl LineContent for GroupLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        crossterm::queue!(
+            stdout,
+            SetForegroundColor(Color::Yellow),
+            SetAttribute(Attribute::Underlined)
+        )?;
+        write!(stdout, "{}", self.name)?;
+        stdout.flush()?;
+        crossterm::queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonGroup<'a> {
+            Group { group: &'a str },
+        }
+
+        serde_json::to_writer(writer, &JsonGroup::Group { group: &self.name })
+    }
+}
+
+struct SkippedLineContent {
+    path: PathBuf,
+    relative_path: PathBuf,
+    max_path_width: Option<usize>,
+    reason: &'static str,
+}
+
+impl SkippedLineContent {
+    fn new(args: &cli::Args, item: &impl WorkItem, reason: &'static str) -> Self {
+        SkippedLineContent {
+            path: item.path().to_owned(),
+            relative_path: item.relative_path().to_owned(),
+            max_path_width: args.max_path_width,
+            reason,
+        }
+    }
+}
+
+impl LineContent for SkippedLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        let (cols, _) = terminal::size()?;
+
+        output::write_path_column(stdout, &self.relative_path, cols, self.max_path_width)?;
+        crossterm::queue!(stdout, SetForegroundColor(Color::Grey))?;
+        write!(stdout, "skipped: {}", self.reason)?;
+        stdout.flush()?;
+        crossterm::queue!(stdout, ResetColor)?;
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonSkipped<'a> {
+            Skipped {
+                path: String,
+                absolute_path: String,
+                reason: &'a str,
+            },
+        }
+
+        serde_json::to_writer(
+            writer,
+            &JsonSkipped::Skipped {
+                path: self.relative_path.display().to_string(),
+                absolute_path: self.path.display().to_string(),
+                reason: self.reason,
+            },
+        )
+    }
+
+    fn write_porcelain(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        write!(
+            writer,
+            "{}\tskipped\t{}",
+            self.relative_path.display(),
+            self.reason
+        )
+    }
+}
+
+struct DiscoveryErrorLineContent {
+    path: Option<PathBuf>,
+    relative_path: Option<PathBuf>,
+    error: crate::Error,
+}
+
+impl LineContent for DiscoveryErrorLineContent {
+    fn write(&self, stdout: &mut output::Writer) -> crossterm::Result<()> {
+        // The error message already includes the full path
+        self.error.write(stdout)
+    }
+
+    fn write_json(&self, writer: &mut dyn io::Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum JsonDiscoveryError<'a> {
+            Error {
+                #[serde(skip_serializing_if = "Option::is_none")]
+                path: Option<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                absolute_path: Option<String>,
+                #[serde(flatten)]
+                error: &'a crate::Error,
+            },
+        }
+
+        serde_json::to_writer(
+            writer,
+            &JsonDiscoveryError::Error {
+                path: self
+                    .relative_path
+                    .as_ref()
+                    .map(|path| path.display().to_string()),
+                absolute_path: self.path.as_ref().map(|path| path.display().to_string()),
+                error: &self.error,
+            },
+        )
+    }
+
+    fn write_porcelain(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        if let Some(relative_path) = &self.relative_path {
+            write!(writer, "{}", relative_path.display())?;
+        }
+        write!(writer, "\terror\t")?;
+        self.error.write_plain(writer)
+    }
+
+    fn is_error(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,235 @@
+mod setup;
+
+use std::process::{Command as StdCommand, Stdio};
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn clone_alias_without_aliases_table() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/clone.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    let upstream = context.temp_dir().child("upstream");
+
+    for (name, alias) in [("one", "first"), ("two", "second")] {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .env("MULTIGIT_CONFIG_PATH", config.path())
+            .arg("--json")
+            .arg("clone")
+            .arg("--name")
+            .arg(name)
+            .arg("--alias")
+            .arg(alias)
+            .arg(upstream.path())
+            .current_dir(context.working_dir())
+            .assert()
+            .success();
+    }
+
+    for (name, alias) in [("one", "first"), ("two", "second")] {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .env("MULTIGIT_CONFIG_PATH", config.path())
+            .arg("resolve")
+            .arg(alias)
+            .current_dir(context.working_dir())
+            .assert()
+            .success()
+            .stdout(predicate::str::ends_with(format!("{}\n", name)));
+    }
+
+    config.assert(predicate::str::contains(
+        "[aliases]\nfirst = \"one\"\nsecond = \"two\"\n",
+    ));
+}
+
+#[test]
+fn clone_alias_concurrent() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/clone.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    let upstream = context.temp_dir().child("upstream");
+
+    let children: Vec<_> = (0..8)
+        .map(|index| {
+            StdCommand::new(assert_cmd::cargo::cargo_bin("mgit"))
+                .env("MULTIGIT_CONFIG_PATH", config.path())
+                .arg("--json")
+                .arg("clone")
+                .arg("--name")
+                .arg(format!("repo{}", index))
+                .arg("--alias")
+                .arg(format!("alias{}", index))
+                .arg(upstream.path())
+                .current_dir(context.working_dir())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    for index in 0..8 {
+        config.assert(predicate::str::contains(format!(
+            "alias{} = \"repo{}\"",
+            index, index
+        )));
+    }
+}
+
+#[test]
+fn clone_track_all() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/clone_branches.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    let upstream = context.temp_dir().child("upstream");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("clone")
+        .arg("--track-all")
+        .arg(upstream.path())
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"created 2 tracking branches"}"#,
+        ));
+
+    let repo = git2::Repository::open(context.working_dir().join("upstream")).unwrap();
+    for name in ["topic", "feature/nested"] {
+        let branch = repo.find_branch(name, git2::BranchType::Local).unwrap();
+        assert_eq!(
+            branch.upstream().unwrap().name().unwrap(),
+            Some(format!("origin/{}", name).as_str())
+        );
+    }
+}
+
+#[test]
+fn clone_mirror() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/clone_branches.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    let upstream = context.temp_dir().child("upstream");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("clone")
+        .arg("--mirror")
+        .arg(upstream.path())
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    let repo = git2::Repository::open(context.working_dir().join("upstream.git")).unwrap();
+    assert!(repo.is_bare());
+    assert_eq!(repo.head().unwrap().name(), Some("refs/heads/main"));
+    for name in ["main", "topic", "feature/nested"] {
+        repo.find_branch(name, git2::BranchType::Local).unwrap();
+    }
+    assert!(repo.find_reference("refs/remotes/origin/HEAD").is_err());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("status")
+        .arg("--fetch")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""path":"upstream.git","#))
+        .stdout(predicate::str::contains(
+            r#""head":{"name":"main","kind":"branch"}"#,
+        ));
+}
+
+#[test]
+fn clone_filter() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/clone.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    let upstream = context.temp_dir().child("upstream");
+    git2::Repository::open(upstream.path())
+        .unwrap()
+        .config()
+        .unwrap()
+        .set_bool("uploadpack.allowFilter", true)
+        .unwrap();
+
+    // Local clones ignore the filter unless the remote is given as a url
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("clone")
+        .arg("--filter")
+        .arg("blob:none")
+        .arg(url::Url::from_file_path(upstream.path()).unwrap().as_str())
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    let repo = git2::Repository::open(context.working_dir().join("upstream")).unwrap();
+    assert_eq!(repo.head().unwrap().name(), Some("refs/heads/main"));
+    assert_eq!(
+        repo.config()
+            .unwrap()
+            .get_string("remote.origin.partialclonefilter")
+            .unwrap(),
+        "blob:none"
+    );
+}
+
+#[test]
+fn clone_filter_invalid() {
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("clone")
+        .arg("--filter")
+        .arg("sparse:oid=abc")
+        .arg("https://host.xz/repo.git")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid filter `sparse:oid=abc`"));
+}
@@ -0,0 +1,118 @@
+mod setup;
+
+use std::process;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+#[test]
+fn commit_sign_off() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("commit")
+        .arg("--message")
+        .arg("Update {path} on {branch}")
+        .arg("--sign-off")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"commit","path":"","state":"committed","oid":"*"}"#,
+        ));
+
+    let log = process::Command::new("git")
+        .args(["log", "-1", "--format=%B%n%an <%ae>", "--name-only"])
+        .current_dir(context.working_dir())
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(log.stdout).unwrap(),
+        "Update  on main\n\nSigned-off-by: Test User <test@example.com>\n\nTest User <test@example.com>\n\nfile.txt\n"
+    );
+}
+
+#[test]
+fn commit_path_relative_to() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("--json")
+        .arg("--relative-to")
+        .arg("absolute")
+        .arg("commit")
+        .arg("--message")
+        .arg("Update {path}")
+        .current_dir(context.temp_dir().path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            r#"{{"kind":"commit","path":{},"state":"committed","oid":"#,
+            serde_json::to_string(&context.working_dir().display().to_string()).unwrap()
+        )));
+
+    // The message always uses the path relative to the root
+    let log = process::Command::new("git")
+        .args(["log", "-1", "--format=%s"])
+        .current_dir(context.working_dir())
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(log.stdout).unwrap(), "Update repo\n");
+}
+
+#[test]
+fn commit_nothing_to_commit() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+
+    for expected in [
+        r#"{"kind":"commit","path":"","state":"committed","oid":"*"}"#,
+        r#"{"kind":"commit","path":"","state":"nothing_to_commit"}"#,
+    ] {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .arg("--json")
+            .arg("commit")
+            .arg("--message")
+            .arg("Update")
+            .current_dir(context.working_dir())
+            .assert()
+            .success()
+            .stdout(output_pred(expected));
+    }
+}
+
+#[test]
+fn commit_pathspec() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("commit")
+        .arg("--message")
+        .arg("Update")
+        .arg("--")
+        .arg("other.txt")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"commit","path":"","state":"nothing_to_commit"}"#,
+        ));
+}
+
+fn output_pred(expected: &str) -> impl Predicate<[u8]> {
+    let regex = format!(
+        "^{}$",
+        regex::escape(&expected.replace("*", "__WILDCARD__")).replace("__WILDCARD__", ".*")
+    );
+
+    predicates::str::is_match(&regex)
+        .unwrap()
+        .trim()
+        .from_utf8()
+}
@@ -0,0 +1,74 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn nested_show_profile() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            concat!(
+                "root = {}\n",
+                "prune = true\n",
+                "ssh = {{ username = \"git\", passphrase = \"secret\" }}\n",
+                "[aliases]\n",
+                "one = \"a/one\"\n",
+                "[settings]\n",
+                "\"b/*\" = {{ unset = [\"prune\"] }}\n",
+                "[profiles.work]\n",
+                "default-branch = \"develop\"\n",
+                "aliases = {{ two = \"a/two\" }}\n",
+            ),
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    // The profile is applied, and the SSH passphrase is left out
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("--profile")
+        .arg("work")
+        .arg("config")
+        .arg("show")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(
+                r#""default-shell":"bash","default-branch":"develop","ssh":{"username":"git"},"prune":true,"aliases":{"one":"a/one","two":"a/two"},"settings":{"b/*":{"unset":["prune"]}}}"#,
+            )
+            .and(predicate::str::starts_with(r#"{"kind":"config","root":"#))
+            .and(predicate::str::contains("secret").not()),
+        );
+}
+
+#[test]
+fn nested_show_toml() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\ndefault-shell = \"cmd\"\n\n[settings]\n\"b/*\" = {{ prune = false }}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("config")
+        .arg("show")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("default-shell = \"cmd\"\n").and(
+            predicate::str::contains("[settings.\"b/*\"]\nprune = false\n"),
+        ));
+}
@@ -0,0 +1,895 @@
+mod setup;
+
+use std::path::Path;
+
+use assert_cmd::Command;
+use assert_fs::{prelude::*, TempDir};
+use predicates::prelude::*;
+
+#[test]
+fn exec_check_invalid_syntax() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--check")
+        .arg("--shell")
+        .arg("bash")
+        .arg("touch ran.txt; if then")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("command has invalid syntax"));
+
+    context
+        .temp_dir()
+        .child("ran.txt")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn exec_check_valid_syntax() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--check")
+        .arg("--shell")
+        .arg("bash")
+        .arg("touch ran.txt")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(with_absolute_path(
+            concat!(
+                r#"{"kind":"exec","path":"","absolute_path":"*","code":0}"#,
+                "\n",
+                r#"{"kind":"message","message":"ran in 1 repo: 1 succeeded, 0 failed"}"#,
+                "\n",
+            ),
+            context.working_dir(),
+        ));
+
+    context
+        .temp_dir()
+        .child("ran.txt")
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn exec_failure_summary() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("exit 3")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(with_absolute_path(
+            concat!(
+                r#"{"kind":"exec","path":"","absolute_path":"*","code":3}"#,
+                "\n",
+                r#"{"kind":"message","message":"ran in 1 repo: 0 succeeded, 1 failed"}"#,
+                "\n",
+                r#"{"kind":"error","message":"1 repo failed","source":null}"#,
+                "\n",
+            ),
+            context.working_dir(),
+        ));
+}
+
+#[test]
+fn exec_retries() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--retries")
+        .arg("2")
+        .arg("--retry-delay")
+        .arg("1s")
+        .arg("test -e attempted || { touch attempted; exit 1; }")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(with_absolute_path(
+            concat!(
+                r#"{"kind":"exec","path":"","absolute_path":"*","code":0,"attempts":2}"#,
+                "\n",
+                r#"{"kind":"message","message":"ran in 1 repo: 1 succeeded (1 after retrying), 0 failed"}"#,
+                "\n",
+            ),
+            context.working_dir(),
+        ));
+}
+
+#[test]
+fn exec_chdir_root() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--chdir-root")
+        .arg("--shell")
+        .arg("bash")
+        .arg(r#"echo "$MULTIGIT_REPOS" > repos.txt"#)
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(with_absolute_path(
+            r#"{"kind":"exec","path":"a","absolute_path":"*","code":0}"#,
+            &context.working_dir().join("a"),
+        )))
+        .stdout(predicate::str::contains(with_absolute_path(
+            r#"{"kind":"exec","path":"b","absolute_path":"*","code":0}"#,
+            &context.working_dir().join("b"),
+        )))
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"ran in 2 directories: 2 succeeded, 0 failed"}"#,
+        ));
+
+    // Each directory is passed the repos directly within it
+    let repos = |dir: &str| {
+        let repos =
+            fs_err::read_to_string(context.working_dir().join(dir).join("repos.txt")).unwrap();
+        let mut repos: Vec<_> = std::env::split_paths(repos.trim_end()).collect();
+        repos.sort();
+        repos
+    };
+    let a = context.working_dir().join("a");
+    assert_eq!(repos("a"), vec![a.join("one"), a.join("two")]);
+    let b = context.working_dir().join("b");
+    assert_eq!(repos("b"), vec![b.join("three")]);
+}
+
+#[test]
+fn exec_cwd_root() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--cwd")
+        .arg("root")
+        .arg("--shell")
+        .arg("bash")
+        .arg(r#"echo "$@" > repos.txt"#)
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(with_absolute_path(
+            concat!(
+                r#"{"kind":"exec","path":"","absolute_path":"*","code":0}"#,
+                "\n",
+                r#"{"kind":"message","message":"ran in 1 directory: 1 succeeded, 0 failed"}"#,
+                "\n",
+            ),
+            context.working_dir(),
+        ));
+
+    let repos = fs_err::read_to_string(context.working_dir().join("repos.txt")).unwrap();
+    assert_eq!(repos, "a/one a/two b/three\n");
+}
+
+#[test]
+fn exec_deadline_kills_command() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--deadline")
+        .arg("1s")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("sleep 30")
+        .current_dir(context.working_dir())
+        .timeout(std::time::Duration::from_secs(20))
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(with_absolute_path(
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"deadline exceeded, so the command was killed","source":null}"#,
+            context.working_dir(),
+        )));
+}
+
+#[test]
+fn exec_on_error_abort() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--jobs")
+        .arg("1")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--on-error")
+        .arg("abort")
+        .arg("exit 1")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"error","message":"aborted after a failure, before 2 repos could run","source":null}"#,
+        ))
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    assert_eq!(stdout.matches(r#""code":1"#).count(), 1);
+    assert_eq!(
+        stdout
+            .matches(r#""reason":"aborted after a failure""#)
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn exec_only_errors() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--only-errors")
+        .arg("case $PWD in */two) exit 1;; esac")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"ran in 3 repos: 2 succeeded, 1 failed"}"#,
+        ))
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    let records: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|record: &serde_json::Value| record["kind"] == "exec")
+        .collect();
+    assert_eq!(records.len(), 1, "{}", stdout);
+    assert_eq!(records[0]["path"], "a/two");
+    assert_eq!(records[0]["code"], 1);
+}
+
+#[test]
+fn exec_on_error_prompt_requires_terminal() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("exec")
+        .arg("--on-error")
+        .arg("prompt")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "`--on-error prompt` requires an interactive terminal",
+        ));
+}
+
+#[test]
+fn exec_keep_order() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--keep-order")
+        .arg("--jobs")
+        .arg("3")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("case $PWD in */one) sleep 1;; esac")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    // Each repo should follow the directory containing it, even though `a/one` finishes last
+    let stdout = String::from_utf8(output).unwrap();
+    let mut directory = None;
+    for line in stdout.lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        let path = record["path"].as_str().unwrap_or_default();
+        match record["kind"].as_str().unwrap() {
+            "directory" => directory = Path::new(path).file_name().map(ToOwned::to_owned),
+            "exec" => assert!(
+                Path::new(path).starts_with(directory.as_ref().unwrap()),
+                "{}",
+                stdout
+            ),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn exec_prefix_json() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--prefix")
+        .arg("echo one; echo two >&2; exit 3")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(with_absolute_path(
+            r#"{"kind":"exec","path":"","absolute_path":"*","code":3,"output":["#,
+            context.working_dir(),
+        )))
+        .stdout(predicate::str::contains(r#""one""#))
+        .stdout(predicate::str::contains(r#""two""#));
+}
+
+#[test]
+#[cfg(unix)]
+fn nested_exec_interrupted() {
+    use std::process::{self, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let start = Instant::now();
+    let child = process::Command::new(assert_cmd::cargo::cargo_bin("mgit"))
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("sleep 30")
+        .current_dir(context.working_dir())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    thread::sleep(Duration::from_secs(1));
+    let status = process::Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The commands are killed, rather than left to finish
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(r#""message":"interrupted, so the command was killed""#));
+    assert!(stdout.ends_with("{\"kind\":\"error\",\"message\":\"interrupted\",\"source\":null}\n"));
+}
+
+#[test]
+#[cfg(unix)]
+fn exec_retry_delay_interrupted() {
+    use std::process::{self, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let child = process::Command::new(assert_cmd::cargo::cargo_bin("mgit"))
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--retries")
+        .arg("1")
+        .arg("--retry-delay")
+        .arg("60s")
+        .arg("exit 1")
+        .current_dir(context.working_dir())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    thread::sleep(Duration::from_secs(1));
+    let status = process::Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The wait for the retry is cut short, and the failure from the first attempt is reported
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(r#""code":1"#), "{}", stdout);
+}
+
+#[test]
+fn exec_retry_delay_requires_retries() {
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("exec")
+        .arg("--retry-delay")
+        .arg("1s")
+        .arg("true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--retries <COUNT>"));
+}
+
+#[test]
+fn nested_exec_env() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nenv = {{ NAME = \"default\", KIND = \"default\" }}\n\n[settings]\n\"b/*\" = {{ env = {{ NAME = \"b\" }} }}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("--keep-order")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--prefix")
+        .arg("--env")
+        .arg("KIND=cli")
+        .arg("echo $NAME $KIND")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""path":"a/one","#))
+        .stdout(predicate::str::contains(r#""output":["default cli"]"#))
+        .stdout(predicate::str::contains(r#""output":["b cli"]"#));
+}
+
+#[test]
+fn exec_tty() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--jobs")
+        .arg("1")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--prefix")
+        .arg("--tty")
+        .arg("if [ -t 1 ]; then echo tty; else echo pipe; fi; exit 2")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(with_absolute_path(
+            r#"{"kind":"exec","path":"","absolute_path":"*","code":2,"output":["tty"]}"#,
+            context.working_dir(),
+        )));
+}
+
+#[test]
+fn exec_tty_requires_one_job() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--jobs")
+        .arg("2")
+        .arg("exec")
+        .arg("--prefix")
+        .arg("--tty")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "`--tty` requires commands to run one at a time (pass `--jobs 1`)",
+        ));
+}
+
+#[test]
+fn exec_tty_without_prefix_requires_interactive_output() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--jobs")
+        .arg("1")
+        .arg("exec")
+        .arg("--tty")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "`--tty` requires interactive output, unless `--prefix` is passed",
+        ));
+}
+
+#[test]
+fn exec_print_path_header_requires_prefix() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--print-path-header")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--prefix"));
+}
+
+#[test]
+fn exec_where() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--where")
+        .arg("behind > 0 && !dirty")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(with_absolute_path(
+            r#"{"kind":"exec","path":"","absolute_path":"*","code":0}"#,
+            context.working_dir(),
+        )));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--where")
+        .arg("ahead > 0 || gone")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            r#"{"kind":"message","message":"ran in 0 repos: 0 succeeded, 0 failed"}
+"#,
+        );
+}
+
+#[test]
+fn exec_where_invalid() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--where")
+        .arg("behind > 0 && unknown")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "unknown field `unknown` in predicate (expected one of `ahead`, `behind`, `dirty`, `detached`, `gone`)",
+        ));
+}
+
+#[test]
+fn exec_require_clean() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/working_tree_changed.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--require-clean")
+        .arg("touch ran.txt")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(with_absolute_path(
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"working tree is dirty"}"#,
+            context.working_dir(),
+        )));
+
+    context
+        .temp_dir()
+        .child("ran.txt")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn exec_skip_if_newer_than() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let data_dir = TempDir::new().unwrap();
+
+    let exec = |command: &[&str]| {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .env("XDG_DATA_HOME", data_dir.path())
+            .arg("--json")
+            .arg("exec")
+            .arg("--skip-if-newer-than")
+            .arg("1d")
+            .args(command)
+            .current_dir(context.working_dir())
+            .assert()
+            .success()
+    };
+
+    exec(&["true"]).stdout(predicate::str::contains(
+        r#"{"kind":"message","message":"ran in 3 repos: 3 succeeded, 0 failed"}"#,
+    ));
+    data_dir
+        .child("multi-git/exec-history.json")
+        .assert(predicate::path::exists());
+
+    exec(&["true"])
+        .stdout(predicate::str::contains(with_absolute_path(
+            r#"{"kind":"skipped","path":"a/one","absolute_path":"*","reason":"ran recently"}"#,
+            &context.working_dir().join("a").join("one"),
+        )))
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"ran in 0 repos: 0 succeeded, 0 failed, 3 skipped"}"#,
+        ));
+
+    // Times are recorded separately for each command
+    exec(&["exit 0"]).stdout(predicate::str::contains(
+        r#"{"kind":"message","message":"ran in 3 repos: 3 succeeded, 0 failed"}"#,
+    ));
+
+    // Commands are told apart by their arguments, not just the text of the command line
+    exec(&[".", "echo", "a b"]).stdout(predicate::str::contains(
+        r#"{"kind":"message","message":"ran in 3 repos: 3 succeeded, 0 failed"}"#,
+    ));
+    exec(&[".", "echo", "a", "b"]).stdout(predicate::str::contains(
+        r#"{"kind":"message","message":"ran in 3 repos: 3 succeeded, 0 failed"}"#,
+    ));
+}
+
+/// Replace the `"absolute_path":"*"` placeholder in `expected` with the JSON-encoded `path`.
+fn with_absolute_path(expected: &str, path: &Path) -> String {
+    expected.replace(
+        r#""absolute_path":"*""#,
+        &format!(
+            r#""absolute_path":{}"#,
+            serde_json::to_string(&path.display().to_string()).unwrap()
+        ),
+    )
+}
+
+#[test]
+fn exec_multiple_targets() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--shell")
+        .arg("none")
+        .arg("a/one")
+        .arg("b/*")
+        .arg("a/*")
+        .arg("--")
+        .arg("git")
+        .arg("rev-parse")
+        .arg("--")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(r#""path":"a/one""#)
+                .count(1)
+                .and(predicate::str::contains(r#""path":"a/two""#).count(1))
+                .and(predicate::str::contains(r#""path":"b/three""#).count(1))
+                .and(predicate::str::contains(
+                    r#"{"kind":"message","message":"ran in 3 repos: 3 succeeded, 0 failed"}"#,
+                )),
+        );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("exec")
+        .arg("a/one")
+        .arg("b/three")
+        .arg("--")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("no command was passed after `--`"));
+}
+
+#[test]
+fn exec_keep_going() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--jobs")
+        .arg("1")
+        .arg("exec")
+        .arg("--shell")
+        .arg("bash")
+        .arg("--keep-going")
+        .arg("exit 1")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::contains(r#""code":1"#)
+                .count(3)
+                .and(predicate::str::contains(
+                    r#"{"kind":"message","message":"ran in 3 repos: 0 succeeded, 3 failed"}"#,
+                )),
+        );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("exec")
+        .arg("--keep-going")
+        .arg("--on-error")
+        .arg("abort")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn exec_cwd_dir() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("exec")
+        .arg("--cwd")
+        .arg("dir")
+        .arg("--shell")
+        .arg("bash")
+        .arg(r#"echo "$MULTIGIT_REPOS" > repos.txt"#)
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"ran in 2 directories: 2 succeeded, 0 failed"}"#,
+        ));
+
+    for dir in ["a", "b"] {
+        assert!(context.working_dir().join(dir).join("repos.txt").exists());
+    }
+
+    // Directories whose repos are all skipped are left out
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n\n[settings]\n\"b/*\" = {{ read-only = true }}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("exec")
+        .arg("--cwd")
+        .arg("dir")
+        .arg("--shell")
+        .arg("bash")
+        .arg(r#"echo "$MULTIGIT_REPOS" > skipped.txt"#)
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"message","message":"ran in 1 directory: 1 succeeded, 0 failed"}"#,
+        ));
+    assert!(context.working_dir().join("a/skipped.txt").exists());
+    assert!(!context.working_dir().join("b/skipped.txt").exists());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("exec")
+        .arg("--cwd")
+        .arg("root")
+        .arg("--require-clean")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "`--require-clean` can only be used with `--cwd repo`",
+        ));
+}
+
+#[test]
+fn exec_on_error_prompt_requires_terminal_output() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+    let output_file = context.temp_dir().child("output.txt");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--output")
+        .arg(output_file.path())
+        .arg("exec")
+        .arg("--on-error")
+        .arg("prompt")
+        .arg("true")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure();
+
+    output_file.assert(predicate::str::contains(
+        "`--on-error prompt` requires an interactive terminal",
+    ));
+}
+
+#[test]
+fn exec_prefix_deadline_background_process() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    // The background process keeps the output open after the shell is killed
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--deadline")
+        .arg("1s")
+        .arg("exec")
+        .arg("--prefix")
+        .arg("--shell")
+        .arg("bash")
+        .arg("sleep 30 & sleep 30")
+        .current_dir(context.working_dir())
+        .timeout(std::time::Duration::from_secs(20))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "deadline exceeded, so the command was killed",
+        ));
+}
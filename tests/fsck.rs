@@ -0,0 +1,60 @@
+mod setup;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+const EMPTY_TREE: &str = ".git/objects/4b/825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+#[test]
+fn commit_fsck() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("fsck")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(concat!(
+            r#"{"kind":"fsck","path":"","code":0,"problems":[]}"#,
+            "\n",
+            r#"{"kind":"message","message":"checked 1 repo: 1 clean, 0 with errors"}"#,
+        )));
+}
+
+#[test]
+fn commit_fsck_missing_object() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/commit.setup").unwrap());
+    fs_err::remove_file(context.working_dir().join(EMPTY_TREE)).unwrap();
+
+    for args in [&[][..], &["--connectivity-only"]] {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .arg("--json")
+            .arg("fsck")
+            .args(args)
+            .current_dir(context.working_dir())
+            .assert()
+            .failure()
+            .stdout(output_pred(concat!(
+                r#"{"kind":"fsck","path":"","code":*,"problems":[*"missing tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904"*]}"#,
+                "\n",
+                r#"{"kind":"message","message":"checked 1 repo: 0 clean, 1 with errors"}"#,
+                "\n",
+                r#"{"kind":"error","message":"1 repo failed","source":null}"#,
+            )));
+    }
+}
+
+fn output_pred(expected: &str) -> impl Predicate<[u8]> {
+    let regex = format!(
+        "^{}$",
+        regex::escape(&expected.replace("*", "__WILDCARD__")).replace("__WILDCARD__", ".*")
+    );
+
+    predicates::str::is_match(&regex)
+        .unwrap()
+        .trim()
+        .from_utf8()
+}
@@ -0,0 +1,68 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn upstream_gone_many() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_gone_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("gone")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"kind":"gone","path":"","branches":[{"name":"current","state":"gone"},{"name":"merged","state":"gone"},{"name":"unmerged","state":"gone"}]}"#,
+            "\n"
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/refs/heads/merged")
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn upstream_gone_many_delete() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_gone_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("gone")
+        .arg("--delete")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"kind":"gone","path":"","branches":[{"name":"current","state":"checked_out"},{"name":"merged","state":"deleted"},{"name":"unmerged","state":"unmerged"}]}"#,
+            "\n"
+        ));
+
+    let refs = context.temp_dir().child("local/.git/refs/heads");
+    refs.child("merged").assert(predicate::path::missing());
+    refs.child("unmerged").assert(predicate::path::exists());
+    refs.child("current").assert(predicate::path::exists());
+    refs.child("kept").assert(predicate::path::exists());
+}
+
+#[test]
+fn upstream_no_gone_branches() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("gone")
+        .arg("--delete")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(concat!(r#"{"kind":"gone","path":"","branches":[]}"#, "\n"));
+}
@@ -0,0 +1,80 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn nested_init() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let config = context.temp_dir().child("config/mgit.toml");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("--json")
+        .arg("init")
+        .arg("--path")
+        .arg(config.path())
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            r#"{{"kind":"message","message":"  export MULTIGIT_CONFIG_PATH=\"{}\""}}"#,
+            config.path().display()
+        )));
+    config.assert(predicate::str::contains("\n[aliases]\n"));
+
+    // The starter config can be loaded, and uses the current directory as the root
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("resolve")
+        .arg("a/one")
+        .current_dir(context.temp_dir().path())
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            context.working_dir().join("a/one").display()
+        ));
+}
+
+#[test]
+fn nested_init_exists() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let config = context.temp_dir().child("mgit.toml");
+    config.write_str("root = \"/\"\n").unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("init")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "already exists (pass --force to overwrite it)",
+        ));
+    config.assert("root = \"/\"\n");
+
+    // The export line is not needed when the config is already at MULTIGIT_CONFIG_PATH
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("init")
+        .arg("--force")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            serde_json::json!({
+                "kind": "message",
+                "message": format!("created config file `{}`", config.path().display()),
+            })
+        ));
+    config.assert(predicate::str::contains("root = "));
+}
@@ -0,0 +1,218 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn log_dir_from_config() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+    log_dir.create_dir_all().unwrap();
+    log_dir.child("old.log").touch().unwrap();
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nlog-dir = {}\nlog-retention-days = 0\n",
+            toml::Value::String(context.working_dir().display().to_string()),
+            toml::Value::String(log_dir.path().display().to_string()),
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .env_remove("MGIT_LOG_DIR")
+        .arg("-v")
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    log_dir.child("old.log").assert(predicate::path::missing());
+    assert_eq!(fs_err::read_dir(log_dir.path()).unwrap().count(), 1);
+
+    // Messages logged while parsing the config are written once the log file is opened
+    let log_file = fs_err::read_dir(log_dir.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let log = fs_err::read_to_string(log_file.path()).unwrap();
+    assert!(log.contains(
+        &format!("Reading config from `{}`", config.path().display()).replace('\\', "\\\\")
+    ));
+}
+
+#[test]
+fn log_retention_days_too_large() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nlog-dir = {}\nlog-retention-days = {}\n",
+            toml::Value::String(context.working_dir().display().to_string()),
+            toml::Value::String(log_dir.path().display().to_string()),
+            u64::MAX / 1000,
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .env_remove("MGIT_LOG_DIR")
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "failed to initialize logging: `log-retention-days` is too large",
+        ))
+        .stdout(predicate::str::contains(r#""kind":"status""#));
+}
+
+#[test]
+fn log_dir_from_env() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .env("MGIT_LOG_DIR", log_dir.path())
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    assert_eq!(fs_err::read_dir(log_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn log_level_off() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .env("MGIT_LOG_DIR", log_dir.path())
+        .env("MGIT_LOG", "off")
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    log_dir.assert(predicate::path::missing());
+}
+
+#[test]
+fn log_init_failure() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    // Use a file as the log directory, so creating it fails
+    let log_dir = context.temp_dir().child("logs");
+    log_dir.touch().unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .env("MGIT_LOG_DIR", log_dir.path())
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("failed to initialize logging"))
+        .stdout(predicate::str::contains(r#""kind":"status""#));
+}
+
+#[test]
+fn log_default_jobs() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+    let run = |args: &[&str]| {
+        fs_err::remove_dir_all(log_dir.path()).ok();
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .env_remove("MULTIGIT_CONFIG_PATH")
+            .env("MGIT_LOG_DIR", log_dir.path())
+            .env_remove("MGIT_LOG")
+            .arg("-v")
+            .arg("--json")
+            .args(args)
+            .current_dir(context.working_dir())
+            .assert()
+            .success();
+
+        let log_file = fs_err::read_dir(log_dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        fs_err::read_to_string(log_file.path()).unwrap()
+    };
+
+    // Network-bound commands run more jobs than there are CPUs
+    let cpus = num_cpus::get();
+    assert!(run(&["status"]).contains(&format!(
+        "running with {} jobs",
+        std::cmp::min(cpus * 4, 32)
+    )));
+    assert!(
+        run(&["exec", "--shell", "bash", "true"]).contains(&format!("running with {} jobs", cpus))
+    );
+    assert!(run(&["--jobs", "3", "status"]).contains("running with 3 jobs"));
+}
+
+#[test]
+fn log_verbose() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    let log_dir = context.temp_dir().child("logs");
+    let run = |args: &[&str], level: Option<&str>| {
+        fs_err::remove_dir_all(log_dir.path()).ok();
+        let mut command = Command::cargo_bin("mgit").unwrap();
+        match level {
+            Some(level) => command.env("MGIT_LOG", level),
+            None => command.env_remove("MGIT_LOG"),
+        };
+        command
+            .env_remove("MULTIGIT_CONFIG_PATH")
+            .env("MGIT_LOG_DIR", log_dir.path())
+            .arg("--json")
+            .args(args)
+            .current_dir(context.working_dir())
+            .assert()
+            .success();
+
+        let log_file = fs_err::read_dir(log_dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let log = fs_err::read_to_string(log_file.path()).unwrap();
+        (
+            log.contains(r#""level":"DEBUG""#),
+            log.contains(r#""level":"TRACE""#),
+        )
+    };
+
+    assert_eq!(run(&["status"], None), (false, false));
+    assert_eq!(run(&["-v", "status"], None), (true, false));
+    assert_eq!(run(&["-vv", "status"], None), (true, true));
+    assert_eq!(run(&["status", "-v"], None), (true, false));
+    assert_eq!(run(&["status"], Some("trace")), (true, true));
+    assert_eq!(run(&["-v", "status"], Some("trace")), (true, false));
+}
@@ -0,0 +1,69 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn nested_move_updates_aliases() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n\n[aliases]\none = \"a/one\"\ntwo = \"a/two\"\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("move")
+        .arg("one")
+        .arg("c/one")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"kind":"message","message":"moved `a/one` to `c/one`"}"#,
+            "\n",
+            r#"{"kind":"message","message":"updated alias `one = \"c/one\"`"}"#,
+            "\n",
+        ));
+
+    context
+        .temp_dir()
+        .child("a/one")
+        .assert(predicate::path::missing());
+    context
+        .temp_dir()
+        .child("c/one/.git")
+        .assert(predicate::path::exists());
+    config.assert(predicate::str::ends_with(
+        "[aliases]\none = \"c/one\"\ntwo = \"a/two\"\n",
+    ));
+}
+
+#[test]
+fn nested_move_destination_exists() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("--json")
+        .arg("move")
+        .arg("a/one")
+        .arg("a/two")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("already exists"));
+
+    context
+        .temp_dir()
+        .child("a/one/.git")
+        .assert(predicate::path::exists());
+}
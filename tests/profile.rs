@@ -0,0 +1,78 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+fn write_config(context: &setup::Context) -> assert_fs::fixture::ChildPath {
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n[aliases]\none = \"a/one\"\n[profiles.oss]\nroot = {}\naliases = {{ three = \"three\" }}\n",
+            toml::Value::String(context.working_dir().display().to_string()),
+            toml::Value::String(context.working_dir().join("b").display().to_string()),
+        ))
+        .unwrap();
+    config
+}
+
+#[test]
+fn profile_arg() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let config = write_config(&context);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .env_remove("MGIT_PROFILE")
+        .arg("--profile")
+        .arg("oss")
+        .arg("--json")
+        .arg("status")
+        .arg("three")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            r#"{"kind":"status","path":"three","#,
+        ));
+}
+
+#[test]
+fn profile_env() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let config = write_config(&context);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .env("MGIT_PROFILE", "oss")
+        .arg("resolve")
+        .arg("three")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("three\n"));
+}
+
+#[test]
+fn profile_missing() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let config = write_config(&context);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .env_remove("MGIT_PROFILE")
+        .arg("--profile")
+        .arg("work")
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(
+            r#"{"kind":"error","message":"failed to get config","source":{"message":"profile `work` does not exist","source":null}}
+"#,
+        );
+}
@@ -1,11 +1,13 @@
 mod setup;
 
-use std::path::Path;
+use std::path::{Component, Path};
 
 use assert_cmd::Command;
 use assert_fs::{prelude::*, TempDir};
 use predicates::prelude::*;
 
+const FAILED: &str = r#"{"kind":"error","message":"1 repo failed","source":null}"#;
+
 macro_rules! pull_test {
     ($name:ident, $expected:expr) => {
         pull_test!($name, $expected, |_| {});
@@ -20,69 +22,110 @@ macro_rules! pull_test {
 
 pull_test!(
     empty,
-    r#"{"kind":"error","path":"","message":"no remotes","source":null}"#
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"no remotes","source":null}"#
 );
 pull_test!(
     upstream_working_tree_added,
-    r#"{"kind":"pull","path":"","state":"fast_forwarded","branch":"main"}"#,
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#,
     |path| {
         path.child("local/file.txt").assert("changed");
     }
 );
 pull_test!(
     upstream_working_tree_overwrite,
-    r#"{"kind":"error","path":"","message":"1 conflict prevents checkout","source":null}"#,
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"1 conflict prevents checkout","source":null}"#,
     |path| {
         path.child("local/file.txt").assert("original");
     }
 );
 pull_test!(
     upstream,
-    r#"{"kind":"pull","path":"","state":"up_to_date","branch":"main"}"#
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"up_to_date","branch":"main","transfer":{*}}"#
 );
 pull_test!(
     upstream_ahead,
-    r#"{"kind":"pull","path":"","state":"up_to_date","branch":"main"}"#
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"up_to_date","branch":"main","transfer":{*}}"#
 );
 pull_test!(
     upstream_behind,
-    r#"{"kind":"pull","path":"","state":"fast_forwarded","branch":"main"}"#,
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#,
     |path| {
         path.child("local/file.txt").assert("changed");
     }
 );
+pull_test!(
+    upstream_multiple_remotes,
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"no default remote","source":null}"#
+);
+pull_test!(
+    upstream_behind_tag,
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#,
+    |path| {
+        path.child("local/.git/refs/tags/v1")
+            .assert(predicate::path::exists());
+    }
+);
 pull_test!(
     upstream_diverged,
-    r#"{"kind":"error","path":"","message":"cannot fast-forward","source":null}"#
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"cannot fast-forward","source":{"message":"branch `main` has diverged from its upstream (1 ahead, 1 behind), rebase or merge it manually","source":null}}"#
 );
 pull_test!(
     upstream_on_branch,
-    r#"{"kind":"error","path":"","message":"not on default branch","source":null}"#
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"not on default branch","source":null}"#
 );
 pull_test!(
     upstream_working_tree_changed,
-    r#"{"kind":"error","path":"","message":"1 conflict prevents checkout","source":null}"#,
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"1 conflict prevents checkout","source":null}"#,
     |path| {
         path.child("local/file.txt").assert("changed");
     }
 );
 pull_test!(
     upstream_empty,
-    r#"{"kind":"error","path":"","message":"remote has no default branch","source":null}"#
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"created_unborn","branch":"main","transfer":{*}}"#
+);
+pull_test!(
+    upstream_empty_default_branch,
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"created_unborn","branch":"trunk","transfer":{*}}"#
+);
+pull_test!(
+    upstream_mirror,
+    r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"repo is bare"}"#
+);
+pull_test!(
+    upstream_unborn_head,
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"remote has no default branch","source":null}"#
 );
 pull_test!(
     upstream_local_empty,
-    r#"{"kind":"pull","path":"","state":"created_unborn","branch":"main"}"#
+    r#"{"kind":"pull","path":"","absolute_path":"*","state":"created_unborn","branch":"main","transfer":{*}}"#
 );
 pull_test!(
     upstream_local_empty_on_branch,
-    r#"{"kind":"error","path":"","message":"not on default branch","source":null}"#
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"not on default branch","source":null}"#
 );
 pull_test!(
     upstream_detached,
-    r#"{"kind":"error","path":"","message":"not on default branch","source":null}"#
+    r#"{"kind":"error","path":"","absolute_path":"*","message":"not on default branch","source":null}"#
 );
 
+#[test]
+fn empty_ignore_errors() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--ignore-errors")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"no remotes","source":null}"#, context.working_dir()
+        ));
+}
+
 #[test]
 fn upstream_on_branch_switch() {
     let context =
@@ -97,7 +140,7 @@ fn upstream_on_branch_switch() {
         .assert()
         .success()
         .stdout(output_pred(
-            r#"{"kind":"pull","path":"","state":"fast_forwarded","branch":"main"}"#,
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#, context.working_dir()
         ));
 
     context
@@ -119,10 +162,12 @@ fn upstream_local_empty_on_branch_switch() {
         .arg("--switch")
         .current_dir(context.working_dir())
         .assert()
-        .success()
-        .stdout(output_pred(
-            r#"{"kind":"error","path":"","message":"cannot locate local branch 'main'","source":null}"#,
-        ));
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"cannot locate local branch 'main'","source":null}"#,
+            FAILED
+        ), context.working_dir()));
 
     context
         .temp_dir()
@@ -142,31 +187,750 @@ fn upstream_detached_switch() {
         .arg("--switch")
         .current_dir(context.working_dir())
         .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"will not switch branch while detached","source":null}"#,
+            FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn upstream_symbolic_remote_switch() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_symbolic_remote.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--switch")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"will not switch branch while HEAD points to `refs/remotes/upstream/main`","source":null}"#,
+            FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn upstream_detached_skip_detached() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_detached.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--skip-detached")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
         .success()
         .stdout(output_pred(
-            r#"{"kind":"error","path":"","message":"will not switch branch while detached","source":null}"#,
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"head is detached"}"#,
+            context.working_dir(),
         ));
 }
 
-fn run_pull_test(name: &str, expected: &str, fs_asserts: impl FnOnce(&TempDir)) {
+#[test]
+fn upstream_tracking_branch() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_tracking_branch.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"not on default branch","source":null}"#, FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn upstream_tracking_branch_no_default_branch_check() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_tracking_branch.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--no-default-branch-check")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"topic","transfer":{*}}"#, context.working_dir()
+        ));
+
+    context.temp_dir().child("local/file.txt").assert("changed");
+}
+
+#[test]
+fn upstream_on_branch_no_default_branch_check() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_on_branch.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--no-default-branch-check")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"no upstream branch","source":null}"#, FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn upstream_multiple_remotes_priority() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_multiple_remotes.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nremote-priority = [\"missing\", \"upstream\", \"origin\"]\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"up_to_date","branch":"main","transfer":{*}}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn upstream_behind_transfer_stats() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(
+                r#""transfer":\{"total_objects":\d+,"received_objects":\d+,"received_bytes":\d+,"elapsed_ms":\d+\}"#,
+            )
+            .unwrap(),
+        );
+}
+
+#[test]
+fn upstream_shallow_unshallow() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_shallow.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--unshallow")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"",*,"state":"fast_forwarded","branch":"main","transfer":{*},"unshallowed":true}"#, context.working_dir()
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/shallow")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_shallow_depth() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_shallow.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--depth")
+        .arg("2")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"",*,"state":"fast_forwarded","branch":"main","transfer":{*}}"#, context.working_dir()
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/shallow")
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn upstream_shallow_depth_tags() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_shallow_tag.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--depth")
+        .arg("2")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"",*,"state":"fast_forwarded","branch":"main","transfer":{*}}"#,
+            context.working_dir(),
+        ));
+
+    // Like `git fetch`, only tags pointing into the fetched history are downloaded by default
+    context
+        .temp_dir()
+        .child("local/.git/refs/tags/unrelated")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_behind_depth_complete() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--depth")
+        .arg("1")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"repo is not shallow"}"#,
+            context.working_dir(),
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/shallow")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_behind_tag_no_tags() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_tag.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--no-tags")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#, context.working_dir()
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/refs/tags/v1")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_behind_tag_fetch_tags_setting() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_tag.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nfetch-tags = false\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    context
+        .temp_dir()
+        .child("local/.git/refs/tags/v1")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_behind_tag_remote_tag_opt() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_tag.setup").unwrap());
+
+    let repo = git2::Repository::open(context.temp_dir().child("local").path()).unwrap();
+    repo.config()
+        .unwrap()
+        .set_str("remote.upstream.tagOpt", "--no-tags")
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    assert!(repo.find_reference("refs/tags/v1").is_err());
+}
+
+#[test]
+fn upstream_branch_deleted_fetch_prune() {
     let context = setup::run(
-        &fs_err::read_to_string(Path::new("tests/setup").join(name).with_extension("setup"))
+        &fs_err::read_to_string("tests/setup/upstream_branch_deleted_fetch_prune.setup").unwrap(),
+    );
+
+    let repo = git2::Repository::open(context.temp_dir().child("local").path()).unwrap();
+    assert!(repo.find_reference("refs/remotes/upstream/topic").is_ok());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"up_to_date","branch":"main","transfer":{*}}"#, context.working_dir()
+        ));
+
+    assert!(repo.find_reference("refs/remotes/upstream/topic").is_err());
+}
+
+#[test]
+fn upstream_behind_show_settings() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nfetch-tags = false\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .arg("--show-settings")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"",*,"transfer":{*},"settings":{"fetch-tags":false}}"#,
+            context.working_dir(),
+        ));
+}
+
+#[test]
+fn upstream_behind_read_only() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nread-only = true\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"repo is read-only"}"#,
+            context.working_dir(),
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""kind":"status""#));
+}
+
+#[test]
+fn upstream_behind_git_ssh_command() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("GIT_SSH_COMMAND", "ssh -i ~/.ssh/other_key")
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#, context.working_dir()
+        ))
+        .stderr("warning: `GIT_SSH_COMMAND` is set, but is only used when running `git` for `pull --depth` or `clone --filter`: other SSH connections use keys from the `ssh` setting or ssh-agent instead\n");
+}
+
+#[test]
+fn upstream_behind_porcelain() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("pull")
+        .arg("--porcelain")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("\tfast_forwarded\tmain\n");
+}
+
+#[test]
+fn upstream_behind_porcelain_null() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("pull")
+        .arg("--porcelain")
+        .arg("-0")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("\tfast_forwarded\tmain\0");
+}
+
+#[test]
+fn empty_porcelain() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("pull")
+        .arg("--porcelain")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout("\terror\tno remotes\n")
+        .stderr("error: 1 repo failed\n");
+}
+
+#[test]
+fn porcelain_json_conflict() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--porcelain")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "the `--porcelain` option cannot be used with `--json`",
+        ));
+}
+
+#[test]
+fn upstream_behind_record_error() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+    context
+        .temp_dir()
+        .child("local/.git/MGIT_LAST_PULL")
+        .create_dir_all()
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*},"record_error":{"message":"failed to record pull for `mgit undo`","source":*}}"#,
+            context.working_dir(),
+        ));
+}
+
+#[test]
+fn fail_on_status_condition() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("pull")
+        .arg("--fail-on")
+        .arg("repo-errors,dirty")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "the `--fail-on dirty` option can only be used with `mgit status`",
+        ));
+}
+
+#[test]
+fn upstream_working_tree_modified_autostash() {
+    let context = setup::run(
+        &fs_err::read_to_string("tests/setup/upstream_working_tree_modified.setup").unwrap(),
+    );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--autostash")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"fast_forwarded","branch":"main","transfer":{*}}"#, context.working_dir()
+        ));
+
+    let local = context.temp_dir().child("local");
+    local.child("file.txt").assert("changed");
+    local.child("other.txt").assert("upstream");
+    local
+        .child(".git/refs/stash")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_working_tree_changed_autostash() {
+    let context = setup::run(
+        &fs_err::read_to_string("tests/setup/upstream_working_tree_changed.setup").unwrap(),
+    );
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nautostash = true\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            r#""message":"fast-forwarded branch `main`, but failed to reapply uncommitted changes (they have been left in the stash)","source":{"message":"stashed changes conflict""#,
+        ));
+
+    let local = context.temp_dir().child("local");
+    local
+        .child("file.txt")
+        .assert(predicate::str::contains("<<<<<<<"));
+    local
+        .child(".git/refs/stash")
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn upstream_diverged_merge() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_diverged.setup").unwrap());
+
+    let repo = git2::Repository::open(context.temp_dir().child("local").path()).unwrap();
+    let mut repo_config = repo.config().unwrap();
+    repo_config.set_str("user.name", "Test User").unwrap();
+    repo_config
+        .set_str("user.email", "test@example.com")
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--ff-only=false")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"merged","branch":"main","commits":1,"transfer":{*}}"#, context.working_dir()
+        ));
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 2);
+    assert!(head
+        .message()
+        .unwrap()
+        .starts_with("Merge branch 'main' of "));
+}
+
+#[test]
+fn upstream_diverged_working_tree_modified_autostash() {
+    let context = setup::run(
+        &fs_err::read_to_string("tests/setup/upstream_diverged_working_tree_modified.setup")
             .unwrap(),
     );
 
+    let repo = git2::Repository::open(context.temp_dir().child("local").path()).unwrap();
+    let mut repo_config = repo.config().unwrap();
+    repo_config.set_str("user.name", "Test User").unwrap();
+    repo_config
+        .set_str("user.email", "test@example.com")
+        .unwrap();
+
     Command::cargo_bin("mgit")
         .unwrap()
         .arg("--json")
         .arg("pull")
+        .arg("--ff-only=false")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            r#""message":"cannot merge","source":{"message":"branch has uncommitted changes","source":null}"#,
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .arg("--ff-only=false")
+        .arg("--autostash")
         .current_dir(context.working_dir())
         .assert()
         .success()
-        .stdout(output_pred(expected));
+        .stdout(output_pred(
+            r#"{"kind":"pull","path":"","absolute_path":"*","state":"merged","branch":"main","commits":1,"transfer":{*}}"#, context.working_dir()
+        ));
+
+    let local = context.temp_dir().child("local");
+    local.child("file.txt").assert("changed");
+    local.child("other.txt").assert("upstream");
+    local
+        .child(".git/refs/stash")
+        .assert(predicate::path::missing());
+}
+
+#[test]
+fn upstream_diverged_conflict_merge_setting() {
+    let context = setup::run(
+        &fs_err::read_to_string("tests/setup/upstream_diverged_conflict.setup").unwrap(),
+    );
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nff-only = false\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            r#""message":"cannot merge","source":{"message":"conflicts in `file.txt`","source":null}"#,
+        ));
+
+    let local = context.temp_dir().child("local");
+    local.child("file.txt").assert("local");
+    local
+        .child(".git/MERGE_HEAD")
+        .assert(predicate::path::missing());
+}
+
+fn run_pull_test(name: &str, expected: &str, fs_asserts: impl FnOnce(&TempDir)) {
+    let context = setup::run(
+        &fs_err::read_to_string(Path::new("tests/setup").join(name).with_extension("setup"))
+            .unwrap(),
+    );
+
+    let assert = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .current_dir(context.working_dir())
+        .assert();
+    if expected.starts_with(r#"{"kind":"error""#) {
+        assert.failure().stdout(output_pred(
+            &format!("{}\n{}", expected, FAILED),
+            context.working_dir(),
+        ));
+    } else {
+        assert
+            .success()
+            .stdout(output_pred(expected, context.working_dir()));
+    }
 
     fs_asserts(context.temp_dir());
 }
 
-fn output_pred(expected: &str) -> impl Predicate<[u8]> {
+/// Match output against `expected`, where `*` matches any text, except in `"absolute_path":"*"`,
+/// which must be the `path` of the same record resolved against `dir`, the directory the command
+/// was run in.
+fn output_pred(expected: &str, dir: &Path) -> impl Predicate<[u8]> {
+    let expected = regex::Regex::new(r#""path":"([^"]*)","absolute_path":"\*""#)
+        .unwrap()
+        .replace_all(expected, |captures: &regex::Captures| {
+            let mut absolute_path = dir.to_owned();
+            for component in Path::new(&captures[1]).components() {
+                match component {
+                    Component::ParentDir => {
+                        absolute_path.pop();
+                    }
+                    component => absolute_path.push(component),
+                }
+            }
+            format!(
+                r#""path":"{}","absolute_path":{}"#,
+                &captures[1],
+                serde_json::to_string(&absolute_path.display().to_string()).unwrap()
+            )
+        });
     let regex = format!(
         "^{}$",
         regex::escape(&expected.replace("*", "__WILDCARD__")).replace("__WILDCARD__", ".*")
@@ -177,3 +941,72 @@ fn output_pred(expected: &str) -> impl Predicate<[u8]> {
         .trim()
         .from_utf8()
 }
+
+#[test]
+fn upstream_behind_many_max_network() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_many.setup").unwrap());
+    let log_dir = context.temp_dir().child("logs");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MGIT_LOG_DIR", log_dir.path())
+        .env_remove("MGIT_LOG")
+        .arg("-v")
+        .arg("--json")
+        .arg("--jobs")
+        .arg("3")
+        .arg("--max-network")
+        .arg("1")
+        .arg("pull")
+        .arg("one")
+        .arg("two")
+        .arg("three")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""state":"fast_forwarded""#).count(3));
+
+    // Only one fetch runs at a time, so none can start while another holds the only permit
+    let log_file = fs_err::read_dir(log_dir.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let log = fs_err::read_to_string(log_file.path()).unwrap();
+    assert_eq!(log.matches("starting network operation").count(), 3);
+    assert_eq!(
+        log.matches("starting network operation, 0 more can start")
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn upstream_behind_detached_porcelain() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_detached.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--skip-detached")
+        .arg("pull")
+        .arg("--porcelain")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    // The header for the directory containing the repos is left out
+    let mut lines: Vec<_> = std::str::from_utf8(&output).unwrap().lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        [
+            "behind\tfast_forwarded\tmain",
+            "detached\tskipped\thead is detached"
+        ]
+    );
+}
@@ -0,0 +1,225 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn nested_relative() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--relative")
+        .arg("a/one")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("a/one\n");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--relative")
+        .arg(".")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(".\n");
+}
+
+#[test]
+fn nested_null() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--relative")
+        .arg("--null")
+        .arg("a/one")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("a/one\0");
+}
+
+#[test]
+fn nested_allow_missing() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("c/four")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "failed to resolve path or alias `c/four`",
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--allow-missing")
+        .arg("c/four")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            context.working_dir().join("c/four").display()
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--allow-missing")
+        .arg("--existing-only")
+        .arg("c/four")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn nested_alias_allow_missing() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n[aliases]\nfour = \"c/four\"\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("resolve")
+        .arg("--allow-missing")
+        .arg("--relative")
+        .arg("four")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("c/four\n"));
+}
+
+#[test]
+fn nested_alias_warnings() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n[aliases]\none = \"a/one\"\none-more = \"a/two\"\nfour = \"c/four\"\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("resolve")
+        .arg("--relative")
+        .arg("one")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "alias `one` is a prefix of alias `one-more`, so `one-more` cannot be abbreviated to `one`",
+        ))
+        .stdout(predicate::str::contains(
+            "alias `four` points to `c/four`, which does not exist",
+        ))
+        .stdout(predicate::str::ends_with("a/one\n"));
+}
+
+#[test]
+fn nested_reverse() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n[aliases]\nfirst = \"a/one\"\nsame = \"./a/one/\"\nthird = \"b/three\"\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("resolve")
+        .arg("--reverse")
+        .current_dir(context.working_dir().join("a/one"))
+        .assert()
+        .success()
+        .stdout("first\nsame\n");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("resolve")
+        .arg("--reverse")
+        .arg("b/three")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            r#"{"kind":"alias","name":"third","path":"b/three"}
+"#,
+        );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("resolve")
+        .arg("--reverse")
+        .arg("a/two")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn nested_verbose() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("a/onee")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "did you mean one of these paths: a/one?",
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env_remove("MULTIGIT_CONFIG_PATH")
+        .arg("resolve")
+        .arg("--verbose")
+        .arg("a/onee")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "did you mean one of these paths: a/one (0.942)?",
+        ));
+}
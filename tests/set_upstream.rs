@@ -0,0 +1,117 @@
+mod setup;
+
+use std::path::Path;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+fn run_set_upstream_test(name: &str, expected: &str) {
+    let context = setup::run(
+        &fs_err::read_to_string(Path::new("tests/setup").join(name).with_extension("setup"))
+            .unwrap(),
+    );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("set-upstream")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(format!("{}\n", expected));
+}
+
+#[test]
+fn upstream() {
+    run_set_upstream_test(
+        "upstream",
+        r#"{"kind":"set_upstream","path":"","state":"already_set"}"#,
+    );
+}
+
+#[test]
+fn upstream_unset() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_unset.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("set-upstream")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            r#"{"kind":"set_upstream","path":"","state":"updated","upstream":"upstream/main"}
+"#,
+        );
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            r#""upstream":{"state":"upstream","ahead":0,"behind":0}"#,
+        ));
+}
+
+#[test]
+fn upstream_on_branch() {
+    run_set_upstream_test(
+        "upstream_on_branch",
+        r#"{"kind":"set_upstream","path":"","state":"no_remote_branch"}"#,
+    );
+}
+
+#[test]
+fn upstream_detached_many() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_detached_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("set-upstream")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(
+                r#"{"kind":"set_upstream","path":"detached","state":"skipped","reason":"head is detached"}"#,
+            )
+            .and(predicate::str::contains(
+                r#"{"kind":"set_upstream","path":"unborn","state":"skipped","reason":"head is unborn"}"#,
+            ))
+            .and(predicate::str::contains(
+                r#"{"kind":"set_upstream","path":"unset","state":"updated","upstream":"upstream/main"}"#,
+            )),
+        );
+}
+
+#[test]
+fn upstream_detached_many_max_path_width() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_detached_many.setup").unwrap());
+    let report = context.temp_dir().child("report.txt");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--output")
+        .arg(report.path())
+        .arg("--max-path-width")
+        .arg("4")
+        .arg("--skip-detached")
+        .arg("set-upstream")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    report.assert(
+        predicate::str::contains("…hed skipped: head is detached\n")
+            .and(predicate::str::contains("…orn skipped: head is unborn\n"))
+            .and(predicate::str::contains("…set tracking upstream/main\n")),
+    );
+}
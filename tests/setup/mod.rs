@@ -23,7 +23,8 @@ pub fn run(data: &str) -> Context {
 
         match cmd {
             "CD" => context.run_cd(rem),
-            "GIT" => context.run_git(rem),
+            "GIT" => context.run_git(rem, true),
+            "GIT_FAILS" => context.run_git(rem, false),
             "WRITE" => context.run_write(rem),
             _ => panic!("Invalid command {}", cmd),
         }
@@ -72,7 +73,7 @@ impl Context {
         self.working_dir = working_dir;
     }
 
-    fn run_git(&mut self, cmd: &str) {
+    fn run_git(&mut self, cmd: &str, expect_success: bool) {
         let status = Command::new(&self.git_exe)
             .arg("-c")
             .arg("core.fsmonitor=")
@@ -84,8 +85,8 @@ impl Context {
             .stdout(Stdio::null())
             .status()
             .unwrap();
-        if !status.success() {
-            panic!("git exited unsuccessfully: {}", status);
+        if status.success() != expect_success {
+            panic!("git exited with unexpected status: {}", status);
         }
     }
 
@@ -1,10 +1,13 @@
 mod setup;
 
-use std::path::Path;
+use std::path::{Component, Path};
 
 use assert_cmd::Command;
+use assert_fs::{prelude::*, TempDir};
 use predicates::prelude::*;
 
+const FAILED: &str = r#"{"kind":"error","message":"1 repo failed","source":null}"#;
+
 macro_rules! status_test {
     ($name:ident, $expected:expr) => {
         #[test]
@@ -16,84 +19,833 @@ macro_rules! status_test {
 
 status_test!(
     empty,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#
 );
 status_test!(
     empty_branch,
-    r#"{"kind":"status","path":"","head":{"name":"topic","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"topic","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#
 );
 status_test!(
     on_main,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     on_branch,
-    r#"{"kind":"status","path":"","head":{"name":"topic","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"topic","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     detached,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     detached_branch,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     detached_branch_ahead,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     detached_tag,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     detached_tag_ahead,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     index_changed,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":true},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":true,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     index_added,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":true},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":true,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
+);
+status_test!(
+    unborn_index_added,
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":true,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false,"initial_commit_staged":true}"#
+);
+status_test!(
+    merge_conflict,
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":true},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     working_tree_changed,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":true,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":true,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     working_tree_added,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
 );
 status_test!(
     upstream,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":true}"#
 );
 status_test!(
     upstream_behind,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":1},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":1},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#
 );
 status_test!(
     upstream_ahead,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":1,"behind":0},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":1,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#
+);
+status_test!(
+    upstream_diverged_many,
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":3,"behind":1},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#
 );
 status_test!(
     upstream_empty,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":null}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":"*upstream","clean":false}"#
 );
 status_test!(
     upstream_local_empty,
-    r#"{"kind":"status","path":"","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","clean":false}"#
 );
 status_test!(
     upstream_local_empty_on_branch,
-    r#"{"kind":"status","path":"","head":{"name":"topic","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"topic","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","clean":false}"#
 );
 status_test!(
     upstream_detached,
-    r#"{"kind":"status","path":"","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false},"default_branch":"main"}"#
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"*","kind":"detached"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#
 );
+status_test!(
+    symbolic_tag,
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"refs/tags/release","kind":"symbolic_ref"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#
+);
+status_test!(
+    upstream_symbolic_remote,
+    r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"refs/remotes/upstream/main","kind":"symbolic_ref"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#
+);
+
+#[test]
+fn detached_skip_detached() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/detached.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--skip-detached")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"head is detached"}"#,
+            context.working_dir(),
+        ));
+}
+
+#[test]
+fn upstream_diverged_many_max_ahead_behind() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_diverged_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--max-ahead-behind")
+        .arg("2")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":2,"behind":1,"ahead_capped":true},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+
+    // Only counts which stopped at the limit are marked
+    let report = context.temp_dir().child("report.txt");
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--output")
+        .arg(report.path())
+        .arg("status")
+        .arg("--max-ahead-behind")
+        .arg("1")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+    report.assert(predicate::str::contains("1↓ 1+↑"));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--max-ahead-behind")
+        .arg("0")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"uncounted"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn upstream_diverged_many_compare() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_diverged_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--compare")
+        .arg("upstream/main")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":3,"behind":1},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","compare":{"state":"compared","ahead":3,"behind":1},"last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--compare")
+        .arg("upstream/release")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":3,"behind":1},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","compare":{"state":"missing"},"last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--compare")
+        .arg("HEAD~2")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":3,"behind":1},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","compare":{"state":"compared","ahead":2,"behind":0},"last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn upstream_detect_default_branch_disabled() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\ndetect-default-branch = false\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":"*upstream","last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn detached_tag_describe_annotated_tags_only() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/detached_tag.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\ndescribe-all-tags = false\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    // The `release` tag is lightweight, so the commit hash is shown instead
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(r#""head":\{"name":"[0-9a-f]+","kind":"detached"\}"#).unwrap(),
+        );
+}
+
+#[test]
+fn upstream_output_file() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+    let report = context.temp_dir().child("report.jsonl");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--output")
+        .arg(report.path())
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+
+    let report = fs_err::read(report.path()).unwrap();
+    assert!(output_pred(
+        r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":true}"#, context.working_dir()
+    ).eval(&report));
+}
+
+#[test]
+fn upstream_output_file_plain() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+    let report = context.temp_dir().child("report.txt");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--output")
+        .arg(report.path())
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+
+    report.assert(predicate::str::contains("main").and(predicate::str::contains("\x1b").not()));
+}
+
+#[test]
+fn upstream_output_file_dates() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+    let report = context.temp_dir().child("report.txt");
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\ndate-format = \"absolute\"\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--output")
+        .arg(report.path())
+        .arg("status")
+        .arg("--relative-date")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+    report.assert(predicate::str::contains("main ✓ just now"));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--output")
+        .arg(report.path())
+        .arg("status")
+        .arg("--verbose")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+    report
+        .assert(predicate::str::is_match(r"main ✓ \d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z ").unwrap());
+}
+
+#[test]
+fn nested_tree() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let report = context.temp_dir().child("report.txt");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--output")
+        .arg(report.path())
+        .arg("--keep-order")
+        .arg("status")
+        .arg("--tree")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+
+    let report = fs_err::read_to_string(report.path()).unwrap();
+    let labels: Vec<&str> = report
+        .lines()
+        .map(|line| match line.find(" main") {
+            Some(index) => line[..index].trim_end(),
+            None => line.trim_end(),
+        })
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            ".",
+            "├── a",
+            "│   ├── one",
+            "│   └── two",
+            "└── b",
+            "    └── three"
+        ]
+    );
+}
+
+#[test]
+fn upstream_gone_many_upstream_all() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_gone_many.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--upstream-all")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(concat!(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"current","kind":"branch"},"upstream":{"state":"gone"},*,"#,
+            r#""branches":[{"name":"current","upstream":{"state":"gone"}},{"name":"kept","upstream":{"state":"upstream","ahead":0,"behind":0}},"#,
+            r#"{"name":"main","upstream":{"state":"upstream","ahead":0,"behind":0}},{"name":"merged","upstream":{"state":"gone"}},"#,
+            r#"{"name":"unmerged","upstream":{"state":"gone"}}],"last_commit_time":*,"clean":false}"#,
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_glob() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("a/t*")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(concat!(
+            r#"{"kind":"directory","path":"*a"}"#,
+            "\n",
+            r#"{"kind":"status","path":"a/two","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#,
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_time() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--time")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(
+                r#"\n\{"kind":"timing","total_ms":\d+,"discovery_ms":\d+,"work_ms":\d+\}\n$"#,
+            )
+            .unwrap(),
+        );
+}
+
+#[test]
+fn nested_multiple_targets() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--keep-order")
+        .arg("status")
+        .arg("a/one")
+        .arg("b")
+        .arg("a/*")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(concat!(
+            r#"{"kind":"status","path":"a/one","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#,
+            "\n",
+            r#"{"kind":"directory","path":"*b"}"#,
+            "\n",
+            r#"{"kind":"status","path":"b/three","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#,
+            "\n",
+            r#"{"kind":"directory","path":"*a"}"#,
+            "\n",
+            r#"{"kind":"status","path":"a/two","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#,
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_stdin() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--stdin")
+        .write_stdin("b/three\n")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"b/three","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn nested_show_settings() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            concat!(
+                "root = {}\n",
+                "default-branch = \"main\"\n",
+                "prune = true\n",
+                "[settings]\n",
+                "\"b/*\" = {{ prune = false, ssh = {{ username = \"git\", passphrase = \"secret\" }} }}\n",
+            ),
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    // Settings are shown after merging, without secrets such as the SSH passphrase
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("status")
+        .arg("b/three")
+        .arg("--show-settings")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"b/three",*,"clean":false,"settings":{"default-branch":"main","ssh":{"username":"git"},"prune":false}}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn nested_stdin_invalid() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--stdin")
+        .write_stdin("a\n")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"a","absolute_path":"*","message":"`*a` is not a repo","source":null}"#,
+            FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_stdin_invalid_reported_last() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--stdin")
+        .write_stdin("b\na/one\n")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}\n{}",
+            r#"{"kind":"status","path":"a/one","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#,
+            r#"{"kind":"error","path":"b","absolute_path":"*","message":"`*b` is not a repo","source":null}"#,
+            FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_relative_to_cwd() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("--relative-to")
+        .arg("cwd")
+        .arg("status")
+        .arg("b/three")
+        .current_dir(context.working_dir().join("a"))
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"../b/three","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#, &context.working_dir().join("a")
+        ));
+}
+
+#[test]
+fn nested_relative_to_absolute() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--relative-to")
+        .arg("absolute")
+        .arg("status")
+        .arg("b/three")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(&format!(
+            r#"{{"kind":"status","path":"{}","absolute_path":"*","head":{{"name":"main","kind":"unborn"}},"upstream":{{"state":"none"}},"working_tree":{{"working_changed":false,"index_changed":false,"conflicted":false}},"default_branch":null,"remote_url":null,"clean":false}}"#,
+            context.working_dir().join("b").join("three").display()
+        ), context.working_dir()));
+}
+
+#[test]
+fn nested_here() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\n",
+            toml::Value::String(context.working_dir().join("b").display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("--here")
+        .arg("status")
+        .current_dir(context.working_dir().join("a").join("one"))
+        .assert()
+        .success()
+        .stdout(output_pred(&format!(
+            r#"{{"kind":"status","path":"{}","absolute_path":"*","head":{{"name":"main","kind":"unborn"}},"upstream":{{"state":"none"}},"working_tree":{{"working_changed":false,"index_changed":false,"conflicted":false}},"default_branch":null,"remote_url":null,"clean":false}}"#,
+            context.working_dir().join("a").join("one").display()
+        ), context.working_dir()))
+        .stderr(format!(
+            "warning: the current repo `{}` is outside the root `{}`\n",
+            context.working_dir().join("a").join("one").display(),
+            context.working_dir().join("b").display()
+        ));
+
+    // No warning is needed for a repo inside the root
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("--here")
+        .arg("status")
+        .current_dir(context.working_dir().join("b").join("three"))
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn on_main_older_than() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--older-than")
+        .arg("1d")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn on_main_newer_than() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--newer-than")
+        .arg("1d")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn empty_newer_than() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--newer-than")
+        .arg("1d")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--newer-than")
+        .arg("1d")
+        .arg("--include-unborn")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"unborn"},"upstream":{"state":"none"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":null,"remote_url":null,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn upstream_gone_errors_only() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_gone.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--errors-only")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"topic","kind":"branch"},"upstream":{"state":"gone"},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":false}"#, context.working_dir()
+        ));
+}
+
+#[test]
+fn upstream_gone_fail_on_gone_upstream() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_gone.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--fail-on")
+        .arg("gone-upstream")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::ends_with(
+            "{\"kind\":\"error\",\"message\":\"1 repo has a gone upstream\",\"source\":null}\n",
+        ));
+}
+
+#[test]
+fn upstream_behind_fail_on_dirty_behind() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--fail-on=dirty,behind")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::ends_with(
+            "{\"kind\":\"error\",\"message\":\"1 repo is behind its upstream\",\"source\":null}\n",
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--fail-on")
+        .arg("dirty")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+}
+
+#[test]
+fn upstream_errors_only() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--errors-only")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn on_main_json_pretty() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json-pretty")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"[
+  {
+    "kind": "status",
+    "path": "",
+    "absolute_path": "*",
+    "head": {
+      "name": "main",
+      "kind": "branch"
+    },
+    "upstream": {
+      "state": "none"
+    },
+    "working_tree": {
+      "working_changed": false,
+      "index_changed": false,
+      "conflicted": false
+    },
+    "default_branch": null,
+    "remote_url": null,
+    "last_commit_time": *,
+    "clean": false
+  }
+]"#,
+            context.working_dir(),
+        ));
+}
 
 fn run_status_test(name: &str, expected: &str) {
     let context = setup::run(
@@ -108,10 +860,196 @@ fn run_status_test(name: &str, expected: &str) {
         .current_dir(context.working_dir())
         .assert()
         .success()
-        .stdout(output_pred(expected));
+        .stdout(output_pred(expected, context.working_dir()));
+}
+
+#[test]
+fn upstream_behind_unfetched_fetch() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_unfetched.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#""upstream":{"state":"upstream","ahead":0,"behind":0}"#,
+        ));
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--fetch")
+        .arg("--fetch-jobs")
+        .arg("2")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#""upstream":{"state":"upstream","ahead":0,"behind":1}"#,
+        ));
+}
+
+#[test]
+fn on_main_fetch_no_remotes() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--fetch")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","absolute_path":"*","message":"failed to fetch","source":{"message":"no remotes","source":null}}"#,
+            FAILED
+        ), context.working_dir()));
+}
+
+#[test]
+fn group_by_remote() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/group.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--group-by")
+        .arg("remote")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"{"kind":"group","group":"(no remote host)"}"#))
+        .stdout(predicate::str::contains(r#"{"kind":"group","group":"github.com"}"#))
+        .stdout(predicate::str::contains(r#"{"kind":"group","group":"gitlab.com"}"#))
+        .stdout(predicate::str::contains(
+            r#""remote_url":"https://github.com/user/github.git","clean":false,"group":"github.com"}"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#""remote_url":"git@gitlab.com:user/gitlab.git","clean":false,"group":"gitlab.com"}"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#""remote_url":null,"clean":false,"group":"(no remote host)"}"#,
+        ));
 }
 
-fn output_pred(expected: &str) -> impl Predicate<[u8]> {
+#[test]
+fn group_by_branch() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/group.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--group-by")
+        .arg("branch")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"kind":"group","group":"main"}"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#"{"kind":"group","group":"topic"}"#,
+        ))
+        .stdout(
+            predicate::str::is_match(
+                r#""path":"gitlab","absolute_path":"[^"]*","head":\{"name":"topic","kind":"unborn"\}"#,
+            )
+            .unwrap(),
+        )
+        .stdout(predicate::str::contains(r#""clean":false,"group":"topic"}"#).count(1))
+        .stdout(predicate::str::contains(r#""clean":false,"group":"main"}"#).count(2))
+        .stdout(predicate::str::contains("directory").not())
+        .get_output()
+        .stdout
+        .clone();
+
+    // Each group header is written immediately before the repos in it
+    let groups: Vec<String> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            format!("{}:{}", record["kind"], record["group"])
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        [
+            r#""group":"main""#,
+            r#""status":"main""#,
+            r#""status":"main""#,
+            r#""group":"topic""#,
+            r#""status":"topic""#,
+        ]
+    );
+}
+
+#[test]
+fn nested_deadline_exceeded() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+
+    let output = Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("--deadline")
+        .arg("0s")
+        .arg("status")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(r#""kind":"status""#).not())
+        .stdout(predicate::str::ends_with(
+            "{\"kind\":\"error\",\"message\":\"deadline exceeded before 3 repos could run\",\"source\":null}\n",
+        ))
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    for path in ["a/one", "a/two", "b/three"] {
+        let absolute_path = path
+            .split('/')
+            .fold(context.working_dir().to_owned(), |dir, name| dir.join(name));
+        let record = format!(
+            r#"{{"kind":"skipped","path":"{}","absolute_path":{},"reason":"deadline exceeded"}}"#,
+            path,
+            serde_json::to_string(&absolute_path.display().to_string()).unwrap()
+        );
+        assert!(stdout.contains(&record), "{}", stdout);
+    }
+}
+
+/// Match output against `expected`, where `*` matches any text, except in `"absolute_path":"*"`,
+/// which must be the `path` of the same record resolved against `dir`, the directory the command
+/// was run in.
+fn output_pred(expected: &str, dir: &Path) -> impl Predicate<[u8]> {
+    let expected = regex::Regex::new(r#""path":"([^"]*)","absolute_path":"\*""#)
+        .unwrap()
+        .replace_all(expected, |captures: &regex::Captures| {
+            let mut absolute_path = dir.to_owned();
+            for component in Path::new(&captures[1]).components() {
+                match component {
+                    Component::ParentDir => {
+                        absolute_path.pop();
+                    }
+                    component => absolute_path.push(component),
+                }
+            }
+            format!(
+                r#""path":"{}","absolute_path":{}"#,
+                &captures[1],
+                serde_json::to_string(&absolute_path.display().to_string()).unwrap()
+            )
+        });
     let regex = format!(
         "^{}$",
         regex::escape(&expected.replace("*", "__WILDCARD__")).replace("__WILDCARD__", ".*")
@@ -122,3 +1060,88 @@ fn output_pred(expected: &str) -> impl Predicate<[u8]> {
         .trim()
         .from_utf8()
 }
+
+#[test]
+fn theme() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/empty.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("theme")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            r#"{"kind":"status","path":"clean","absolute_path":"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#""head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"https://example.com/repo.git","clean":true}"#,
+        ))
+        .stdout(predicate::str::contains(
+            r#""message":"failed to open repo","source":null}"#,
+        ));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn nested_discovery_cache() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/nested.setup").unwrap());
+    let data_dir = TempDir::new().unwrap();
+
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\ndiscovery-cache = true\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    let status = |args: &[&str]| {
+        Command::cargo_bin("mgit")
+            .unwrap()
+            .env("MULTIGIT_CONFIG_PATH", config.path())
+            .env("XDG_DATA_HOME", data_dir.path())
+            .arg("--json")
+            .args(args)
+            .arg("status")
+            .current_dir(context.working_dir())
+            .assert()
+            .success()
+    };
+
+    status(&[])
+        .stdout(predicate::str::contains(r#""path":"a/one""#))
+        .stdout(predicate::str::contains(r#""path":"a/two""#))
+        .stdout(predicate::str::contains(r#""path":"b/three""#));
+    data_dir
+        .child("multi-git/discovery-cache.json")
+        .assert(predicate::path::exists());
+
+    // Adding a repo changes the modification time of its parent, so it is found despite the cache
+    git2::Repository::init(context.working_dir().join("b/four")).unwrap();
+    status(&[])
+        .stdout(predicate::str::contains(r#""path":"b/three""#))
+        .stdout(predicate::str::contains(r#""path":"b/four""#));
+
+    status(&["--refresh-cache"])
+        .stdout(predicate::str::contains(r#""path":"a/one""#))
+        .stdout(predicate::str::contains(r#""path":"b/four""#));
+}
+
+#[test]
+fn upstream_collapse_json() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("status")
+        .arg("--collapse")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"status","path":"","absolute_path":"*","head":{"name":"main","kind":"branch"},"upstream":{"state":"upstream","ahead":0,"behind":0},"working_tree":{"working_changed":false,"index_changed":false,"conflicted":false},"default_branch":"main","remote_url":"*upstream","last_commit_time":*,"clean":true}"#, context.working_dir()
+        ));
+}
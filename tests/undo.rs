@@ -0,0 +1,333 @@
+mod setup;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+const FAILED: &str = r#"{"kind":"error","message":"1 repo failed","source":null}"#;
+
+#[test]
+fn upstream_behind_undo() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    pull(&context, &[]);
+    context.temp_dir().child("local/file.txt").assert("changed");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"undo","path":"","time":"*","branch":"refs/heads/main","moved":{"from":"*","to":"*"}}"#,
+        ));
+    context
+        .temp_dir()
+        .child("local/file.txt")
+        .assert(predicate::path::missing());
+
+    // The pull can only be undone once
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"message","message":"no pull to undo"}"#,
+        ));
+}
+
+#[test]
+fn upstream_on_branch_switch_undo() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_on_branch.setup").unwrap());
+
+    pull(&context, &["--switch"]);
+    context
+        .temp_dir()
+        .child("local/.git/HEAD")
+        .assert("ref: refs/heads/main\n");
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"undo","path":"","time":"*","branch":"refs/heads/main","moved":{"from":"*","to":"*"},"switched_from":"refs/heads/topic"}"#,
+        ));
+
+    context
+        .temp_dir()
+        .child("local/.git/HEAD")
+        .assert("ref: refs/heads/topic\n");
+    let repo = git2::Repository::open(context.working_dir()).unwrap();
+    let main = repo
+        .revparse_single("main")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(main.summary(), Some("Initial commit"));
+}
+
+#[test]
+fn upstream_local_empty_undo() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_local_empty.setup").unwrap());
+
+    pull(&context, &[]);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"undo","path":"","time":"*","branch":"refs/heads/main","moved":{"from":null,"to":"*"}}"#,
+        ));
+
+    let repo = git2::Repository::open(context.working_dir()).unwrap();
+    assert!(matches!(repo.head(), Err(err) if err.code() == git2::ErrorCode::UnbornBranch));
+    context
+        .temp_dir()
+        .child("local/.git/HEAD")
+        .assert("ref: refs/heads/main\n");
+}
+
+#[test]
+fn upstream_behind_undo_changed() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    pull(&context, &[]);
+    context
+        .temp_dir()
+        .child("local/file.txt")
+        .write_str("edited")
+        .unwrap();
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","message":"cannot undo pull","source":{"message":"branch `main` has uncommitted changes","source":null}}"#,
+            FAILED
+        )));
+
+    let repo = git2::Repository::open(context.working_dir()).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "Test").unwrap();
+    config.set_str("user.email", "test@example.com").unwrap();
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("commit")
+        .arg("--message")
+        .arg("Edit file")
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}",
+            r#"{"kind":"error","path":"","message":"cannot undo pull","source":{"message":"branch `main` has changed since it was pulled","source":null}}"#,
+            FAILED
+        )));
+    context.temp_dir().child("local/file.txt").assert("edited");
+}
+
+#[test]
+fn upstream_behind_undo_requires_confirmation() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    pull(&context, &[]);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(
+            r#"{"kind":"error","message":"pass `--yes` to undo the pull without confirmation","source":null}"#,
+        ));
+    context.temp_dir().child("local/file.txt").assert("changed");
+}
+
+#[test]
+fn upstream_behind_many_undo_latest() {
+    let context =
+        setup::run(&fs_err::read_to_string("tests/setup/upstream_behind_many.setup").unwrap());
+
+    pull(&context, &["one", "two"]);
+    let upstream = git2::Repository::open(context.temp_dir().child("upstream").path()).unwrap();
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    let parent = upstream.head().unwrap().peel_to_commit().unwrap();
+    upstream
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Commit 1",
+            &parent.tree().unwrap(),
+            &[&parent],
+        )
+        .unwrap();
+    pull(&context, &["one"]);
+    context
+        .temp_dir()
+        .child("three/.git/MGIT_LAST_PULL")
+        .write_str("invalid")
+        .unwrap();
+
+    // Only the most recent pull is undone, which did not change `two`
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(output_pred(&format!(
+            "{}\n{}\n{}",
+            r#"{"kind":"undo","path":"one","time":"*","branch":"refs/heads/main","moved":{"from":"*","to":"*"}}"#,
+            r#"{"kind":"error","path":"three","absolute_path":"*","message":"failed to read the last pull","source":*}"#,
+            FAILED
+        )));
+
+    let summary = |name: &str| {
+        let repo = git2::Repository::open(context.temp_dir().child(name).path()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        head.summary().unwrap().to_owned()
+    };
+    assert_eq!(summary("one"), "Commit 0");
+    assert_eq!(summary("two"), "Commit 0");
+    context
+        .temp_dir()
+        .child("two/.git/MGIT_LAST_PULL")
+        .assert(predicate::path::exists());
+}
+
+#[test]
+fn upstream_working_tree_modified_autostash_undo() {
+    let context = setup::run(
+        &fs_err::read_to_string("tests/setup/upstream_working_tree_modified.setup").unwrap(),
+    );
+
+    pull(&context, &["--autostash"]);
+    context
+        .temp_dir()
+        .child("local/other.txt")
+        .assert("upstream");
+
+    // The changes stashed by the pull are stashed again while resetting the branch
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"undo","path":"","time":"*","branch":"refs/heads/main","moved":{"from":"*","to":"*"},"autostashed":true}"#,
+        ));
+    context
+        .temp_dir()
+        .child("local/other.txt")
+        .assert("original");
+    context.temp_dir().child("local/file.txt").assert("changed");
+
+    let mut repo = git2::Repository::open(context.working_dir()).unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("Initial commit"));
+    drop(head);
+    let mut stashes = 0;
+    repo.stash_foreach(|_, _, _| {
+        stashes += 1;
+        true
+    })
+    .unwrap();
+    assert_eq!(stashes, 0);
+}
+
+#[test]
+fn upstream_behind_undo_read_only() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/upstream_behind.setup").unwrap());
+
+    pull(&context, &[]);
+    let config = context.temp_dir().child("config.toml");
+    config
+        .write_str(&format!(
+            "root = {}\nread-only = true\n",
+            toml::Value::String(context.working_dir().display().to_string())
+        ))
+        .unwrap();
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .env("MULTIGIT_CONFIG_PATH", config.path())
+        .arg("--json")
+        .arg("undo")
+        .arg("--yes")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(output_pred(
+            r#"{"kind":"skipped","path":"","absolute_path":"*","reason":"repo is read-only"}"#,
+        ));
+    context.temp_dir().child("local/file.txt").assert("changed");
+    context
+        .temp_dir()
+        .child("local/.git/MGIT_LAST_PULL")
+        .assert(predicate::path::exists());
+}
+
+fn pull(context: &setup::Context, args: &[&str]) {
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("pull")
+        .args(args)
+        .current_dir(context.working_dir())
+        .assert()
+        .success();
+}
+
+fn output_pred(expected: &str) -> impl Predicate<[u8]> {
+    let regex = format!(
+        "^{}$",
+        regex::escape(&expected.replace("*", "__WILDCARD__")).replace("__WILDCARD__", ".*")
+    );
+
+    predicates::str::is_match(&regex)
+        .unwrap()
+        .trim()
+        .from_utf8()
+}
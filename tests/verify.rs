@@ -0,0 +1,96 @@
+mod setup;
+
+use std::process::Command as StdCommand;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+
+#[test]
+fn on_main_unsigned() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("verify")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r#"^\{"kind":"verify","path":"","commit":"[0-9a-f]{40}","signature":"unsigned"\}\n"#).unwrap());
+}
+
+#[test]
+fn on_main_invalid_rev() {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("verify")
+        .arg("--rev")
+        .arg("does-not-exist")
+        .current_dir(context.working_dir())
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(
+            r#"{"kind":"error","path":"","message":"failed to verify signature","#,
+        ));
+}
+
+#[test]
+fn on_main_ssh_signed() {
+    if which::which("ssh-keygen").is_err() {
+        return;
+    }
+
+    let context = setup::run(&fs_err::read_to_string("tests/setup/on_main.setup").unwrap());
+
+    let key = context.temp_dir().child("key");
+    let status = StdCommand::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+        .arg(key.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let public_key = fs_err::read_to_string(key.path().with_extension("pub")).unwrap();
+    let allowed_signers = context.temp_dir().child("allowed_signers");
+    allowed_signers
+        .write_str(&format!("signer@example.com {}", public_key))
+        .unwrap();
+
+    let git = |args: &[&str]| {
+        let output = StdCommand::new("git")
+            .args(args)
+            .current_dir(context.working_dir())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+    };
+    git(&["config", "gpg.format", "ssh"]);
+    git(&["config", "user.signingkey", key.path().to_str().unwrap()]);
+    git(&[
+        "config",
+        "gpg.ssh.allowedSignersFile",
+        allowed_signers.path().to_str().unwrap(),
+    ]);
+    git(&[
+        "commit",
+        "--allow-empty",
+        "--gpg-sign",
+        "--message",
+        "Signed commit",
+    ]);
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("verify")
+        .current_dir(context.working_dir())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#""signature":"valid","signer":"signer@example.com","#,
+        ));
+}
@@ -0,0 +1,37 @@
+mod setup;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn run_whereis(revision: &str) -> assert_cmd::assert::Assert {
+    let context = setup::run(&fs_err::read_to_string("tests/setup/whereis.setup").unwrap());
+
+    Command::cargo_bin("mgit")
+        .unwrap()
+        .arg("--json")
+        .arg("whereis")
+        .arg(revision)
+        .current_dir(context.working_dir())
+        .assert()
+}
+
+#[test]
+fn whereis_branch() {
+    run_whereis("topic").success().stdout(
+        predicate::str::is_match(r#"^\{"kind":"found","path":"a","oid":"[0-9a-f]{40}"\}\n$"#)
+            .unwrap(),
+    );
+}
+
+#[test]
+fn whereis_tag() {
+    run_whereis("v1").success().stdout(
+        predicate::str::is_match(r#"^\{"kind":"found","path":"b","oid":"[0-9a-f]{40}"\}\n$"#)
+            .unwrap(),
+    );
+}
+
+#[test]
+fn whereis_not_found() {
+    run_whereis("missing").success().stdout("");
+}